@@ -1,3 +1,8 @@
+import getpass
+import json
+import os
+import socket
+from datetime import datetime, timezone
 from functools import cache
 from pathlib import Path
 from typing import Optional
@@ -10,7 +15,7 @@ from requests.adapters import HTTPAdapter
 from godata import server
 from godata.errors import GodataError
 
-from .parser import RequestType, parse_response
+from .parser import RequestType, error_code, parse_response
 from .unixsocket import UnixHTTPAdapter
 
 """
@@ -56,6 +61,11 @@ def get_client():
 
     CLIENT = requests.Session()
     CLIENT.mount(SERVER_URL, ADAPTER)
+    # Recorded with every change in the project's audit log
+    CLIENT.headers["X-Godata-Client"] = client_name()
+    token = os.environ.get("GODATA_TOKEN", server_config.token)
+    if token:
+        CLIENT.headers["Authorization"] = f"Bearer {token}"
 
     try:
         check_server(CLIENT, SERVER_URL)
@@ -65,6 +75,14 @@ def get_client():
         return get_client()
 
 
+def client_name():
+    try:
+        user = getpass.getuser()
+    except Exception:
+        user = "unknown"
+    return f"{user}@{socket.gethostname()}"
+
+
 def get_version(client, url):
     resp = client.get(f"{url}/version")
     return resp.json()
@@ -91,10 +109,10 @@ def create_project(
     storage_location: str = None,
 ):
     client, url = get_client()
-    args = {"force": str(force).lower()}
+    body = {"force": force}
     if storage_location:
-        args["storage_location"] = storage_location
-    result = client.post(f"{url}/create/{collection_name}/{project_name}", params=args)
+        body["storage_location"] = str(storage_location)
+    result = client.post(f"{url}/create/{collection_name}/{project_name}", json=body)
     return parse_response(result, RequestType.PROJECT)
 
 
@@ -150,50 +168,74 @@ def link_file(
     file_path: str,
     metadata: dict = {},
     force: bool = False,
+    if_match: Optional[str] = None,
+    lease: Optional[str] = None,
+    provenance: Optional[dict] = None,
 ):
+    """
+    `provenance` records what the file was made from, as a dict with the project paths
+    of its "inputs" and optionally the "tool", its "version" and its "parameters".
+    """
     client, url = get_client()
-    params = {
+    body = {
         "project_path": project_path,
-        "real_path": file_path,
-        "force": str(force).lower(),
+        "real_path": str(file_path),
+        "force": force,
+        "metadata": {str(k): metadata_value(v) for k, v in metadata.items()},
     }
-    if set(metadata.keys()).intersection(set(params.keys())):
-        raise GodataError(
-            f"Metadata keys {set(metadata.keys())} conflict with parameter keys "
-            f"{set(params.keys())}."
-        )
-    for k, v in metadata.items():
-        try:
-            params.update({str(k): str(v)})
-        except TypeError:
-            raise GodataError(
-                "Metadata keys and values must be convertible strings."
-            ) from None
-
+    if provenance is not None:
+        body["provenance"] = metadata_value(provenance)
     resp = client.post(
-        f"{url}/projects/{collection_name}/{project_name}/files", params=params
+        f"{url}/projects/{collection_name}/{project_name}/files",
+        json=body,
+        headers=write_headers(if_match, lease),
     )
     result = parse_response(resp, RequestType.FILE)
     return result
     print(result)
 
 
+def metadata_value(value):
+    """
+    Metadata is sent as JSON, anything JSON can't represent is sent as a string.
+    Datetimes are sent as RFC 3339 strings and are returned as strings.
+    Naive datetimes are assumed to be in UTC.
+    """
+    match value:
+        case bool() | int() | float() | str():
+            return value
+        case datetime():
+            if value.tzinfo is None:
+                value = value.replace(tzinfo=timezone.utc)
+            return value.isoformat()
+        case list() | tuple():
+            return [metadata_value(v) for v in value]
+        case dict():
+            return {str(k): metadata_value(v) for k, v in value.items()}
+        case _:
+            return str(value)
+
+
 def link_folder(
     collection_name: str,
     project_name: str,
     project_path: str,
     folder_path: str,
     recursive: bool = False,
+    if_match: Optional[str] = None,
+    lease: Optional[str] = None,
 ):
     client, url = get_client()
-    params = {
+    body = {
         "project_path": project_path,
-        "real_path": folder_path,
+        "real_path": str(folder_path),
         "type": "folder",
-        "recursive": str(recursive).lower(),
+        "recursive": recursive,
     }
     resp = client.post(
-        f"{url}/projects/{collection_name}/{project_name}/files", params=params
+        f"{url}/projects/{collection_name}/{project_name}/files",
+        json=body,
+        headers=write_headers(if_match, lease),
     )
     return parse_response(resp, RequestType.FILE)
 
@@ -204,15 +246,22 @@ def move(
     source_path: str,
     destination_path: str,
     overwrite: bool = False,
+    if_match: Optional[str] = None,
 ):
+    """
+    `if_match` is checked against the source, which is the entry being moved. With
+    `overwrite`, whatever is at the destination is replaced regardless of its revision.
+    """
     client, url = get_client()
-    params = {
+    body = {
         "source_path": source_path,
         "destination_path": destination_path,
-        "overwrite": str(overwrite).lower(),
+        "overwrite": overwrite,
     }
     resp = client.post(
-        f"{url}/projects/{collection_name}/{project_name}/files/move", params=params
+        f"{url}/projects/{collection_name}/{project_name}/files/move",
+        json=body,
+        headers=write_headers(if_match),
     )
     return parse_response(resp, RequestType.FILE)
 
@@ -252,25 +301,327 @@ def get_file(
     return parse_response(resp, RequestType.FILE)
 
 
-def generate_path(collection_name: str, project_name: str, project_path: str):
+def get_etag(
+    collection_name: str, project_name: str, project_path: Optional[str] = None
+):
+    """
+    The ETag of a file or folder. Passing it as `if_match` to a write makes the write
+    fail with RevisionMismatch if someone else has changed the entry since.
+    """
+    client, url = get_client()
+    params = {"project_path": project_path} if project_path else {}
+    resp = client.get(
+        f"{url}/projects/{collection_name}/{project_name}/list", params=params
+    )
+    if not resp.ok and error_code(resp) == "path_is_file":
+        resp = client.get(
+            f"{url}/projects/{collection_name}/{project_name}/files", params=params
+        )
+    parse_response(resp, RequestType.FILE)
+    return resp.headers["ETag"]
+
+
+def write_headers(if_match: Optional[str] = None, lease: Optional[str] = None):
+    headers = {}
+    if if_match:
+        headers["If-Match"] = if_match
+    if lease:
+        headers["X-Lease-Id"] = lease
+    return headers
+
+
+def generate_path(
+    collection_name: str,
+    project_name: str,
+    project_path: str,
+    lease: Optional[str] = None,
+):
     client, url = get_client()
     params = {"project_path": project_path}
     resp = client.get(
         f"{url}/projects/{collection_name}/{project_name}/generate",
         params=params,
+        headers=write_headers(lease=lease),
     )
     return parse_response(resp, RequestType.FILE)
 
 
-def remove_file(collection_name: str, project_name: str, project_path: str):
+def remove_file(
+    collection_name: str,
+    project_name: str,
+    project_path: str,
+    if_match: Optional[str] = None,
+):
     client, url = get_client()
     params = {"project_path": project_path}
     resp = client.delete(
-        f"{url}/projects/{collection_name}/{project_name}/files", params=params
+        f"{url}/projects/{collection_name}/{project_name}/files",
+        params=params,
+        headers=write_headers(if_match),
+    )
+    return parse_response(resp, RequestType.FILE)
+
+
+def apply_batch(
+    collection_name: str,
+    project_name: str,
+    operations: list[dict],
+    atomic: bool = False,
+    lease: Optional[str] = None,
+):
+    """
+    Apply several link, move, remove and metadata operations in one request. Each
+    operation is a dict with an "op" key and the same arguments as the single
+    operation, e.g. {"op": "move", "source_path": "a", "destination_path": "b"}.
+    An operation with a "revision" (the number in an ETag) is only applied if its
+    target is still at that revision. The target of a move is its source.
+    """
+    client, url = get_client()
+    body = {"operations": [batch_operation(op) for op in operations], "atomic": atomic}
+    resp = client.post(
+        f"{url}/projects/{collection_name}/{project_name}/batch",
+        json=body,
+        headers=write_headers(lease=lease),
+    )
+    return parse_response(resp, RequestType.FILE)
+
+
+def batch_operation(operation: dict):
+    operation = dict(operation)
+    if "real_path" in operation:
+        operation["real_path"] = str(operation["real_path"])
+    if "metadata" in operation:
+        operation["metadata"] = {
+            str(k): metadata_value(v) for k, v in operation["metadata"].items()
+        }
+    return operation
+
+
+def acquire_lease(
+    collection_name: str,
+    project_name: str,
+    project_path: str,
+    holder: str,
+    ttl: Optional[int] = None,
+):
+    """
+    Claim a path, and everything below it, for `ttl` seconds. Other clients can't link
+    or generate paths there until the lease is released or expires. Pass the "id" of
+    the returned lease as `lease` to act for it.
+    """
+    client, url = get_client()
+    body = {"project_path": project_path, "holder": holder}
+    if ttl is not None:
+        body["ttl"] = ttl
+    resp = client.post(
+        f"{url}/projects/{collection_name}/{project_name}/leases", json=body
     )
     return parse_response(resp, RequestType.FILE)
 
 
+def renew_lease(
+    collection_name: str, project_name: str, lease: str, ttl: Optional[int] = None
+):
+    client, url = get_client()
+    body = {"id": lease}
+    if ttl is not None:
+        body["ttl"] = ttl
+    resp = client.post(
+        f"{url}/projects/{collection_name}/{project_name}/leases/renew", json=body
+    )
+    return parse_response(resp, RequestType.FILE)
+
+
+def release_lease(collection_name: str, project_name: str, lease: str):
+    client, url = get_client()
+    resp = client.delete(
+        f"{url}/projects/{collection_name}/{project_name}/leases",
+        params={"id": lease},
+    )
+    return parse_response(resp, RequestType.FILE)
+
+
+def list_leases(collection_name: str, project_name: str):
+    client, url = get_client()
+    resp = client.get(f"{url}/projects/{collection_name}/{project_name}/leases")
+    return parse_response(resp, RequestType.FILE)
+
+
+def add_hook(
+    collection_name: str,
+    project_name: str,
+    pattern: str,
+    url: Optional[str] = None,
+    command: Optional[list[str]] = None,
+    operations: Optional[list[str]] = None,
+):
+    """
+    Run a hook when a path matching `pattern` changes. The hook either POSTs the change
+    to `url` or runs `command` with the change on its standard input. By default it runs
+    when files are linked or removed. Hooks run in the background and failed deliveries
+    are retried, see `list_hook_deliveries`.
+    """
+    client, url_ = get_client()
+    body = {"pattern": pattern}
+    if url is not None:
+        body["url"] = url
+    if command is not None:
+        body["command"] = command
+    if operations is not None:
+        body["operations"] = operations
+    resp = client.post(
+        f"{url_}/projects/{collection_name}/{project_name}/hooks", json=body
+    )
+    return parse_response(resp, RequestType.FILE)
+
+
+def list_hooks(collection_name: str, project_name: str):
+    client, url = get_client()
+    resp = client.get(f"{url}/projects/{collection_name}/{project_name}/hooks")
+    return parse_response(resp, RequestType.FILE)
+
+
+def remove_hook(collection_name: str, project_name: str, hook: str):
+    client, url = get_client()
+    resp = client.delete(
+        f"{url}/projects/{collection_name}/{project_name}/hooks",
+        params={"id": hook},
+    )
+    return parse_response(resp, RequestType.FILE)
+
+
+def list_hook_deliveries(
+    collection_name: str, project_name: str, hook: Optional[str] = None
+):
+    client, url = get_client()
+    params = {"hook": hook} if hook is not None else None
+    resp = client.get(
+        f"{url}/projects/{collection_name}/{project_name}/hooks/deliveries",
+        params=params,
+    )
+    return parse_response(resp, RequestType.FILE)
+
+
+def query_audit(
+    collection_name: str,
+    project_name: str,
+    project_path: Optional[str] = None,
+    action: Optional[str] = None,
+    client: Optional[str] = None,
+    since: Optional[datetime] = None,
+    until: Optional[datetime] = None,
+    before: Optional[int] = None,
+    limit: Optional[int] = None,
+):
+    """
+    Get the changes made to a project from its audit log, newest first. Only changes to
+    `project_path` and anything below it are returned if given. If there are more
+    entries, "next" in the result can be passed as `before` to get them.
+    """
+    client_, url = get_client()
+    params = {
+        "project_path": project_path,
+        "action": action,
+        "client": client,
+        # Naive datetimes are taken to be in UTC, as for metadata
+        "since": metadata_value(since) if since is not None else None,
+        "until": metadata_value(until) if until is not None else None,
+        "before": before,
+        "limit": limit,
+    }
+    params = {key: value for key, value in params.items() if value is not None}
+    resp = client_.get(
+        f"{url}/projects/{collection_name}/{project_name}/audit", params=params
+    )
+    return parse_response(resp, RequestType.PROJECT)
+
+
+def get_lineage(
+    collection_name: str,
+    project_name: str,
+    project_path: Optional[str] = None,
+    direction: Optional[str] = None,
+    depth: Optional[int] = None,
+):
+    """
+    Get the lineage graph of a project, or the part of it around `project_path`.
+    `direction` is "upstream", "downstream" or "both" (the default), and `depth` limits
+    how many steps away from the path the graph goes.
+    """
+    client, url = get_client()
+    params = {"project_path": project_path, "direction": direction, "depth": depth}
+    params = {key: value for key, value in params.items() if value is not None}
+    resp = client.get(
+        f"{url}/projects/{collection_name}/{project_name}/lineage", params=params
+    )
+    return parse_response(resp, RequestType.PROJECT)
+
+
+def export_lineage_prov(
+    collection_name: str,
+    project_name: str,
+    project_path: Optional[str] = None,
+    direction: Optional[str] = None,
+    depth: Optional[int] = None,
+):
+    """
+    Get the same graph as `get_lineage` as a W3C PROV-JSON document.
+    """
+    client, url = get_client()
+    params = {"project_path": project_path, "direction": direction, "depth": depth}
+    params = {key: value for key, value in params.items() if value is not None}
+    resp = client.get(
+        f"{url}/projects/{collection_name}/{project_name}/lineage/prov", params=params
+    )
+    return parse_response(resp, RequestType.PROJECT)
+
+
+def stale_files(
+    collection_name: str,
+    project_name: str,
+    project_path: Optional[str] = None,
+    checksums: bool = False,
+):
+    """
+    Get the files in a project that are out of date with their inputs, optionally only
+    those below `project_path`. With `checksums`, the contents of every input are read
+    and compared to what they were when the file was linked.
+    """
+    client, url = get_client()
+    params = {"checksums": str(checksums).lower()}
+    if project_path is not None:
+        params["project_path"] = project_path
+    resp = client.get(
+        f"{url}/projects/{collection_name}/{project_name}/lineage/stale", params=params
+    )
+    return parse_response(resp, RequestType.PROJECT)
+
+
+def watch(collection_name: str, project_name: str, prefix: Optional[str] = None):
+    """
+    Yield the changes made to a project as they happen, optionally only those to paths
+    below `prefix`. If the client falls behind, a change with the "missed" key says how
+    many changes it didn't get. Blocks until the server closes the stream.
+    """
+    client, url = get_client()
+    params = {"prefix": prefix} if prefix is not None else None
+    with client.get(
+        f"{url}/projects/{collection_name}/{project_name}/events",
+        params=params,
+        stream=True,
+    ) as resp:
+        if not resp.ok:
+            parse_response(resp, RequestType.PROJECT)
+        data = []
+        for line in resp.iter_lines(decode_unicode=True):
+            # Events are separated by empty lines, keep-alive comments start with ":"
+            if line.startswith("data:"):
+                data.append(line[5:].lstrip())
+            elif not line and data:
+                yield json.loads("\n".join(data))
+                data = []
+
+
 def export_tree(collection_name: str, project_name: str, output_path: Path):
     client, url = get_client()
     params = {"output_path": str(output_path)}
@@ -280,6 +631,6 @@ def export_tree(collection_name: str, project_name: str, output_path: Path):
 
 def import_tree(collection_name: str, project_name: str, input_path: Path):
     client, url = get_client()
-    params = {"input_path": str(input_path)}
-    resp = client.get(f"{url}/import/{collection_name}/{project_name}", params=params)
+    body = {"input_path": str(input_path), "force": True}
+    resp = client.post(f"{url}/import/{collection_name}/{project_name}", json=body)
     return parse_response(resp, RequestType.PROJECT)
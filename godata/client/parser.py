@@ -16,6 +16,7 @@ from godata.errors import (
     GodataFileError,
     GodataProjectError,
     NotFound,
+    RevisionMismatch,
 )
 
 
@@ -38,7 +39,32 @@ def parse_response(
         case _:
             error = match_other_error(response.status_code)
     if not err_ok:
-        raise error(response.json())
+        exception = error(error_message(response))
+        # The stable error code lets callers tell failures apart without
+        # matching on the message
+        exception.code = error_code(response)
+        raise exception
+
+
+def error_message(response: Response) -> str:
+    """
+    Errors are returned as an object with the message and the ID of the request,
+    which can be used to find the request in the server logs.
+    """
+    body = response.json()
+    if not isinstance(body, dict):
+        return body
+    message = body.get("message", "")
+    if request_id := body.get("request_id"):
+        message += f" (request id: {request_id})"
+    return message
+
+
+def error_code(response: Response) -> str | None:
+    body = response.json()
+    if not isinstance(body, dict):
+        return None
+    return body.get("code")
 
 
 def match_file_error(status_code: int):
@@ -49,6 +75,8 @@ def match_file_error(status_code: int):
             return FileNotFoundError
         case 409:
             return FileExistsError
+        case 412:
+            return RevisionMismatch
         case _:
             return GodataProjectError
 
@@ -26,5 +26,13 @@ class NotFound(GodataProjectError):
     pass
 
 
+class RevisionMismatch(GodataProjectError):
+    """
+    A conditional write failed because the entry was changed by someone else.
+    """
+
+    pass
+
+
 class GodataFileError(Exception):
     pass
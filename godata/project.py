@@ -57,6 +57,7 @@ class GodataProject:
         recursive: bool = False,
         overwrite=False,
         verbose=True,
+        provenance: dict | None = None,
         _force=False,
     ) -> bool:
         """
@@ -85,6 +86,9 @@ class GodataProject:
                 be deleted from disk.
             verbose (bool, optional): If set to True, this will print a message to the
                 console indicating the result of the operation.
+            provenance (dict, optional): What the file was made from, with the project
+                paths of its "inputs" and optionally the "tool", its "version" and its
+                "parameters". Only files can have provenance.
         """
 
         fpath = Path(file_path)
@@ -93,6 +97,8 @@ class GodataProject:
         fpath = fpath.resolve()
 
         if fpath.is_dir():
+            if provenance is not None:
+                raise ValueError("Provenance can only be recorded for files")
             result = client.link_folder(
                 self.collection, self.name, project_path, str(fpath), recursive
             )
@@ -104,6 +110,7 @@ class GodataProject:
                 str(fpath),
                 metadata=metadata,
                 force=overwrite,
+                provenance=provenance,
             )
         if verbose:
             print(result["message"])
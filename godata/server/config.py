@@ -20,6 +20,8 @@ class ServerConfig(pydantic.BaseModel):
     server_url: Optional[str] = None
     server_path: Path = DEFAULT_SERVER_INSTALL_LOCATION / "godata_server"
     port: Optional[int] = pydantic.Field(ge=0, le=65535, default=None)
+    # API token, for servers that require authentication
+    token: Optional[str] = None
 
     def stop(self):
         self.is_running = False
@@ -0,0 +1,119 @@
+// Bundling of whole projects into a single tar archive, optionally compressed with zstd.
+// The layout of an archive mirrors a project storage directory: the exported tree is
+// stored in `.tree`, and each file is stored at its path relative to the storage root.
+// Files that lived outside of the project storage are copied into `.external`, so the
+// archive can be unpacked on another machine without breaking any links.
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use std::fs;
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+pub(crate) const EXTERNAL_DIR: &str = ".external";
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Compression {
+    None,
+    Zstd,
+}
+
+impl Compression {
+    pub(crate) fn parse(value: Option<&str>) -> Result<Compression> {
+        match value {
+            None | Some("none") => Ok(Compression::None),
+            Some("zstd") => Ok(Compression::Zstd),
+            Some(other) => Err(GodataError::new(
+                GodataErrorType::InvalidArgument,
+                format!("Unknown compression `{}`, expected `none` or `zstd`", other),
+            )
+            .with_code("invalid_compression")),
+        }
+    }
+}
+
+pub(crate) enum ArchiveWriter {
+    Plain(tar::Builder<fs::File>),
+    Zstd(tar::Builder<zstd::Encoder<'static, fs::File>>),
+}
+
+impl ArchiveWriter {
+    pub(crate) fn create(path: &Path, compression: Compression) -> Result<ArchiveWriter> {
+        if path.exists() {
+            return Err(GodataError::new(
+                GodataErrorType::AlreadyExists,
+                format!("Output file `{}` already exists", path.display()),
+            )
+            .with_code("output_exists"));
+        }
+        let file = fs::File::create(path)?;
+        let writer = match compression {
+            Compression::None => ArchiveWriter::Plain(tar::Builder::new(file)),
+            Compression::Zstd => {
+                ArchiveWriter::Zstd(tar::Builder::new(zstd::Encoder::new(file, 0)?))
+            }
+        };
+        Ok(writer)
+    }
+
+    pub(crate) fn append_file(&mut self, archive_path: &Path, real_path: &Path) -> Result<()> {
+        if !real_path.is_file() {
+            return Err(GodataError::new(
+                GodataErrorType::NotFound,
+                format!("File `{}` does not exist", real_path.display()),
+            )
+            .with_code("file_not_found"));
+        }
+        match self {
+            ArchiveWriter::Plain(b) => b.append_path_with_name(real_path, archive_path)?,
+            ArchiveWriter::Zstd(b) => b.append_path_with_name(real_path, archive_path)?,
+        }
+        Ok(())
+    }
+
+    pub(crate) fn append_dir(&mut self, archive_path: &Path, real_path: &Path) -> Result<()> {
+        match self {
+            ArchiveWriter::Plain(b) => b.append_dir_all(archive_path, real_path)?,
+            ArchiveWriter::Zstd(b) => b.append_dir_all(archive_path, real_path)?,
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> Result<()> {
+        match self {
+            ArchiveWriter::Plain(b) => {
+                b.into_inner()?;
+            }
+            ArchiveWriter::Zstd(b) => {
+                b.into_inner()?.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn external_archive_path(real_path: &Path) -> PathBuf {
+    // External files are stored under their original absolute path, so the same
+    // file linked at several virtual paths is only stored once.
+    let relative: PathBuf = real_path
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    Path::new(EXTERNAL_DIR).join(relative)
+}
+
+pub(crate) fn unpack(archive_path: &Path, output_dir: &Path) -> Result<()> {
+    // Compression is detected from the file contents rather than the extension
+    let mut magic = [0u8; 4];
+    let is_zstd = {
+        let mut file = fs::File::open(archive_path)?;
+        file.read(&mut magic)? == magic.len() && magic == ZSTD_MAGIC
+    };
+    let file = BufReader::new(fs::File::open(archive_path)?);
+    if is_zstd {
+        tar::Archive::new(zstd::Decoder::new(file)?).unpack(output_dir)?;
+    } else {
+        tar::Archive::new(file).unpack(output_dir)?;
+    }
+    Ok(())
+}
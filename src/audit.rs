@@ -0,0 +1,267 @@
+// Audit trail. Every change to a project is recorded in its database with the time, the
+// client that made it (see `request`) and the state of the path before and after, so
+// questions like "who removed this file last spring" can be answered long after the
+// server logs are gone. Entries are numbered in order and never changed or removed. They
+// are only dropped with the project itself, and are not part of snapshots or exports.
+//
+// Entries for changes to the tree are held back and written in the same database
+// transaction as the changes themselves, so a change is never saved without its entry
+// and a batch that is rolled back leaves nothing behind.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sled::{Batch, Tree};
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use crate::fsystem::{decode, encode, is_within};
+use crate::request::{self, Client};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AuditAction {
+    Link,
+    Move,
+    Remove,
+    Metadata,
+    CreateSnapshot,
+    RestoreSnapshot,
+    DeleteSnapshot,
+    AcquireLease,
+    RenewLease,
+    ReleaseLease,
+    AddHook,
+    RemoveHook,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct AuditEntry {
+    sequence: u64,
+    timestamp: DateTime<Utc>,
+    action: AuditAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    project_path: Option<String>,
+    // Where a moved file or folder came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_path: Option<String>,
+    client: Client,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    before: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after: Option<Value>,
+}
+
+// Which entries to return, newest first. `before` is the sequence number to continue
+// from, as returned in `next`.
+#[derive(Debug, Default)]
+pub(crate) struct AuditQuery {
+    pub(crate) project_path: Option<String>,
+    pub(crate) action: Option<AuditAction>,
+    pub(crate) client: Option<String>,
+    pub(crate) since: Option<DateTime<Utc>>,
+    pub(crate) until: Option<DateTime<Utc>>,
+    pub(crate) before: Option<u64>,
+    pub(crate) limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct AuditPage {
+    entries: Vec<AuditEntry>,
+    // Pass as `before` to get the next page, missing on the last one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<u64>,
+}
+
+pub(crate) struct AuditLog {
+    tree: Tree,
+    next: u64,
+    // Entries from the batch being applied
+    held: Option<Vec<AuditEntry>>,
+}
+
+impl AuditEntry {
+    // The entry is made for the client of the current request
+    pub(crate) fn new(action: AuditAction, project_path: Option<&str>) -> AuditEntry {
+        AuditEntry {
+            sequence: 0,
+            timestamp: Utc::now(),
+            action,
+            project_path: project_path.map(|path| path.to_string()),
+            source_path: None,
+            client: request::current_client(),
+            request_id: request::current_id(),
+            before: None,
+            after: None,
+        }
+    }
+
+    pub(crate) fn with_source(mut self, source_path: &str) -> Self {
+        self.source_path = Some(source_path.to_string());
+        self
+    }
+
+    pub(crate) fn with_before(mut self, before: Option<Value>) -> Self {
+        self.before = before;
+        self
+    }
+
+    pub(crate) fn with_after(mut self, after: Option<Value>) -> Self {
+        self.after = after;
+        self
+    }
+
+    fn matches(&self, query: &AuditQuery) -> bool {
+        if query.action.is_some_and(|action| action != self.action)
+            || query.since.is_some_and(|since| self.timestamp < since)
+            || query.until.is_some_and(|until| self.timestamp > until)
+        {
+            return false;
+        }
+        if let Some(client) = &query.client {
+            let client = client.as_str();
+            let matched = self.client.token.as_deref() == Some(client)
+                || self.client.name.as_deref() == Some(client)
+                || self.client.address.as_deref() == Some(client)
+                || self.client.uid.is_some_and(|uid| uid.to_string() == client);
+            if !matched {
+                return false;
+            }
+        }
+        match &query.project_path {
+            None => true,
+            Some(path) => {
+                // Changes to the path itself or anything below it
+                let path = path.trim_matches('/');
+                [&self.project_path, &self.source_path]
+                    .into_iter()
+                    .flatten()
+                    .any(|entry_path| is_within(entry_path, path))
+            }
+        }
+    }
+}
+
+impl AuditLog {
+    pub(crate) fn load(tree: Tree) -> Result<AuditLog> {
+        let next = match tree.last()? {
+            Some((key, _)) => sequence(&key)? + 1,
+            None => 1,
+        };
+        Ok(AuditLog {
+            tree,
+            next,
+            held: None,
+        })
+    }
+
+    pub(crate) fn record(&mut self, entry: AuditEntry) -> Result<()> {
+        match self.held.as_mut() {
+            Some(held) => {
+                held.push(entry);
+                Ok(())
+            }
+            None => self.write(entry),
+        }
+    }
+
+    pub(crate) fn hold(&mut self) {
+        self.held = Some(Vec::new());
+    }
+
+    pub(crate) fn is_held(&self) -> bool {
+        self.held.is_some()
+    }
+
+    // The held entries, ready to be written with the tree changes they describe.
+    // `revision` gives the revision each path will have once those changes are saved.
+    pub(crate) fn stage(&self, revision: impl Fn(&str) -> Option<u64>) -> Result<Batch> {
+        let mut batch = Batch::default();
+        for (sequence, entry) in (self.next..).zip(self.held.iter().flatten()) {
+            let mut entry = entry.clone();
+            entry.sequence = sequence;
+            if let (Some(Value::Object(after)), Some(path)) =
+                (&mut entry.after, &entry.project_path)
+            {
+                if after.contains_key("revision") {
+                    after.insert("revision".to_string(), revision(path).into());
+                }
+            }
+            batch.insert(&sequence.to_be_bytes(), encode(&entry)?);
+        }
+        Ok(batch)
+    }
+
+    // The staged entries were written
+    pub(crate) fn committed(&mut self) {
+        let count = self.held.take().map_or(0, |held| held.len());
+        self.next += count as u64;
+    }
+
+    pub(crate) fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    pub(crate) fn discard(&mut self) {
+        self.held = None;
+    }
+
+    pub(crate) fn query(&self, query: &AuditQuery) -> Result<AuditPage> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        if limit == 0 || limit > MAX_LIMIT {
+            return Err(GodataError::invalid_argument(
+                "limit",
+                &limit.to_string(),
+                format!("Limit must be between 1 and {}", MAX_LIMIT),
+            ));
+        }
+        let entries = match query.before {
+            Some(before) => self.tree.range(..before.to_be_bytes()),
+            None => self.tree.range::<&[u8], _>(..),
+        };
+        let mut page = Vec::new();
+        for item in entries.rev() {
+            let (_, value) = item?;
+            let entry: AuditEntry = decode(&value)?;
+            if !entry.matches(query) {
+                continue;
+            }
+            if page.len() == limit {
+                // There's at least one more entry, so the page is continued from here
+                let next = page.last().map(|entry: &AuditEntry| entry.sequence);
+                return Ok(AuditPage {
+                    entries: page,
+                    next,
+                });
+            }
+            page.push(entry);
+        }
+        Ok(AuditPage {
+            entries: page,
+            next: None,
+        })
+    }
+
+    fn write(&mut self, mut entry: AuditEntry) -> Result<()> {
+        entry.sequence = self.next;
+        self.tree
+            .insert(entry.sequence.to_be_bytes(), encode(&entry)?)?;
+        self.next += 1;
+        Ok(())
+    }
+}
+
+fn sequence(key: &[u8]) -> Result<u64> {
+    let bytes: [u8; 8] = key.try_into().map_err(|_| {
+        GodataError::new(
+            GodataErrorType::InternalError,
+            "Found a corrupted entry in the audit log".to_string(),
+        )
+        .with_code("audit_corrupted")
+    })?;
+    Ok(u64::from_be_bytes(bytes))
+}
@@ -0,0 +1,544 @@
+// Token based authentication and authorization. Tokens are random strings handed out
+// once at creation; only their SHA-256 hash is stored. Each token carries a list of
+// grants, where a grant gives a permission level on a scope:
+//
+//   - `*` covers every collection and project (an admin grant on `*` can manage tokens)
+//   - `<collection>` covers a collection and every project inside it
+//   - `<collection>/<project>` covers a single project
+//
+// Levels are ordered, so a write grant also allows reading and an admin grant also
+// allows writing. Authorization is enforced by a filter in front of all routes, which
+// works out from the request path and method which scope and level are required. A
+// route that isn't listed there is refused, so a new route is never open by accident.
+// Routes that read or write arbitrary paths on the server (exports, imports and diffs
+// against a path) need an admin grant on `*`, as they reach outside any one project.
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use crate::locations::get_default_storage_dir;
+use crate::request;
+use chrono::Utc;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sled::{Db, Tree};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::instrument;
+use warp::http::Method;
+use warp::path::FullPath;
+use warp::{Filter, Rejection};
+
+const TOKEN_PREFIX: &str = "gd_";
+const GLOBAL_SCOPE: &str = "*";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Level {
+    Read,
+    Write,
+    Admin,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Grant {
+    pub(crate) scope: String,
+    pub(crate) level: Level,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct TokenInfo {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) created: String,
+    pub(crate) grants: Vec<Grant>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct NewToken {
+    #[serde(flatten)]
+    pub(crate) info: TokenInfo,
+    // Only ever returned when the token is created
+    pub(crate) token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenRecord {
+    hash: String,
+    info: TokenInfo,
+}
+
+// What a request needs in order to be allowed through
+#[derive(Debug, PartialEq)]
+enum Requirement {
+    Public,
+    Authenticated,
+    Denied,
+    Access {
+        collection: Option<String>,
+        project: Option<String>,
+        level: Level,
+    },
+}
+
+impl Grant {
+    fn validate(&self) -> Result<()> {
+        let parts: Vec<&str> = self.scope.split('/').collect();
+        let valid = match parts.as_slice() {
+            [GLOBAL_SCOPE] => true,
+            [collection] => !collection.is_empty(),
+            [collection, project] => {
+                !collection.is_empty()
+                    && !project.is_empty()
+                    && *collection != GLOBAL_SCOPE
+                    && *project != GLOBAL_SCOPE
+            }
+            _ => false,
+        };
+        if !valid {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidArgument,
+                format!(
+                    "Invalid scope `{}`, expected `*`, `<collection>` or `<collection>/<project>`",
+                    self.scope
+                ),
+            )
+            .with_code("invalid_scope")
+            .with_details(serde_json::json!({"scope": self.scope})));
+        }
+        Ok(())
+    }
+
+    fn covers(&self, collection: Option<&str>, project: Option<&str>, level: Level) -> bool {
+        if self.level < level {
+            return false;
+        }
+        if self.scope == GLOBAL_SCOPE {
+            return true;
+        }
+        match (self.scope.split_once('/'), collection, project) {
+            // Collection grants cover every project in the collection
+            (None, Some(c), _) => self.scope == c,
+            (Some((gc, gp)), Some(c), Some(p)) => gc == c && gp == p,
+            _ => false,
+        }
+    }
+}
+
+impl TokenInfo {
+    fn allows(&self, requirement: &Requirement) -> bool {
+        match requirement {
+            Requirement::Public | Requirement::Authenticated => true,
+            Requirement::Denied => false,
+            Requirement::Access {
+                collection,
+                project,
+                level,
+            } => self
+                .grants
+                .iter()
+                .any(|g| g.covers(collection.as_deref(), project.as_deref(), *level)),
+        }
+    }
+}
+
+pub(crate) struct AuthManager {
+    enabled: bool,
+    _db: Db,
+    tokens: Tree,
+    hashes: Tree,
+}
+
+impl AuthManager {
+    pub(crate) fn get_manager(enabled: bool) -> Result<AuthManager> {
+        let db_location = get_default_storage_dir()?.join(".auth");
+        let db = sled::open(db_location)?;
+        Ok(AuthManager {
+            enabled,
+            tokens: db.open_tree("tokens")?,
+            hashes: db.open_tree("token_hashes")?,
+            _db: db,
+        })
+    }
+
+    #[instrument(skip(self, grants))]
+    pub(crate) fn create_token(&self, name: &str, grants: Vec<Grant>) -> Result<NewToken> {
+        for grant in grants.iter() {
+            grant.validate()?;
+        }
+        let token = format!(
+            "{}{}{}",
+            TOKEN_PREFIX,
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        );
+        let info = TokenInfo {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            created: Utc::now().to_rfc3339(),
+            grants,
+        };
+        let record = TokenRecord {
+            hash: hash_token(&token),
+            info: info.clone(),
+        };
+        self.tokens.insert(&info.id, encode(&record)?)?;
+        self.hashes.insert(&record.hash, info.id.as_bytes())?;
+        self.tokens.flush()?;
+        tracing::info!("Created token {} ({})", info.id, info.name);
+        Ok(NewToken { info, token })
+    }
+
+    pub(crate) fn list_tokens(&self) -> Result<Vec<TokenInfo>> {
+        let mut tokens = Vec::new();
+        for item in self.tokens.iter() {
+            let (_, value) = item?;
+            tokens.push(decode(&value)?.info);
+        }
+        tokens.sort_by(|a, b| a.created.cmp(&b.created));
+        Ok(tokens)
+    }
+
+    #[instrument(skip(self, grants))]
+    pub(crate) fn set_grants(&self, id: &str, grants: Vec<Grant>) -> Result<TokenInfo> {
+        for grant in grants.iter() {
+            grant.validate()?;
+        }
+        let mut record = self.get_record(id)?;
+        record.info.grants = grants;
+        self.tokens.insert(id, encode(&record)?)?;
+        self.tokens.flush()?;
+        Ok(record.info)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn delete_token(&self, id: &str) -> Result<()> {
+        let record = self.get_record(id)?;
+        self.hashes.remove(&record.hash)?;
+        self.tokens.remove(id)?;
+        self.tokens.flush()?;
+        tracing::info!("Deleted token {} ({})", id, record.info.name);
+        Ok(())
+    }
+
+    fn get_record(&self, id: &str) -> Result<TokenRecord> {
+        match self.tokens.get(id)? {
+            Some(value) => decode(&value),
+            None => Err(GodataError::new(
+                GodataErrorType::NotFound,
+                format!("Token `{}` does not exist", id),
+            )
+            .with_code("token_not_found")
+            .with_details(serde_json::json!({"id": id}))),
+        }
+    }
+
+    fn lookup(&self, token: &str) -> Result<Option<TokenInfo>> {
+        let id = match self.hashes.get(hash_token(token))? {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let id = String::from_utf8_lossy(&id).to_string();
+        Ok(Some(self.get_record(&id)?.info))
+    }
+
+    fn check(
+        &self,
+        method: &Method,
+        path: &str,
+        authorization: Option<&str>,
+        params: &HashMap<String, String>,
+    ) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let requirements = requirements(method, path, params);
+        if requirements.iter().all(|r| *r == Requirement::Public) {
+            return Ok(());
+        }
+        let token = match authorization.and_then(|h| h.strip_prefix("Bearer ")) {
+            Some(token) => token.trim(),
+            None => {
+                return Err(GodataError::new(
+                    GodataErrorType::Unauthorized,
+                    "Missing bearer token".to_string(),
+                )
+                .with_code("missing_token"))
+            }
+        };
+        let info = match self.lookup(token)? {
+            Some(info) => info,
+            None => {
+                tracing::warn!("Rejected request with an unknown token");
+                return Err(GodataError::new(
+                    GodataErrorType::Unauthorized,
+                    "Invalid token".to_string(),
+                )
+                .with_code("invalid_token"));
+            }
+        };
+        for requirement in requirements.iter() {
+            if !info.allows(requirement) {
+                tracing::warn!(
+                    "Token {} ({}) is not allowed to {} {}",
+                    info.id,
+                    info.name,
+                    method,
+                    path
+                );
+                return Err(GodataError::new(
+                    GodataErrorType::NotPermitted,
+                    format!(
+                        "Token `{}` does not have permission for this request",
+                        info.name
+                    ),
+                )
+                .with_code("permission_denied")
+                .with_details(serde_json::json!({"token": info.name})));
+            }
+        }
+        request::set_token(&info.name);
+        Ok(())
+    }
+}
+
+fn requirements(method: &Method, path: &str, params: &HashMap<String, String>) -> Vec<Requirement> {
+    let raw: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let segments: Vec<Cow<str>> = raw
+        .iter()
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy())
+        .collect();
+    // Routes get their collection and project names from the raw segments, so a name that
+    // only means something once decoded would be checked against one project and used
+    // on another
+    if raw
+        .iter()
+        .zip(&segments)
+        .any(|(raw, decoded)| raw != decoded)
+    {
+        return vec![Requirement::Denied];
+    }
+    let segments: Vec<&str> = segments.iter().map(|segment| segment.as_ref()).collect();
+    let access = |collection: &str, project: Option<&str>, level: Level| Requirement::Access {
+        collection: Some(collection.to_string()),
+        project: project.map(|p| p.to_string()),
+        level,
+    };
+    let server = Requirement::Access {
+        collection: None,
+        project: None,
+        level: Level::Admin,
+    };
+    let mutating = !matches!(*method, Method::GET | Method::HEAD);
+    match segments.as_slice() {
+        ["version"] => vec![Requirement::Public],
+        ["collections"] => vec![Requirement::Authenticated],
+        ["admin", ..] => vec![server],
+        ["projects", c] => vec![access(c, None, Level::Read)],
+        // Deleting a project cannot be undone, so it needs more than write access
+        ["projects", c, p] if *method == Method::DELETE => vec![access(c, Some(p), Level::Admin)],
+        // Hooks run commands as the server's user, and listing them shows those commands
+        ["projects", c, p, "hooks"] | ["projects", c, p, "hooks", "deliveries"] => {
+            vec![access(c, Some(p), Level::Admin)]
+        }
+        ["projects", c, p, "files" | "list" | "exists" | "generate" | "batch"]
+        | ["projects", c, p, "files", "move"]
+        | ["projects", c, p, "snapshots" | "leases" | "events" | "audit" | "lineage"]
+        | ["projects", c, p, "snapshots", "restore"]
+        | ["projects", c, p, "leases", "renew"]
+        | ["projects", c, p, "lineage", "prov" | "stale"] => match mutating {
+            true => vec![access(c, Some(p), Level::Write)],
+            false => vec![access(c, Some(p), Level::Read)],
+        },
+        ["create", c, p] => vec![access(c, Some(p), Level::Write)],
+        ["load" | "drop", c, p] => vec![access(c, Some(p), Level::Read)],
+        ["import" | "export", _, _] => vec![server],
+        ["diff", c, p] => {
+            let mut required = vec![access(c, Some(p), Level::Read)];
+            if let Some(other) = params.get("other_project") {
+                let other_collection = params
+                    .get("other_collection")
+                    .map(|oc| oc.as_str())
+                    .unwrap_or(c);
+                required.push(access(other_collection, Some(other), Level::Read));
+            }
+            if params.contains_key("other_path") {
+                required.push(server);
+            }
+            required
+        }
+        _ => vec![Requirement::Denied],
+    }
+}
+
+pub(crate) fn authorize(
+    auth_manager: Arc<AuthManager>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |method: Method,
+                  path: FullPath,
+                  authorization: Option<String>,
+                  params: HashMap<String, String>| {
+                let auth_manager = auth_manager.clone();
+                async move {
+                    auth_manager
+                        .check(&method, path.as_str(), authorization.as_deref(), &params)
+                        .map_err(warp::reject::custom)
+                }
+            },
+        )
+        .untuple_one()
+}
+
+fn hash_token(token: &str) -> String {
+    format!("sha256:{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn encode(record: &TokenRecord) -> Result<Vec<u8>> {
+    serde_json::to_vec(record).map_err(|e| {
+        GodataError::new(
+            GodataErrorType::InternalError,
+            format!("Failed to encode token: {}", e),
+        )
+        .with_code("token_corrupted")
+    })
+}
+
+fn decode(bytes: &[u8]) -> Result<TokenRecord> {
+    serde_json::from_slice(bytes).map_err(|e| {
+        GodataError::new(
+            GodataErrorType::InternalError,
+            format!("Failed to decode token: {}", e),
+        )
+        .with_code("token_corrupted")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(level: Level) -> Requirement {
+        Requirement::Access {
+            collection: Some("c".to_string()),
+            project: Some("p".to_string()),
+            level,
+        }
+    }
+
+    fn server() -> Requirement {
+        Requirement::Access {
+            collection: None,
+            project: None,
+            level: Level::Admin,
+        }
+    }
+
+    fn required(method: Method, path: &str) -> Vec<Requirement> {
+        requirements(&method, path, &HashMap::new())
+    }
+
+    fn grant(scope: &str, level: Level) -> TokenInfo {
+        TokenInfo {
+            id: "id".to_string(),
+            name: "name".to_string(),
+            created: "now".to_string(),
+            grants: vec![Grant {
+                scope: scope.to_string(),
+                level,
+            }],
+        }
+    }
+
+    #[test]
+    fn reads_and_writes_need_project_access() {
+        assert_eq!(
+            required(Method::GET, "/projects/c/p/list"),
+            vec![project(Level::Read)]
+        );
+        assert_eq!(
+            required(Method::POST, "/projects/c/p/files"),
+            vec![project(Level::Write)]
+        );
+        assert_eq!(
+            required(Method::POST, "/projects/c/p/files/move"),
+            vec![project(Level::Write)]
+        );
+        assert_eq!(
+            required(Method::DELETE, "/projects/c/p"),
+            vec![project(Level::Admin)]
+        );
+    }
+
+    #[test]
+    fn hooks_need_project_admin() {
+        assert_eq!(
+            required(Method::GET, "/projects/c/p/hooks"),
+            vec![project(Level::Admin)]
+        );
+        assert_eq!(
+            required(Method::GET, "/projects/c/p/hooks/deliveries"),
+            vec![project(Level::Admin)]
+        );
+    }
+
+    #[test]
+    fn server_paths_need_server_admin() {
+        assert_eq!(required(Method::GET, "/export/c/p"), vec![server()]);
+        assert_eq!(required(Method::POST, "/import/c/p"), vec![server()]);
+        let params = HashMap::from([("other_path".to_string(), "/tmp".to_string())]);
+        assert_eq!(
+            requirements(&Method::GET, "/diff/c/p", &params),
+            vec![project(Level::Read), server()]
+        );
+    }
+
+    #[test]
+    fn diff_needs_both_projects() {
+        let params = HashMap::from([("other_project".to_string(), "q".to_string())]);
+        let other = Requirement::Access {
+            collection: Some("c".to_string()),
+            project: Some("q".to_string()),
+            level: Level::Read,
+        };
+        assert_eq!(
+            requirements(&Method::GET, "/diff/c/p", &params),
+            vec![project(Level::Read), other]
+        );
+    }
+
+    #[test]
+    fn unknown_and_encoded_paths_are_denied() {
+        assert_eq!(
+            required(Method::GET, "/projects/c/p/unknown"),
+            vec![Requirement::Denied]
+        );
+        assert_eq!(
+            required(Method::GET, "/projects/c/p%2Fq/list"),
+            vec![Requirement::Denied]
+        );
+        assert_eq!(required(Method::GET, "/version"), vec![Requirement::Public]);
+    }
+
+    #[test]
+    fn grants_cover_their_scope_and_level() {
+        let reader = grant("c/p", Level::Read);
+        assert!(reader.allows(&project(Level::Read)));
+        assert!(!reader.allows(&project(Level::Write)));
+        assert!(!reader.allows(&server()));
+        assert!(!reader.allows(&Requirement::Denied));
+
+        let collection = grant("c", Level::Write);
+        assert!(collection.allows(&project(Level::Write)));
+        assert!(!collection.allows(&project(Level::Admin)));
+        assert!(!grant("d", Level::Admin).allows(&project(Level::Read)));
+
+        let admin = grant("*", Level::Admin);
+        assert!(admin.allows(&server()));
+        assert!(!admin.allows(&Requirement::Denied));
+    }
+}
@@ -0,0 +1,92 @@
+// Batches of tree operations on a single project. All operations in a batch are applied
+// to the in-memory tree and written to the database together, so linking many files
+// costs one request and one write instead of one of each per file.
+//
+// By default a failed operation is reported and the rest of the batch still goes
+// through. An atomic batch stops at the first failure and leaves the project exactly as
+// it was.
+//
+// Any operation can carry the revision its target is expected to be at, the equivalent
+// of an `If-Match` header on the single operation. The target is the path the operation
+// changes, which for a move is the source.
+
+use crate::errors::GodataError;
+use crate::lineage::ProvenanceRequest;
+use crate::metadata::Metadata;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "lowercase", deny_unknown_fields)]
+pub(crate) enum BatchOperation {
+    Link {
+        project_path: String,
+        real_path: String,
+        #[serde(default)]
+        metadata: Metadata,
+        provenance: Option<ProvenanceRequest>,
+        #[serde(default)]
+        force: bool,
+        revision: Option<u64>,
+    },
+    Move {
+        source_path: String,
+        destination_path: String,
+        #[serde(default)]
+        overwrite: bool,
+        revision: Option<u64>,
+    },
+    Remove {
+        project_path: String,
+        revision: Option<u64>,
+    },
+    Metadata {
+        project_path: String,
+        metadata: Metadata,
+        // Replace the existing metadata instead of merging into it
+        #[serde(default)]
+        replace: bool,
+        revision: Option<u64>,
+    },
+}
+
+impl BatchOperation {
+    pub(crate) fn target_path(&self) -> &str {
+        match self {
+            BatchOperation::Link { project_path, .. }
+            | BatchOperation::Remove { project_path, .. }
+            | BatchOperation::Metadata { project_path, .. } => project_path,
+            BatchOperation::Move { source_path, .. } => source_path,
+        }
+    }
+
+    // The path, real path and provenance of a file to link
+    pub(crate) fn link(&self) -> Option<(&str, &Path, Option<&ProvenanceRequest>)> {
+        match self {
+            BatchOperation::Link {
+                project_path,
+                real_path,
+                provenance,
+                ..
+            } => Some((project_path, Path::new(real_path), provenance.as_ref())),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn revision(&self) -> Option<u64> {
+        match self {
+            BatchOperation::Link { revision, .. }
+            | BatchOperation::Move { revision, .. }
+            | BatchOperation::Remove { revision, .. }
+            | BatchOperation::Metadata { revision, .. } => *revision,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub(crate) enum OperationResult {
+    // Internal files that were replaced or removed, which the client should delete
+    Applied { removed: Vec<String> },
+    Failed { error: GodataError },
+}
@@ -0,0 +1,369 @@
+// Server configuration. Settings are read from a TOML file at startup, then overridden
+// by `GODATA_*` environment variables and finally by command line arguments. The
+// resolved configuration is stored globally so the rest of the server can consult it
+// without threading it through every call.
+//
+// Example config file:
+//
+//     [server]
+//     port = 8000
+//     bind = ["127.0.0.1", "10.0.0.5"]
+//     socket_path = "~/.godata.sock"
+//     socket_mode = 0o600
+//     allowed_uids = [1001, 1002]
+//
+//     [server.tls]
+//     cert = "/etc/godata/cert.pem"
+//     key = "/etc/godata/key.pem"
+//
+//     [auth]
+//     enabled = true
+//
+//     [paths]
+//     data_dir = "/data/godata/db"
+//     storage_dir = "/data/godata/storage"
+//
+//     [log]
+//     level = "debug"
+//     dir = "/var/log/godata"
+//     retention_days = 7
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use directories::{BaseDirs, ProjectDirs};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::level_filters::LevelFilter;
+
+const CONFIG_FILE_NAME: &str = "server.toml";
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    pub(crate) server: ServerConfig,
+    pub(crate) auth: AuthConfig,
+    pub(crate) paths: PathsConfig,
+    pub(crate) log: LogConfig,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ServerConfig {
+    // If a port is set the server listens on TCP, otherwise on a unix socket
+    pub(crate) port: Option<u16>,
+    // Addresses to listen on in TCP mode. Defaults to localhost only.
+    pub(crate) bind: Vec<IpAddr>,
+    pub(crate) socket_path: Option<PathBuf>,
+    // File permissions of the unix socket
+    pub(crate) socket_mode: u32,
+    // Users other than the one running the server that may connect to the unix socket
+    pub(crate) allowed_uids: Vec<u32>,
+    pub(crate) tls: TlsConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            port: None,
+            bind: Vec::new(),
+            socket_path: None,
+            socket_mode: 0o600,
+            allowed_uids: Vec::new(),
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct TlsConfig {
+    pub(crate) cert: Option<PathBuf>,
+    pub(crate) key: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct AuthConfig {
+    // Require a token on every request. Tokens are managed with the /admin endpoints
+    // or created with `--create-token`.
+    pub(crate) enabled: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct PathsConfig {
+    // Where the project databases live
+    pub(crate) data_dir: Option<PathBuf>,
+    // Where files stored in projects (and logs) are written by default
+    pub(crate) storage_dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LogConfig {
+    pub(crate) level: String,
+    pub(crate) dir: Option<PathBuf>,
+    // Log files older than this are deleted at startup. 0 keeps them forever.
+    pub(crate) retention_days: u32,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: "info".to_string(),
+            dir: None,
+            retention_days: 30,
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn load(path: Option<PathBuf>) -> Result<Config> {
+        // An explicitly requested config file must exist, the default one is optional
+        let path = match path.or_else(|| std::env::var_os("GODATA_CONFIG").map(PathBuf::from)) {
+            Some(path) => {
+                let path = expand_home(&path);
+                if !path.is_file() {
+                    return Err(GodataError::new(
+                        GodataErrorType::NotFound,
+                        format!("Config file `{}` does not exist", path.display()),
+                    )
+                    .with_code("config_not_found"));
+                }
+                Some(path)
+            }
+            None => default_config_path().filter(|p| p.is_file()),
+        };
+        let mut config = match path {
+            Some(path) => Config::read(&path)?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Config> {
+        let contents = std::fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| {
+            GodataError::new(
+                GodataErrorType::InvalidArgument,
+                format!("Failed to parse config file `{}`: {}", path.display(), e),
+            )
+            .with_code("invalid_config")
+        })
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        if let Some(port) = env_var("GODATA_PORT") {
+            self.server.port = Some(port.parse::<u16>().map_err(|_| {
+                GodataError::new(
+                    GodataErrorType::InvalidArgument,
+                    format!("Invalid value `{}` for GODATA_PORT", port),
+                )
+                .with_code("invalid_config")
+            })?);
+        }
+        if let Some(addresses) = env_var("GODATA_BIND") {
+            self.server.bind = addresses
+                .split(',')
+                .map(|a| parse_address(a.trim()))
+                .collect::<Result<Vec<_>>>()?;
+        }
+        if let Some(path) = env_var("GODATA_TLS_CERT") {
+            self.server.tls.cert = Some(PathBuf::from(path));
+        }
+        if let Some(path) = env_var("GODATA_TLS_KEY") {
+            self.server.tls.key = Some(PathBuf::from(path));
+        }
+        if let Some(enabled) = env_var("GODATA_AUTH") {
+            self.auth.enabled = enabled.parse::<bool>().map_err(|_| {
+                GodataError::new(
+                    GodataErrorType::InvalidArgument,
+                    format!("Invalid value `{}` for GODATA_AUTH", enabled),
+                )
+                .with_code("invalid_config")
+            })?;
+        }
+        if let Some(path) = env_var("GODATA_SOCKET_PATH") {
+            self.server.socket_path = Some(PathBuf::from(path));
+        }
+        if let Some(mode) = env_var("GODATA_SOCKET_MODE") {
+            self.server.socket_mode = parse_mode(&mode)?;
+        }
+        if let Some(uids) = env_var("GODATA_ALLOWED_UIDS") {
+            self.server.allowed_uids = uids
+                .split(',')
+                .map(|uid| {
+                    uid.trim().parse::<u32>().map_err(|_| {
+                        GodataError::new(
+                            GodataErrorType::InvalidArgument,
+                            format!("Invalid uid `{}` in GODATA_ALLOWED_UIDS", uid),
+                        )
+                        .with_code("invalid_config")
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+        }
+        if let Some(path) = env_var("GODATA_DATA_DIR") {
+            self.paths.data_dir = Some(PathBuf::from(path));
+        }
+        if let Some(path) = env_var("GODATA_STORAGE_DIR") {
+            self.paths.storage_dir = Some(PathBuf::from(path));
+        }
+        if let Some(level) = env_var("GODATA_LOG_LEVEL") {
+            self.log.level = level;
+        }
+        if let Some(path) = env_var("GODATA_LOG_DIR") {
+            self.log.dir = Some(PathBuf::from(path));
+        }
+        if let Some(days) = env_var("GODATA_LOG_RETENTION_DAYS") {
+            self.log.retention_days = days.parse::<u32>().map_err(|_| {
+                GodataError::new(
+                    GodataErrorType::InvalidArgument,
+                    format!("Invalid value `{}` for GODATA_LOG_RETENTION_DAYS", days),
+                )
+                .with_code("invalid_config")
+            })?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        self.log_level()?;
+        if self.server.socket_mode > 0o777 {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidArgument,
+                format!("Invalid socket mode {:o}", self.server.socket_mode),
+            )
+            .with_code("invalid_config"));
+        }
+        if self.tls_paths()?.is_some() && self.server.port.is_none() {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidArgument,
+                "TLS is only supported when the server listens on a TCP port".to_string(),
+            )
+            .with_code("invalid_config"));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn bind_addresses(&self) -> Vec<SocketAddr> {
+        let port = self.server.port.unwrap_or_default();
+        if self.server.bind.is_empty() {
+            return vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)];
+        }
+        self.server
+            .bind
+            .iter()
+            .map(|ip| SocketAddr::new(*ip, port))
+            .collect()
+    }
+
+    pub(crate) fn tls_paths(&self) -> Result<Option<(PathBuf, PathBuf)>> {
+        match (&self.server.tls.cert, &self.server.tls.key) {
+            (Some(cert), Some(key)) => Ok(Some((expand_home(cert), expand_home(key)))),
+            (None, None) => Ok(None),
+            _ => Err(GodataError::new(
+                GodataErrorType::InvalidArgument,
+                "Both a TLS certificate and a key are required to enable TLS".to_string(),
+            )
+            .with_code("invalid_config")),
+        }
+    }
+
+    pub(crate) fn log_level(&self) -> Result<LevelFilter> {
+        LevelFilter::from_str(&self.log.level).map_err(|_| {
+            GodataError::new(
+                GodataErrorType::InvalidArgument,
+                format!(
+                    "Invalid log level `{}`, expected one of off, error, warn, info, debug or trace",
+                    self.log.level
+                ),
+            ).with_code("invalid_config")
+        })
+    }
+
+    pub(crate) fn data_dir(&self) -> PathBuf {
+        match &self.paths.data_dir {
+            Some(path) => expand_home(path),
+            None => BaseDirs::new().unwrap().data_dir().join("godata"),
+        }
+    }
+
+    pub(crate) fn storage_dir(&self) -> PathBuf {
+        match &self.paths.storage_dir {
+            Some(path) => expand_home(path),
+            None => BaseDirs::new().unwrap().home_dir().join("godata"),
+        }
+    }
+
+    pub(crate) fn socket_path(&self) -> PathBuf {
+        match &self.server.socket_path {
+            Some(path) => expand_home(path),
+            None => BaseDirs::new().unwrap().home_dir().join(".godata.sock"),
+        }
+    }
+
+    pub(crate) fn log_dir(&self) -> PathBuf {
+        match &self.log.dir {
+            Some(path) => expand_home(path),
+            None => self.storage_dir().join("logs"),
+        }
+    }
+}
+
+pub(crate) fn init(config: Config) {
+    if CONFIG.set(config).is_err() {
+        tracing::warn!("Server configuration was already initialized");
+    }
+}
+
+pub(crate) fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "godata").map(|dirs| dirs.config_dir().join(CONFIG_FILE_NAME))
+}
+
+pub(crate) fn parse_address(address: &str) -> Result<IpAddr> {
+    address.parse::<IpAddr>().map_err(|_| {
+        GodataError::new(
+            GodataErrorType::InvalidArgument,
+            format!("Invalid bind address `{}`", address),
+        )
+        .with_code("invalid_config")
+    })
+}
+
+pub(crate) fn parse_mode(mode: &str) -> Result<u32> {
+    // Modes are always octal, with or without a leading `0o` or `0`
+    let digits = mode.trim_start_matches("0o");
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|m| *m <= 0o777)
+        .ok_or_else(|| {
+            GodataError::new(
+                GodataErrorType::InvalidArgument,
+                format!(
+                    "Invalid socket mode `{}`, expected an octal mode such as 600",
+                    mode
+                ),
+            )
+            .with_code("invalid_config")
+        })
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => BaseDirs::new().unwrap().home_dir().join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
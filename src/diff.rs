@@ -0,0 +1,134 @@
+// Comparison of two project trees. Both sides are flattened into a map from virtual
+// path to the (resolved) real path and metadata of each file, so projects, exported
+// trees and manifests can all be compared against one another.
+
+use crate::metadata::{Metadata, MetadataValue};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+pub(crate) struct DiffEntry {
+    pub(crate) real_path: PathBuf,
+    pub(crate) metadata: Metadata,
+}
+
+pub(crate) type DiffEntries = BTreeMap<String, DiffEntry>;
+
+pub(crate) enum DiffSource {
+    Project { name: String, collection: String },
+    Snapshot(String),
+    Tree(PathBuf),
+    Manifest(PathBuf),
+}
+
+#[derive(Serialize, Default)]
+pub(crate) struct ProjectDiff {
+    pub(crate) added: Vec<FileSummary>,
+    pub(crate) removed: Vec<FileSummary>,
+    pub(crate) changed: Vec<FileChange>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct FileSummary {
+    pub(crate) path: String,
+    pub(crate) real_path: String,
+    pub(crate) metadata: Metadata,
+}
+
+#[derive(Serialize)]
+pub(crate) struct FileChange {
+    pub(crate) path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) target: Option<ValueChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) metadata: Option<MetadataChange>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ValueChange<T = String> {
+    pub(crate) before: T,
+    pub(crate) after: T,
+}
+
+#[derive(Serialize, Default)]
+pub(crate) struct MetadataChange {
+    pub(crate) added: Metadata,
+    pub(crate) removed: Metadata,
+    pub(crate) changed: HashMap<String, ValueChange<MetadataValue>>,
+}
+
+impl FileSummary {
+    fn new(path: &str, entry: &DiffEntry) -> FileSummary {
+        FileSummary {
+            path: path.to_string(),
+            real_path: entry.real_path.to_string_lossy().to_string(),
+            metadata: entry.metadata.clone(),
+        }
+    }
+}
+
+pub(crate) fn diff_metadata(before: &Metadata, after: &Metadata) -> Option<MetadataChange> {
+    let mut change = MetadataChange::default();
+    for (key, value) in before.iter() {
+        match after.get(key) {
+            None => {
+                change.removed.insert(key.clone(), value.clone());
+            }
+            Some(new_value) if new_value != value => {
+                change.changed.insert(
+                    key.clone(),
+                    ValueChange {
+                        before: value.clone(),
+                        after: new_value.clone(),
+                    },
+                );
+            }
+            Some(_) => (),
+        }
+    }
+    for (key, value) in after.iter() {
+        if !before.contains_key(key) {
+            change.added.insert(key.clone(), value.clone());
+        }
+    }
+    if change.added.is_empty() && change.removed.is_empty() && change.changed.is_empty() {
+        return None;
+    }
+    Some(change)
+}
+
+pub(crate) fn diff(base: &DiffEntries, other: &DiffEntries) -> ProjectDiff {
+    // Changes are reported going from `base` to `other`
+    let mut result = ProjectDiff::default();
+    for (path, entry) in base.iter() {
+        let other_entry = match other.get(path) {
+            None => {
+                result.removed.push(FileSummary::new(path, entry));
+                continue;
+            }
+            Some(e) => e,
+        };
+        let target = if entry.real_path != other_entry.real_path {
+            Some(ValueChange {
+                before: entry.real_path.to_string_lossy().to_string(),
+                after: other_entry.real_path.to_string_lossy().to_string(),
+            })
+        } else {
+            None
+        };
+        let metadata = diff_metadata(&entry.metadata, &other_entry.metadata);
+        if target.is_some() || metadata.is_some() {
+            result.changed.push(FileChange {
+                path: path.clone(),
+                target,
+                metadata,
+            });
+        }
+    }
+    for (path, entry) in other.iter() {
+        if !base.contains_key(path) {
+            result.added.push(FileSummary::new(path, entry));
+        }
+    }
+    result
+}
@@ -1,47 +1,228 @@
+// Errors returned by the server. Every error has a broad type, which decides the HTTP
+// status, and a stable machine-readable code naming the failure, e.g.
+// `collection_not_found` or `path_is_folder`, so clients never have to parse messages.
+// Error bodies look like
+//
+//     {
+//         "code": "project_not_found",
+//         "type": "not_found",
+//         "message": "Project `p` does not exist",
+//         "details": {"collection": "c", "project": "p"},
+//         "request_id": "2403b36c-0a80-4ce6-a01b-9c86958b20e2"
+//     }
+
+use crate::request;
+use serde::Serialize;
+use serde_json::Value;
+use std::convert::Infallible;
 use std::error::Error;
+use warp::http::StatusCode;
+use warp::{Rejection, Reply};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub(crate) enum GodataErrorType {
     NotFound,
     AlreadyExists,
     InvalidPath,
+    InvalidArgument,
     NotPermitted,
+    Unauthorized,
+    Conflict,
+    PreconditionFailed,
+    Unavailable,
+    #[serde(rename = "io_error")]
     IOError,
     InternalError,
 }
 
-impl Into<warp::http::StatusCode> for GodataErrorType {
-    fn into(self) -> warp::http::StatusCode {
+impl GodataErrorType {
+    // Used for errors that don't have a more specific code
+    fn default_code(&self) -> &'static str {
         match self {
-            GodataErrorType::NotFound => warp::http::StatusCode::NOT_FOUND,
-            GodataErrorType::AlreadyExists => warp::http::StatusCode::CONFLICT,
-            GodataErrorType::InvalidPath => warp::http::StatusCode::BAD_REQUEST,
-            GodataErrorType::NotPermitted => warp::http::StatusCode::FORBIDDEN,
-            _ => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            GodataErrorType::NotFound => "not_found",
+            GodataErrorType::AlreadyExists => "already_exists",
+            GodataErrorType::InvalidPath => "invalid_path",
+            GodataErrorType::InvalidArgument => "invalid_argument",
+            GodataErrorType::NotPermitted => "not_permitted",
+            GodataErrorType::Unauthorized => "unauthorized",
+            GodataErrorType::Conflict => "conflict",
+            GodataErrorType::PreconditionFailed => "precondition_failed",
+            GodataErrorType::Unavailable => "unavailable",
+            GodataErrorType::IOError => "io_error",
+            GodataErrorType::InternalError => "internal_error",
+        }
+    }
+}
+
+impl From<GodataErrorType> for StatusCode {
+    fn from(val: GodataErrorType) -> Self {
+        match val {
+            GodataErrorType::NotFound => StatusCode::NOT_FOUND,
+            GodataErrorType::AlreadyExists => StatusCode::CONFLICT,
+            GodataErrorType::InvalidPath => StatusCode::BAD_REQUEST,
+            GodataErrorType::InvalidArgument => StatusCode::BAD_REQUEST,
+            GodataErrorType::NotPermitted => StatusCode::FORBIDDEN,
+            GodataErrorType::Unauthorized => StatusCode::UNAUTHORIZED,
+            GodataErrorType::Conflict => StatusCode::CONFLICT,
+            GodataErrorType::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            GodataErrorType::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            GodataErrorType::IOError | GodataErrorType::InternalError => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub(crate) struct GodataError {
+    #[serde(rename = "type")]
     pub(crate) error_type: GodataErrorType,
+    pub(crate) code: &'static str,
     pub(crate) message: String,
+    pub(crate) details: Option<Value>,
 }
 
-impl warp::Reply for GodataError {
-    fn into_response(self) -> warp::reply::Response {
-        warp::reply::with_status(warp::reply::json(&self.message), self.error_type.into())
-            .into_response()
-    }
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    #[serde(rename = "type")]
+    error_type: GodataErrorType,
+    message: &'a str,
+    details: &'a Option<Value>,
+    request_id: Option<String>,
 }
 
 impl GodataError {
     pub(crate) fn new(error_type: GodataErrorType, message: String) -> Self {
         Self {
             error_type,
+            code: error_type.default_code(),
             message,
+            details: None,
         }
     }
+
+    pub(crate) fn with_code(mut self, code: &'static str) -> Self {
+        self.code = code;
+        self
+    }
+
+    pub(crate) fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub(crate) fn missing_argument(argument: &str) -> Self {
+        GodataError::new(
+            GodataErrorType::InvalidArgument,
+            format!("Missing {} argument", argument),
+        )
+        .with_code("missing_argument")
+        .with_details(serde_json::json!({ "argument": argument }))
+    }
+
+    pub(crate) fn invalid_argument(argument: &str, value: &str, message: String) -> Self {
+        GodataError::new(GodataErrorType::InvalidArgument, message)
+            .with_code("invalid_argument")
+            .with_details(serde_json::json!({ "argument": argument, "value": value }))
+    }
+
+    // Every error the server sends carries the ID of the request, so it can be matched
+    // to the server logs
+    fn response(&self, status: StatusCode) -> warp::reply::Response {
+        let body = ErrorBody {
+            code: self.code,
+            error_type: self.error_type,
+            message: &self.message,
+            details: &self.details,
+            request_id: request::current_id(),
+        };
+        warp::reply::with_status(warp::reply::json(&body), status).into_response()
+    }
+}
+
+impl Reply for GodataError {
+    fn into_response(self) -> warp::reply::Response {
+        self.response(self.error_type.into())
+    }
+}
+
+impl warp::reject::Reject for GodataError {}
+
+// Requests that never reach a handler are answered with the same error body
+pub(crate) async fn handle_rejection(
+    rejection: Rejection,
+) -> std::result::Result<warp::reply::Response, Infallible> {
+    use warp::filters::body::BodyDeserializeError;
+    use warp::reject::{
+        InvalidHeader, InvalidQuery, LengthRequired, MethodNotAllowed, MissingHeader,
+        PayloadTooLarge, UnsupportedMediaType,
+    };
+
+    if let Some(e) = rejection.find::<GodataError>() {
+        return Ok(e.response(e.error_type.into()));
+    }
+    let invalid = |code: &'static str, message: String| {
+        GodataError::new(GodataErrorType::InvalidArgument, message).with_code(code)
+    };
+    let (status, error) = if let Some(e) = rejection.find::<BodyDeserializeError>() {
+        (
+            StatusCode::BAD_REQUEST,
+            invalid("invalid_body", e.to_string()),
+        )
+    } else if let Some(e) = rejection.find::<InvalidQuery>() {
+        (
+            StatusCode::BAD_REQUEST,
+            invalid("invalid_query", e.to_string()),
+        )
+    } else if let Some(e) = rejection.find::<MissingHeader>() {
+        (
+            StatusCode::BAD_REQUEST,
+            invalid("missing_header", e.to_string()),
+        )
+    } else if let Some(e) = rejection.find::<InvalidHeader>() {
+        (
+            StatusCode::BAD_REQUEST,
+            invalid("invalid_header", e.to_string()),
+        )
+    } else if let Some(e) = rejection.find::<UnsupportedMediaType>() {
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            invalid("unsupported_media_type", e.to_string()),
+        )
+    } else if let Some(e) = rejection.find::<LengthRequired>() {
+        (
+            StatusCode::LENGTH_REQUIRED,
+            invalid("length_required", e.to_string()),
+        )
+    } else if let Some(e) = rejection.find::<PayloadTooLarge>() {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            invalid("payload_too_large", e.to_string()),
+        )
+    } else if let Some(e) = rejection.find::<MethodNotAllowed>() {
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            invalid("method_not_allowed", e.to_string()),
+        )
+    } else if rejection.is_not_found() {
+        (
+            StatusCode::NOT_FOUND,
+            GodataError::new(GodataErrorType::NotFound, "Route not found".to_string())
+                .with_code("route_not_found"),
+        )
+    } else {
+        tracing::error!("Unhandled rejection: {:?}", rejection);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            GodataError::new(
+                GodataErrorType::InternalError,
+                "Internal server error".to_string(),
+            ),
+        )
+    };
+    Ok(error.response(status))
 }
 
 impl std::fmt::Display for GodataError {
@@ -52,37 +233,27 @@ impl std::fmt::Display for GodataError {
 
 impl From<std::io::Error> for GodataError {
     fn from(error: std::io::Error) -> Self {
-        Self {
-            error_type: GodataErrorType::IOError,
-            message: error.to_string(),
-        }
+        GodataError::new(GodataErrorType::IOError, error.to_string())
     }
 }
 
 impl From<sled::Error> for GodataError {
     fn from(error: sled::Error) -> Self {
-        Self {
-            error_type: GodataErrorType::IOError,
-            message: error.to_string(),
-        }
+        GodataError::new(GodataErrorType::IOError, error.to_string()).with_code("database_error")
     }
 }
 
 impl From<regex::Error> for GodataError {
     fn from(error: regex::Error) -> Self {
-        Self {
-            error_type: GodataErrorType::InvalidPath,
-            message: error.to_string(),
-        }
+        GodataError::new(GodataErrorType::InvalidArgument, error.to_string())
+            .with_code("invalid_pattern")
     }
 }
 
 impl From<fnmatch_regex::error::Error> for GodataError {
     fn from(error: fnmatch_regex::error::Error) -> Self {
-        Self {
-            error_type: GodataErrorType::InvalidPath,
-            message: error.to_string(),
-        }
+        GodataError::new(GodataErrorType::InvalidArgument, error.to_string())
+            .with_code("invalid_pattern")
     }
 }
 
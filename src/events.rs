@@ -0,0 +1,277 @@
+// Change events. Every change a project makes to its tree is published as an event, so
+// dashboards and later pipeline stages can react to files being linked, moved or
+// removed instead of polling. Clients subscribe to the events of one project over
+// Server-Sent Events, optionally only for the paths below a prefix.
+//
+// Events are only handed to the clients listening when they are published, nothing is
+// stored. Every project has its own channel, so a burst of changes to one project never
+// holds up the clients of another. A client that falls too far behind is told how many
+// events it missed.
+//
+// Changes are held back until they are saved, so a change or batch that is rolled back
+// never publishes anything.
+
+use crate::diff::MetadataChange;
+use crate::errors::Result;
+use crate::fsystem::is_within;
+use crate::sync::lock;
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, watch};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+
+// Number of events a client can fall behind on a project before it starts missing them
+const EVENT_BUFFER: usize = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Operation {
+    Link,
+    Move,
+    Remove,
+    Metadata,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Change {
+    operation: Operation,
+    project_path: String,
+    // Where a moved file or folder came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    real_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<MetadataChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revision: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ChangeEvent {
+    collection: String,
+    project: String,
+    #[serde(flatten)]
+    change: Change,
+    timestamp: DateTime<Utc>,
+}
+
+// What a subscriber receives
+pub(crate) enum Delivery {
+    Event(Arc<ChangeEvent>),
+    Missed(u64),
+}
+
+impl Operation {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Link => "link",
+            Operation::Move => "move",
+            Operation::Remove => "remove",
+            Operation::Metadata => "metadata",
+        }
+    }
+}
+
+impl Change {
+    pub(crate) fn new(operation: Operation, project_path: &str) -> Change {
+        Change {
+            operation,
+            project_path: project_path.to_string(),
+            source_path: None,
+            real_path: None,
+            metadata: None,
+            revision: None,
+        }
+    }
+
+    pub(crate) fn with_source(mut self, source_path: &str) -> Self {
+        self.source_path = Some(source_path.to_string());
+        self
+    }
+
+    pub(crate) fn with_real_path(mut self, real_path: Option<String>) -> Self {
+        self.real_path = real_path;
+        self
+    }
+
+    pub(crate) fn with_metadata(mut self, metadata: Option<MetadataChange>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub(crate) fn with_revision(mut self, revision: Option<u64>) -> Self {
+        self.revision = revision;
+        self
+    }
+}
+
+impl ChangeEvent {
+    pub(crate) fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    pub(crate) fn project(&self) -> &str {
+        &self.project
+    }
+
+    pub(crate) fn operation(&self) -> Operation {
+        self.change.operation
+    }
+
+    pub(crate) fn project_path(&self) -> &str {
+        &self.change.project_path
+    }
+
+    pub(crate) fn real_path(&self) -> Option<&str> {
+        self.change.real_path.as_deref()
+    }
+
+    fn matches(&self, prefix: Option<&str>) -> bool {
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim_matches('/'),
+            None => return true,
+        };
+        // A move is seen from both ends
+        is_within(&self.change.project_path, prefix)
+            || self
+                .change
+                .source_path
+                .as_deref()
+                .is_some_and(|source| is_within(source, prefix))
+    }
+}
+
+// Shared by every project. Closing the bus ends every subscription, which the server
+// does on shutdown, since it would otherwise wait on open event streams forever.
+pub(crate) struct EventBus {
+    // The channel of each project, by `collection/project`, created when it's first used.
+    // Channels are kept for as long as the server runs, so a project that is dropped and
+    // loaded again still reaches the clients that were already listening.
+    channels: Mutex<HashMap<String, broadcast::Sender<Arc<ChangeEvent>>>>,
+    closed: watch::Sender<bool>,
+}
+
+impl EventBus {
+    pub(crate) fn new() -> EventBus {
+        let (closed, _) = watch::channel(false);
+        EventBus {
+            channels: Mutex::new(HashMap::new()),
+            closed,
+        }
+    }
+
+    fn channel(
+        &self,
+        collection: &str,
+        project: &str,
+    ) -> Result<broadcast::Sender<Arc<ChangeEvent>>> {
+        let mut channels = lock(&self.channels)?;
+        let sender = channels
+            .entry(format!("{}/{}", collection, project))
+            .or_insert_with(|| broadcast::channel(EVENT_BUFFER).0);
+        Ok(sender.clone())
+    }
+
+    pub(crate) fn publisher(&self, collection: &str, project: &str) -> Result<Publisher> {
+        Ok(Publisher {
+            sender: self.channel(collection, project)?,
+            collection: collection.to_string(),
+            project: project.to_string(),
+            held: None,
+        })
+    }
+
+    pub(crate) fn subscribe(
+        &self,
+        collection: String,
+        project: String,
+        prefix: Option<String>,
+    ) -> Result<impl Stream<Item = Delivery>> {
+        let mut closed = self.closed.subscribe();
+        let receiver = self.channel(&collection, &project)?.subscribe();
+        Ok(BroadcastStream::new(receiver)
+            .filter_map(move |item| {
+                let delivery = match item {
+                    Ok(event) if event.matches(prefix.as_deref()) => Some(Delivery::Event(event)),
+                    Ok(_) => None,
+                    Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                        tracing::warn!("Event subscriber missed {} events", missed);
+                        Some(Delivery::Missed(missed))
+                    }
+                };
+                async move { delivery }
+            })
+            .take_until(async move {
+                let _ = closed.wait_for(|closed| *closed).await;
+            }))
+    }
+
+    pub(crate) fn close(&self) {
+        self.closed.send_replace(true);
+    }
+}
+
+// Publishes the changes made to one project
+pub(crate) struct Publisher {
+    sender: broadcast::Sender<Arc<ChangeEvent>>,
+    collection: String,
+    project: String,
+    // Changes from the batch being applied
+    held: Option<Vec<ChangeEvent>>,
+}
+
+// Events are returned once they are sent, so the project can pass them on to its hooks
+impl Publisher {
+    pub(crate) fn publish(&mut self, change: Change) -> Option<Arc<ChangeEvent>> {
+        let event = ChangeEvent {
+            collection: self.collection.clone(),
+            project: self.project.clone(),
+            change,
+            timestamp: Utc::now(),
+        };
+        match self.held.as_mut() {
+            Some(held) => {
+                held.push(event);
+                None
+            }
+            None => Some(self.send(event)),
+        }
+    }
+
+    pub(crate) fn hold(&mut self) {
+        self.held = Some(Vec::new());
+    }
+
+    // Revisions are only assigned when the changes are saved, so the held changes get
+    // theirs from the committed tree
+    pub(crate) fn release(
+        &mut self,
+        revision: impl Fn(&str) -> Option<u64>,
+    ) -> Vec<Arc<ChangeEvent>> {
+        let held = self.held.take().unwrap_or_default();
+        held.into_iter()
+            .map(|mut event| {
+                if event.change.revision.is_some() {
+                    event.change.revision = revision(&event.change.project_path);
+                }
+                self.send(event)
+            })
+            .collect()
+    }
+
+    pub(crate) fn discard(&mut self) {
+        self.held = None;
+    }
+
+    fn send(&self, event: ChangeEvent) -> Arc<ChangeEvent> {
+        let event = Arc::new(event);
+        // Sending only fails when nobody is listening, in which case there's no one to tell
+        let _ = self.sender.send(event.clone());
+        event
+    }
+}
@@ -4,17 +4,22 @@
 
 // As far as the rest of the library is concrened,
 
+use chrono::Utc;
 use regex::Regex;
-use sled::{Batch, Db};
-use std::collections::HashMap;
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use sled::{Batch, Db, Transactional, Tree};
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
 use ciborium::{from_reader, into_writer};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tracing::instrument;
 
 use crate::errors::{GodataError, GodataErrorType, Result};
+use crate::lineage::Provenance;
+use crate::locations::path_to_string;
+use crate::metadata::{self, Metadata, METADATA_VERSION};
 
 #[derive(Clone)]
 enum FSObject {
@@ -29,25 +34,54 @@ impl FSObject {
         }
     }
 
+    fn revision(&self) -> u64 {
+        match self {
+            FSObject::File(f) => f.revision,
+            FSObject::Folder(f) => f.revision,
+        }
+    }
+
     fn rename(&mut self, new_name: String) {
         match self {
-            FSObject::File(f) => f.name = new_name,
-            FSObject::Folder(f) => f.name = new_name,
+            FSObject::File(f) => {
+                f.name = new_name;
+                f.revision = UNSAVED;
+            }
+            FSObject::Folder(f) => {
+                // The name is stored in the folder's own record
+                f.name = new_name;
+                f._modified = true;
+            }
         }
     }
 }
+
+// Every file and folder carries the revision at which it last changed. A project has a
+// single counter which is bumped by each save, so a revision is never reused within a
+// project, even when an entry is removed and another is created at the same path. A
+// folder changes when its own metadata or its direct children change.
+//
+// A file that has changed since the last save has revision `UNSAVED`, and gets the new
+// revision when its folder is written. Entries stored before revisions existed also
+// read as `UNSAVED` until they are next written.
+const UNSAVED: u64 = 0;
+
 #[derive(Clone)]
 pub(crate) struct File {
     pub(crate) real_path: PathBuf,
     pub(crate) name: String,
-    pub(crate) metadata: HashMap<String, String>,
+    pub(crate) metadata: Metadata,
+    pub(crate) revision: u64,
+    // What the file was made from, see `lineage`
+    pub(crate) provenance: Option<Box<Provenance>>,
     _uuid: String,
 }
 #[derive(Clone)]
 struct Folder {
     pub(self) name: String,
     children: HashMap<String, FSObject>,
-    metadata: HashMap<String, String>,
+    metadata: Metadata,
+    revision: u64,
     _uuid: String,
     _modified: bool,
 }
@@ -58,7 +92,9 @@ struct DbFolder {
     folders_uuids: Vec<String>,
     files: Vec<DbFile>,
     #[serde(default)]
-    metadata: HashMap<String, String>,
+    metadata: Metadata,
+    #[serde(default)]
+    revision: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -67,7 +103,37 @@ struct DbFile {
     real_path: String,
     uuid: String,
     #[serde(default)]
-    metadata: HashMap<String, String>,
+    metadata: Metadata,
+    #[serde(default)]
+    revision: u64,
+    #[serde(default)]
+    provenance: Option<Box<Provenance>>,
+}
+
+// Snapshots are stored as separate trees in the project database. An index tree
+// holds the information about each snapshot, so a snapshot tree without an index
+// entry (for example from an interrupted write) is never visible.
+const SNAPSHOT_INDEX: &str = "snapshots";
+const SNAPSHOT_PREFIX: &str = "snapshot:";
+const METADATA_VERSION_KEY: &str = "metadata_version";
+const REVISION_KEY: &str = "revision";
+// Leases on paths in the project (see `lease`) are kept alongside the tree
+const LEASE_TREE: &str = "leases";
+// As are the project's hooks (see `hooks`)
+const HOOK_TREE: &str = "hooks";
+// And its audit trail (see `audit`)
+const AUDIT_TREE: &str = "audit";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct SnapshotInfo {
+    pub(crate) name: String,
+    pub(crate) created: String,
+    pub(crate) files: usize,
+}
+
+pub(crate) struct Snapshot {
+    // A read-only view of a tree, as it was when a snapshot was taken or as exported
+    root: Folder,
 }
 
 pub(crate) struct FileSystem {
@@ -75,6 +141,27 @@ pub(crate) struct FileSystem {
     _name: String,
     _modified: bool,
     db: Db,
+    // The revision written by the last save
+    revision: u64,
+    // Records of folders that have been removed from the tree, deleted on the next save
+    dropped: Vec<String>,
+    // One for each open transaction, innermost last
+    savepoints: Vec<Savepoint>,
+}
+
+// Changes are made to the in-memory tree first and written to the database by `save`,
+// which deletes the dropped folder records and writes the modified folders in a single
+// sled `Batch`. Sled applies a batch atomically, so the stored tree always matches the
+// in-memory tree at some save and is never half-updated.
+//
+// A transaction groups several changes into one save. While a transaction is open
+// saving is deferred until the outermost transaction commits, and aborting restores the
+// tree as it was when the transaction began. Transactions can be nested, an inner
+// commit just hands its changes to the enclosing transaction.
+struct Savepoint {
+    root: Folder,
+    dropped: usize,
+    modified: bool,
 }
 
 enum RemoveResult {
@@ -82,18 +169,91 @@ enum RemoveResult {
     IsEmpty,
 }
 
-pub(crate) fn is_empty(path: &PathBuf) -> bool {
-    let db = sled::open(path).unwrap();
+pub(crate) fn is_within(path: &str, folder: &str) -> bool {
+    // Whether a virtual path is the folder itself or somewhere below it
+    folder.is_empty()
+        || path
+            .strip_prefix(folder)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+pub(crate) fn is_empty(path: &PathBuf) -> Result<bool> {
+    let db = sled::open(path)?;
     // Count the entries in the database
-    let root_folder = db.get("root".as_bytes()).unwrap();
+    let root_folder = match db.get("root".as_bytes())? {
+        Some(root_folder) => root_folder,
+        None => return Ok(true),
+    };
     // Deserialize the root folder
-    let db_folder = from_reader(root_folder.unwrap().as_ref());
-    let db_folder: DbFolder = db_folder.unwrap();
+    let db_folder: DbFolder = decode(&root_folder)?;
     // If there are any files or folders in the root folder, return false
-    if db_folder.folders_uuids.len() > 0 || db_folder.files.len() > 0 {
-        return false;
+    Ok(db_folder.folders_uuids.is_empty() && db_folder.files.is_empty())
+}
+
+#[instrument]
+pub(crate) fn validate_tree(path: &PathBuf) -> Result<usize> {
+    // Check that an exported tree can be loaded before importing it. Every folder
+    // must be present and decodable, starting from the root. Returns the number of
+    // files found in the tree.
+    if !path.is_dir() {
+        return Err(GodataError::new(
+            GodataErrorType::NotFound,
+            format!("No tree found at `{}`", path.display()),
+        )
+        .with_code("tree_not_found")
+        .with_details(serde_json::json!({"path": path})));
+    }
+    let db = sled::open(path)?;
+    let mut file_count = 0;
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut to_visit = vec!["root".to_string()];
+    while let Some(uuid) = to_visit.pop() {
+        if !seen.insert(uuid.clone()) {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("Folder `{}` is referenced more than once in the tree", uuid),
+            )
+            .with_code("invalid_tree"));
+        }
+        let bytes = match db.get(uuid.as_bytes())? {
+            Some(bytes) => bytes,
+            None if uuid == "root" => {
+                return Err(GodataError::new(
+                    GodataErrorType::InvalidPath,
+                    "Tree does not contain a root folder".to_string(),
+                )
+                .with_code("invalid_tree"))
+            }
+            None => {
+                return Err(GodataError::new(
+                    GodataErrorType::InvalidPath,
+                    format!("Folder `{}` is referenced but missing from the tree", uuid),
+                )
+                .with_code("invalid_tree"))
+            }
+        };
+        let db_folder: DbFolder = from_reader(bytes.as_ref()).map_err(|e| {
+            GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("Folder `{}` could not be decoded: {}", uuid, e),
+            )
+            .with_code("invalid_tree")
+        })?;
+        file_count += db_folder.files.len();
+        to_visit.extend(db_folder.folders_uuids);
     }
-    true
+    Ok(file_count)
+}
+
+pub(crate) fn read_tree(path: &Path) -> Result<Snapshot> {
+    // Open an exported tree without writing to it. Old metadata is migrated in memory
+    // only, so the export is left exactly as it was.
+    let db = sled::open(path)?;
+    let mut root = Folder::from_tree(&db, "root".to_string())?;
+    if needs_migration(&db)? {
+        root.migrate_metadata();
+    }
+    Ok(Snapshot { root })
 }
 
 fn drain(mut folder: Folder) -> Vec<File> {
@@ -123,9 +283,10 @@ impl FileSystem {
             Err(e) => {
                 tracing::error!("Sled failed to open database: {}", e);
                 return Err(GodataError::new(
-                    GodataErrorType::IOError,
-                    format!("Failed to open database"),
-                ));
+                    GodataErrorType::Unavailable,
+                    "Failed to open database".to_string(),
+                )
+                .with_code("database_unavailable"));
             }
         };
 
@@ -136,6 +297,7 @@ impl FileSystem {
                 name: "root".to_string(),
                 children: HashMap::new(),
                 metadata: HashMap::new(),
+                revision: UNSAVED,
                 _uuid: "root".to_string(),
                 _modified: true,
             },
@@ -148,7 +310,8 @@ impl FileSystem {
                 return Err(GodataError::new(
                     GodataErrorType::AlreadyExists,
                     "File system already exists".to_string(),
-                ));
+                )
+                .with_code("project_exists"));
             }
         };
 
@@ -157,9 +320,13 @@ impl FileSystem {
             _name: name,
             _modified: true,
             db,
+            revision: 0,
+            dropped: Vec::new(),
+            savepoints: Vec::new(),
         })
     }
 
+    #[allow(clippy::type_complexity)]
     #[instrument(skip(self))]
     pub(crate) fn export(
         &mut self,
@@ -167,7 +334,17 @@ impl FileSystem {
         // Copy the database to the specified path
         self.save()?;
         self.db.flush()?;
-        let res = self.db.export();
+        // Leases belong to the running project, not to its contents
+        let res = self
+            .db
+            .export()
+            .into_iter()
+            .filter(|(_, name, _)| {
+                ![LEASE_TREE, HOOK_TREE, AUDIT_TREE]
+                    .iter()
+                    .any(|tree| name.as_slice() == tree.as_bytes())
+            })
+            .collect();
         tracing::info!("Serialized database for project `{}`", self._name);
         Ok(res)
     }
@@ -184,9 +361,10 @@ impl FileSystem {
                     e
                 );
                 return Err(GodataError::new(
-                    GodataErrorType::IOError,
-                    format!("Failed to open database"),
-                ));
+                    GodataErrorType::Unavailable,
+                    "Failed to open database".to_string(),
+                )
+                .with_code("database_unavailable"));
             }
         };
         let root_folder = db.get("root".as_bytes())?;
@@ -203,17 +381,40 @@ impl FileSystem {
                 return Err(GodataError::new(
                     GodataErrorType::NotFound,
                     "File system was opened, but no root folder was found".to_string(),
-                ));
+                )
+                .with_code("storage_corrupted"));
             }
             Some(_) => Folder::from_tree(&db, "root".to_string())?,
         };
 
-        Ok(FileSystem {
+        let revision = match db.get(REVISION_KEY)? {
+            Some(bytes) => Some(decode::<u64>(&bytes)?),
+            None => None,
+        };
+        let mut fs = FileSystem {
             root,
             _modified: false,
             _name: name.to_string(),
             db,
-        })
+            revision: revision.unwrap_or_default(),
+            dropped: Vec::new(),
+            savepoints: Vec::new(),
+        };
+        if needs_migration(&fs.db)? {
+            tracing::info!("Migrating metadata for project `{}`", name);
+            fs.root.migrate_metadata();
+            fs._modified = true;
+        }
+        if revision.is_none() {
+            // Written before revisions existed, give every entry one
+            tracing::info!("Adding revisions to project `{}`", name);
+            fs.root.touch();
+            fs._modified = true;
+        }
+        if fs._modified {
+            fs.save()?;
+        }
+        Ok(fs)
     }
 
     #[instrument(skip(self))]
@@ -221,49 +422,31 @@ impl FileSystem {
         &self,
         virtual_path: Option<String>,
     ) -> Result<HashMap<String, Vec<String>>> {
-        let folder = match virtual_path {
-            Some(path) => {
-                let f_ = self.root.get(&path)?;
-                match f_ {
-                    FSObject::File(_) => {
-                        tracing::info!("Path is a file!");
-                        return Err(GodataError::new(
-                            GodataErrorType::InvalidPath,
-                            format!("Path `{}` is a file", path),
-                        ));
-                    }
-                    FSObject::Folder(f) => f,
-                }
-            }
-            None => &self.root,
-        };
-        let mut files = Vec::new();
-        let mut folders = Vec::new();
-
-        for (name, child) in folder.children.iter() {
-            match child {
-                FSObject::File(_) => files.push(name.clone()),
-                FSObject::Folder(_) => folders.push(name.clone()),
-            }
-        }
-        let mut children = HashMap::new();
-        children.insert("folders".to_string(), folders);
-        children.insert("files".to_string(), files);
-        Ok(children)
+        self.root.list(virtual_path)
     }
 
     #[instrument(skip(self))]
     pub(crate) fn get(&self, virtual_path: &str) -> Result<&File> {
-        let file = self.root.get(virtual_path)?;
-        match file {
-            FSObject::Folder(_) => {
-                tracing::info!("Path is a folder!");
-                Err(GodataError::new(
-                    GodataErrorType::InvalidPath,
-                    "Path is a folder".into(),
-                ))
-            }
-            FSObject::File(f) => Ok(f),
+        self.root.get_file(virtual_path)
+    }
+
+    pub(crate) fn revision(&self, virtual_path: &str) -> Result<u64> {
+        self.root.revision(virtual_path)
+    }
+
+    // The revision a path will have once the open transaction is saved. Anything changed
+    // in the transaction is written at the next revision of the project.
+    pub(crate) fn pending_revision(&self, virtual_path: &str) -> Result<u64> {
+        let changed = match virtual_path {
+            "" => self.root._modified,
+            _ => match self.root.get(virtual_path)? {
+                FSObject::File(f) => f.revision == UNSAVED,
+                FSObject::Folder(f) => f._modified,
+            },
+        };
+        match changed {
+            true => Ok(self.revision + 1),
+            false => self.root.revision(virtual_path),
         }
     }
 
@@ -281,7 +464,8 @@ impl FileSystem {
                         return Err(GodataError::new(
                             GodataErrorType::InvalidPath,
                             format!("Path `{}` is a file", path),
-                        ));
+                        )
+                        .with_code("path_is_file"));
                     }
                     FSObject::Folder(f) => f,
                 }
@@ -299,7 +483,8 @@ impl FileSystem {
                     virtual_path.unwrap_or("root"),
                     pattern
                 ),
-            )),
+            )
+            .with_code("no_matching_files")),
         }
     }
 
@@ -307,36 +492,40 @@ impl FileSystem {
         &mut self,
         project_path: &str,
         real_path: PathBuf,
-        metadata: HashMap<String, String>,
+        metadata: Metadata,
+        provenance: Option<Provenance>,
         overwrite: bool,
     ) -> Result<Option<Vec<File>>> {
-        let name = project_path.split('/').last().unwrap().to_string();
-        let result = if name == project_path {
-            let mut file = File::new(real_path, name);
-            file.metadata = metadata;
-            self.root.insert(FSObject::File(file), "", overwrite)?
-        } else {
-            let ppath = project_path
-                .strip_suffix(format!("/{}", name).as_str())
-                .unwrap();
-            let mut file = File::new(real_path, name);
-            file.metadata = metadata;
-            self.root.insert(FSObject::File(file), ppath, overwrite)?
-        };
-        self._modified = true;
-        self.save()?;
-        Ok(result)
+        let (ppath, name) = project_path.rsplit_once('/').unwrap_or(("", project_path));
+        let mut file = File::new(real_path, name.to_string());
+        file.metadata = metadata;
+        file.provenance = provenance.map(Box::new);
+        if !overwrite {
+            let result = self.root.insert(FSObject::File(file), ppath, false)?;
+            self._modified = true;
+            self.save()?;
+            return Ok(result.map(|previous| self.discard(previous)));
+        }
+        // Overwriting can replace a whole folder
+        self.transaction(|fs| {
+            let result = fs.root.insert(FSObject::File(file), ppath, true)?;
+            fs._modified = true;
+            Ok(result.map(|previous| fs.discard(previous)))
+        })
     }
 
     pub(crate) fn insert_many<I>(&mut self, files: I, virtual_path: &str) -> Result<()>
     where
         I: Iterator<Item = PathBuf>,
     {
-        let file_objects = files.map(|path| {
-            let name = path.file_name().unwrap().to_str().unwrap().to_string();
-            File::new(path, name)
-        });
-        self.root.insert_many(file_objects, virtual_path)?;
+        let file_objects = files
+            .filter_map(|path| {
+                let name = path.file_name()?.to_os_string();
+                Some(path_to_string(Path::new(&name)).map(|name| File::new(path, name)))
+            })
+            .collect::<Result<Vec<File>>>()?;
+        self.root
+            .insert_many(file_objects.into_iter(), virtual_path)?;
         self._modified = true;
         self.save()?;
         Ok(())
@@ -349,37 +538,21 @@ impl FileSystem {
             "Removed item at path `{}`, dropping from tree",
             virtual_path
         );
-        let mut batch = Batch::default();
         let output = match result {
             RemoveResult::IsEmpty => {
-                self.root.drop_from_tree(&mut batch)?;
                 let mut files: Vec<File> = Vec::new();
-                for (_, child) in self.root.children.drain() {
-                    match child {
-                        FSObject::File(f) => {
-                            files.push(f);
-                        }
-                        FSObject::Folder(f) => {
-                            let mut child_files = drain(f);
-                            files.append(&mut child_files);
-                        }
-                    }
+                let children: Vec<FSObject> =
+                    self.root.children.drain().map(|(_, child)| child).collect();
+                for child in children {
+                    files.append(&mut self.discard(child));
                 }
-                self.root.children.clear();
+                self.root._modified = true;
                 files
             }
-            RemoveResult::Item(f) => match f {
-                FSObject::File(f) => {
-                    vec![f]
-                }
-                FSObject::Folder(mut f) => {
-                    f.drop_from_tree(&mut batch)?;
-                    drain(f)
-                }
-            },
+            RemoveResult::Item(item) => self.discard(item),
         };
-        self.db.apply_batch(batch)?;
         self._modified = true;
+        self.save()?;
 
         Ok(output)
     }
@@ -396,14 +569,26 @@ impl FileSystem {
             return Err(GodataError::new(
                 GodataErrorType::NotFound,
                 format!("Source path `{}` does not exist", source_path),
-            ));
+            )
+            .with_code("path_not_found")
+            .with_details(serde_json::json!({"path": source_path})));
         }
         if self.root.exists(dest_path) && !overwrite {
             tracing::info!("Destination path already exists");
             return Err(GodataError::new(
                 GodataErrorType::AlreadyExists,
                 format!("Destination path `{}` already exists", dest_path),
-            ));
+            )
+            .with_code("path_exists")
+            .with_details(serde_json::json!({"path": dest_path})));
+        }
+        if dest_path.starts_with(&format!("{}/", source_path)) {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("Cannot move `{}` into itself", source_path),
+            )
+            .with_code("move_into_self")
+            .with_details(serde_json::json!({"path": source_path})));
         }
         let item = self.root.get(source_path)?;
         // HANDLE RENAME SEMANTICS
@@ -413,24 +598,376 @@ impl FileSystem {
         item.rename(fname.to_string());
         // Split the destination path into path and name
 
-        let result = self.root.insert(item, fpath, overwrite)?;
-        self.remove(source_path)?;
-        self._modified = true;
-        self.save()?;
-        Ok(result)
+        // The copy keeps the records of a moved folder, so they survive removing the
+        // source. Either both steps are saved or neither is.
+        self.transaction(|fs| {
+            let result = fs.root.insert(item, fpath, overwrite)?;
+            let result = result.map(|previous| fs.discard(previous));
+            fs.remove(source_path)?;
+            fs._modified = true;
+            Ok(result)
+        })
+    }
+
+    fn discard(&mut self, item: FSObject) -> Vec<File> {
+        // Take an item that has been removed from the tree, returning all of its files.
+        // Records for any folders are deleted on the next save.
+        match item {
+            FSObject::File(f) => vec![f],
+            FSObject::Folder(f) => {
+                f.collect_uuids(&mut self.dropped);
+                drain(f)
+            }
+        }
     }
 
     pub(crate) fn exists(&self, virtual_path: &str) -> bool {
         self.root.exists(virtual_path)
     }
 
+    pub(crate) fn walk(&self) -> Vec<(String, &File)> {
+        // Return every file in the tree, along with its full virtual path
+        let mut files = Vec::new();
+        self.root.walk("", &mut files);
+        files
+    }
+
+    pub(crate) fn walk_folders(&self) -> Vec<(String, &Metadata)> {
+        // Return every folder below the root, along with its full virtual path and metadata
+        let mut folders = Vec::new();
+        self.root.walk_folders("", &mut folders);
+        folders
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn set_folder_metadata(
+        &mut self,
+        virtual_path: &str,
+        metadata: Metadata,
+    ) -> Result<()> {
+        let folder = self.root.get_folder_mut(virtual_path)?;
+        folder.metadata = metadata;
+        folder._modified = true;
+        self._modified = true;
+        self.save()
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn update_metadata(
+        &mut self,
+        virtual_path: &str,
+        metadata: Metadata,
+        replace: bool,
+    ) -> Result<(Metadata, Metadata)> {
+        // Set the metadata of a file or folder. Unless `replace` is set the new values
+        // are merged into the existing metadata. Returns the metadata before and after.
+        let (fpath, fname) = virtual_path.rsplit_once('/').unwrap_or(("", virtual_path));
+        let folder = self.root.get_folder_mut(fpath)?;
+        let existing = match folder.children.get_mut(fname) {
+            Some(FSObject::File(f)) => {
+                f.revision = UNSAVED;
+                &mut f.metadata
+            }
+            Some(FSObject::Folder(f)) => {
+                f._modified = true;
+                &mut f.metadata
+            }
+            None => {
+                return Err(GodataError::new(
+                    GodataErrorType::NotFound,
+                    format!("Path `{}` does not exist", virtual_path),
+                )
+                .with_code("path_not_found")
+                .with_details(serde_json::json!({"path": virtual_path})))
+            }
+        };
+        let before = existing.clone();
+        if replace {
+            *existing = metadata;
+        } else {
+            existing.extend(metadata);
+        }
+        let after = existing.clone();
+        folder._modified = true;
+        self._modified = true;
+        self.save()?;
+        Ok((before, after))
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn set_real_path(&mut self, virtual_path: &str, real_path: PathBuf) -> Result<()> {
+        // Point an existing file at a new real path, keeping its metadata
+        let (fpath, fname) = virtual_path.rsplit_once('/').unwrap_or(("", virtual_path));
+        let folder = self.root.get_folder_mut(fpath)?;
+        match folder.children.get_mut(fname) {
+            Some(FSObject::File(f)) => {
+                f.real_path = real_path;
+                f.revision = UNSAVED;
+                folder._modified = true;
+            }
+            Some(FSObject::Folder(_)) => {
+                return Err(GodataError::new(
+                    GodataErrorType::InvalidPath,
+                    format!("Path `{}` is a folder", virtual_path),
+                )
+                .with_code("path_is_folder")
+                .with_details(serde_json::json!({"path": virtual_path})))
+            }
+            None => {
+                return Err(GodataError::new(
+                    GodataErrorType::NotFound,
+                    format!("Path `{}` does not exist", virtual_path),
+                )
+                .with_code("path_not_found")
+                .with_details(serde_json::json!({"path": virtual_path})))
+            }
+        }
+        self._modified = true;
+        self.save()
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn create_snapshot(&mut self, name: &str) -> Result<SnapshotInfo> {
+        if name.is_empty() {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidArgument,
+                "Snapshot name cannot be empty".to_string(),
+            )
+            .with_code("invalid_snapshot_name"));
+        }
+        let index = self.db.open_tree(SNAPSHOT_INDEX)?;
+        if index.contains_key(name)? {
+            return Err(GodataError::new(
+                GodataErrorType::AlreadyExists,
+                format!("Snapshot `{}` already exists", name),
+            )
+            .with_code("snapshot_exists")
+            .with_details(serde_json::json!({"snapshot": name})));
+        }
+        self.save()?;
+        let snapshot_tree = self.db.open_tree(format!("{}{}", SNAPSHOT_PREFIX, name))?;
+        snapshot_tree.clear()?;
+        let mut batch = Batch::default();
+        for item in self.db.iter() {
+            let (key, value) = item?;
+            batch.insert(key, value);
+        }
+        snapshot_tree.apply_batch(batch)?;
+
+        let info = SnapshotInfo {
+            name: name.to_string(),
+            created: Utc::now().to_rfc3339(),
+            files: self.walk().len(),
+        };
+        index.insert(name, encode(&info)?)?;
+        tracing::info!("Created snapshot `{}` for project `{}`", name, self._name);
+        Ok(info)
+    }
+
+    pub(crate) fn list_snapshots(&self) -> Result<Vec<SnapshotInfo>> {
+        let index = self.db.open_tree(SNAPSHOT_INDEX)?;
+        let mut snapshots = Vec::new();
+        for item in index.iter() {
+            let (_, value) = item?;
+            snapshots.push(decode::<SnapshotInfo>(&value)?);
+        }
+        snapshots.sort_by(|a, b| a.created.cmp(&b.created));
+        Ok(snapshots)
+    }
+
     #[instrument(skip(self))]
+    pub(crate) fn delete_snapshot(&mut self, name: &str) -> Result<()> {
+        let index = self.db.open_tree(SNAPSHOT_INDEX)?;
+        if index.remove(name)?.is_none() {
+            return Err(snapshot_not_found(name));
+        }
+        self.db.drop_tree(format!("{}{}", SNAPSHOT_PREFIX, name))?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn open_snapshot(&self, name: &str) -> Result<Snapshot> {
+        let index = self.db.open_tree(SNAPSHOT_INDEX)?;
+        if !index.contains_key(name)? {
+            return Err(snapshot_not_found(name));
+        }
+        let snapshot_tree = self.db.open_tree(format!("{}{}", SNAPSHOT_PREFIX, name))?;
+        let mut root = Folder::from_tree(&snapshot_tree, "root".to_string())?;
+        if needs_migration(&snapshot_tree)? {
+            root.migrate_metadata();
+        }
+        Ok(Snapshot { root })
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn restore_snapshot(&mut self, name: &str) -> Result<()> {
+        // Replace the live tree with the contents of a snapshot. Only the tree is
+        // restored, files that were deleted from disk since the snapshot stay deleted.
+        let snapshot = self.open_snapshot(name)?;
+        let snapshot_tree = self.db.open_tree(format!("{}{}", SNAPSHOT_PREFIX, name))?;
+        let mut batch = Batch::default();
+        for key in self.db.iter().keys() {
+            batch.remove(key?);
+        }
+        for item in snapshot_tree.iter() {
+            let (key, value) = item?;
+            batch.insert(key, value);
+        }
+        // Restored entries keep their revisions, but the counter never goes backwards
+        batch.insert(REVISION_KEY, encode(&self.revision)?);
+        self.db.apply_batch(batch)?;
+        self.root = snapshot.root;
+        self.dropped.clear();
+        self._modified = false;
+        tracing::info!("Restored project `{}` to snapshot `{}`", self._name, name);
+        Ok(())
+    }
+
+    pub(crate) fn leases(&self) -> Result<Tree> {
+        Ok(self.db.open_tree(LEASE_TREE)?)
+    }
+
+    pub(crate) fn hooks(&self) -> Result<Tree> {
+        Ok(self.db.open_tree(HOOK_TREE)?)
+    }
+
+    pub(crate) fn audit(&self) -> Result<Tree> {
+        Ok(self.db.open_tree(AUDIT_TREE)?)
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        self.save()?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    pub(crate) fn begin(&mut self) {
+        self.savepoints.push(Savepoint {
+            root: self.root.clone(),
+            dropped: self.dropped.len(),
+            modified: self._modified,
+        });
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn commit(&mut self) -> Result<()> {
+        self.commit_with(None)
+    }
+
+    // Commits the outermost transaction and writes `journal` to its own tree in the same
+    // database transaction, so the entries are saved if and only if the changes are
+    pub(crate) fn commit_with(&mut self, journal: Option<(&Tree, Batch)>) -> Result<()> {
+        let savepoint = match self.savepoints.pop() {
+            Some(savepoint) => savepoint,
+            None => {
+                tracing::error!("Tried to commit without an open transaction");
+                return Err(GodataError::new(
+                    GodataErrorType::InternalError,
+                    "No transaction is open".to_string(),
+                )
+                .with_code("no_transaction"));
+            }
+        };
+        if !self.savepoints.is_empty() {
+            if journal.is_some() {
+                self.restore(savepoint);
+                return Err(GodataError::new(
+                    GodataErrorType::InternalError,
+                    "Only the outermost transaction can be committed with a journal".to_string(),
+                )
+                .with_code("nested_transaction"));
+            }
+            return Ok(());
+        }
+        if let Err(e) = self.save_with(journal) {
+            tracing::error!(
+                "Failed to commit transaction for project `{}`: {}",
+                self._name,
+                e
+            );
+            self.restore(savepoint);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn abort(&mut self) {
+        match self.savepoints.pop() {
+            Some(savepoint) => self.restore(savepoint),
+            None => tracing::warn!("Tried to abort without an open transaction"),
+        }
+    }
+
+    fn restore(&mut self, savepoint: Savepoint) {
+        tracing::info!("Rolling back changes to project `{}`", self._name);
+        self.root = savepoint.root;
+        self.dropped.truncate(savepoint.dropped);
+        self._modified = savepoint.modified;
+    }
+
+    fn transaction<T>(&mut self, f: impl FnOnce(&mut FileSystem) -> Result<T>) -> Result<T> {
+        self.begin();
+        match f(self) {
+            Ok(value) => {
+                self.commit()?;
+                Ok(value)
+            }
+            Err(e) => {
+                self.abort();
+                Err(e)
+            }
+        }
+    }
+
     fn save(&mut self) -> Result<()> {
-        // Write the root folder to the database
+        self.save_with(None)
+    }
+
+    #[instrument(skip(self, journal))]
+    fn save_with(&mut self, journal: Option<(&Tree, Batch)>) -> Result<()> {
+        // Write the tree to the database, unless a transaction is open
+        if !self.savepoints.is_empty() {
+            return Ok(());
+        }
         tracing::info!("Saving filesystem for project `{}`", self._name);
         let mut batch = Batch::default();
-        self.root.write_to_tree(&mut batch)?;
-        self.db.apply_batch(batch)?;
+        if !self.dropped.is_empty() {
+            // A moved folder is dropped from its old location but is still in the tree
+            let mut live = Vec::new();
+            self.root.collect_uuids(&mut live);
+            let live: HashSet<String> = live.into_iter().collect();
+            for uuid in self.dropped.iter().filter(|uuid| !live.contains(*uuid)) {
+                batch.remove(uuid.as_bytes());
+            }
+        }
+        let revision = match self._modified {
+            true => self.revision + 1,
+            false => self.revision,
+        };
+        self.root.write_to_tree(&mut batch, revision)?;
+        batch.insert(METADATA_VERSION_KEY, encode(&METADATA_VERSION)?);
+        batch.insert(REVISION_KEY, encode(&revision)?);
+        match journal {
+            None => self.db.apply_batch(batch)?,
+            Some((journal, entries)) => {
+                let db: &Tree = &self.db;
+                (db, journal)
+                    .transaction(|(db, journal)| {
+                        db.apply_batch(&batch)?;
+                        journal.apply_batch(&entries)?;
+                        Ok::<(), ConflictableTransactionError<()>>(())
+                    })
+                    .map_err(|e| match e {
+                        TransactionError::Storage(e) => GodataError::from(e),
+                        TransactionError::Abort(()) => GodataError::new(
+                            GodataErrorType::InternalError,
+                            "Saving the project was aborted".to_string(),
+                        ),
+                    })?;
+            }
+        }
+        self.revision = revision;
+        self.dropped.clear();
         self.root.reset();
         self._modified = false;
         Ok(())
@@ -440,12 +977,73 @@ impl FileSystem {
     }
 }
 
+impl Snapshot {
+    pub(crate) fn list(
+        &self,
+        virtual_path: Option<String>,
+    ) -> Result<HashMap<String, Vec<String>>> {
+        self.root.list(virtual_path)
+    }
+
+    pub(crate) fn get(&self, virtual_path: &str) -> Result<&File> {
+        self.root.get_file(virtual_path)
+    }
+
+    pub(crate) fn revision(&self, virtual_path: &str) -> Result<u64> {
+        self.root.revision(virtual_path)
+    }
+
+    pub(crate) fn walk(&self) -> Vec<(String, &File)> {
+        let mut files = Vec::new();
+        self.root.walk("", &mut files);
+        files
+    }
+}
+
+fn snapshot_not_found(name: &str) -> GodataError {
+    GodataError::new(
+        GodataErrorType::NotFound,
+        format!("Snapshot `{}` does not exist", name),
+    )
+    .with_code("snapshot_not_found")
+    .with_details(serde_json::json!({"snapshot": name}))
+}
+
+fn needs_migration(tree: &Tree) -> Result<bool> {
+    let version = match tree.get(METADATA_VERSION_KEY)? {
+        Some(bytes) => decode::<u32>(&bytes)?,
+        None => 0,
+    };
+    Ok(version < METADATA_VERSION)
+}
+
+pub(crate) fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    into_writer(value, &mut bytes).map_err(|e| {
+        GodataError::new(
+            GodataErrorType::IOError,
+            format!("Failed to serialize value: {}", e),
+        )
+        .with_code("database_error")
+    })?;
+    Ok(bytes)
+}
+
+pub(crate) fn decode<T: for<'de> Deserialize<'de>>(bytes: &[u8]) -> Result<T> {
+    from_reader(bytes).map_err(|e| {
+        GodataError::new(
+            GodataErrorType::InternalError,
+            format!("Failed to deserialize value: {}", e),
+        )
+        .with_code("storage_corrupted")
+    })
+}
+
 impl Drop for FileSystem {
     #[instrument(skip(self))]
     fn drop(&mut self) {
-        let res = self.save();
-        if res.is_err() {
-            tracing::error!("Failed to save filesystem on drop: {}", res.err().unwrap());
+        if let Err(e) = self.save() {
+            tracing::error!("Failed to save filesystem on drop: {}", e);
         }
     }
 }
@@ -456,6 +1054,7 @@ impl Folder {
             name,
             children: HashMap::new(),
             metadata: HashMap::new(),
+            revision: UNSAVED,
             _uuid: Uuid::new_v4().to_string(),
             _modified: true,
         }
@@ -471,28 +1070,38 @@ impl Folder {
         }
     }
     #[instrument(skip(db))]
-    fn from_tree(db: &Db, uuid: String) -> Result<Folder> {
-        let folder_info = db.get(uuid.as_bytes());
-        if folder_info.is_err() {
-            tracing::error!(
-                "Failed to read folder from database: {}",
-                folder_info.err().unwrap()
-            );
-            return Err(GodataError::new(
-                GodataErrorType::IOError,
-                "Failed to read folder from database".to_string(),
-            ));
-        }
-        let folder_info = folder_info.unwrap();
-        if folder_info.is_none() {
-            tracing::error!("Folder not found in database");
-            return Err(GodataError::new(
-                GodataErrorType::NotFound,
-                "Folder not found".to_string(),
-            ));
-        }
+    fn from_tree(db: &Tree, uuid: String) -> Result<Folder> {
+        let folder_info = match db.get(uuid.as_bytes()) {
+            Ok(Some(folder_info)) => folder_info,
+            Ok(None) => {
+                tracing::error!("Folder not found in database");
+                return Err(GodataError::new(
+                    GodataErrorType::NotFound,
+                    "Folder not found".to_string(),
+                )
+                .with_code("path_not_found"));
+            }
+            Err(e) => {
+                tracing::error!("Failed to read folder from database: {}", e);
+                return Err(GodataError::new(
+                    GodataErrorType::IOError,
+                    "Failed to read folder from database".to_string(),
+                )
+                .with_code("database_error"));
+            }
+        };
 
-        let db_folder: DbFolder = from_reader(folder_info.unwrap().as_ref()).unwrap();
+        let db_folder: DbFolder = match from_reader(folder_info.as_ref()) {
+            Ok(db_folder) => db_folder,
+            Err(e) => {
+                tracing::error!("Failed to decode folder `{}`: {}", uuid, e);
+                return Err(GodataError::new(
+                    GodataErrorType::InternalError,
+                    "Failed to decode folder".to_string(),
+                )
+                .with_code("storage_corrupted"));
+            }
+        };
         let mut children = HashMap::new();
         for fuuid in db_folder.folders_uuids {
             let folder = Folder::from_tree(db, fuuid)?;
@@ -507,34 +1116,65 @@ impl Folder {
             name: db_folder.name,
             children,
             metadata: db_folder.metadata,
+            revision: db_folder.revision,
             _uuid: uuid,
             _modified: false,
         })
     }
 
-    fn write_to_tree(&mut self, batch: &mut Batch) -> Result<()> {
-        // Write the folder and all of its children to the database
+    fn migrate_metadata(&mut self) {
+        // Every folder is marked as modified so the migrated values are written back
+        metadata::migrate(&mut self.metadata);
+        self._modified = true;
+        for (_, child) in self.children.iter_mut() {
+            match child {
+                FSObject::File(f) => metadata::migrate(&mut f.metadata),
+                FSObject::Folder(f) => f.migrate_metadata(),
+            }
+        }
+    }
+
+    fn touch(&mut self) {
+        // Mark this folder and every folder below it as modified
+        self._modified = true;
+        for (_, child) in self.children.iter_mut() {
+            if let FSObject::Folder(f) = child {
+                f.touch();
+            }
+        }
+    }
+
+    fn write_to_tree(&mut self, batch: &mut Batch, revision: u64) -> Result<()> {
+        // Write the folder and all of its children to the database. Modified folders
+        // and their changed files are written at the given revision.
         if self._modified {
+            self.revision = revision;
+            for (_, child) in self.children.iter_mut() {
+                if let FSObject::File(f) = child {
+                    if f.revision == UNSAVED {
+                        f.revision = revision;
+                    }
+                }
+            }
             self.write_to_db(batch)?;
         }
         for (_, child) in self.children.iter_mut() {
             match child {
                 FSObject::File(_) => (),
-                FSObject::Folder(f) => f.write_to_tree(batch)?,
+                FSObject::Folder(f) => f.write_to_tree(batch, revision)?,
             }
         }
         Ok(())
     }
 
-    fn drop_from_tree(&mut self, batch: &mut Batch) -> Result<()> {
-        // Remove the folder and all of its children from the database
-        batch.remove(self._uuid.as_bytes());
-        for (_, child) in self.children.iter_mut() {
+    fn collect_uuids(&self, uuids: &mut Vec<String>) {
+        // The database keys of this folder and all of the folders below it
+        uuids.push(self._uuid.clone());
+        for (_, child) in self.children.iter() {
             if let FSObject::Folder(f) = child {
-                f.drop_from_tree(batch)?;
+                f.collect_uuids(uuids);
             }
         }
-        Ok(())
     }
 
     #[instrument(skip(self, files))]
@@ -563,16 +1203,16 @@ impl Folder {
                 match item {
                     FSObject::File(_) => {
                         tracing::info!("Trying to insert into a file");
-                        Err(GodataError::new(
-                            GodataErrorType::InvalidPath,
-                            "Path is a file".into(),
-                        ))
+                        Err(
+                            GodataError::new(GodataErrorType::InvalidPath, "Path is a file".into())
+                                .with_code("path_is_file"),
+                        )
                     } // We have a file with this name, and nothing is left in the path
                     FSObject::Folder(f) => f._insert_many(files, path_parts), // We have a folder with this name, and we need to check the rest of the path
                 }
             }
             None => {
-                let mut folder = Folder::new(path_part.unwrap().to_string());
+                let mut folder = Folder::new(path_part.unwrap_or_default().to_string());
                 folder._insert_many(files, path_parts)?;
                 self.children
                     .insert(folder.name.clone(), FSObject::Folder(folder));
@@ -594,37 +1234,34 @@ impl Folder {
         Ok(())
     }
 
-    fn to_db_folder(&self) -> DbFolder {
+    fn to_db_folder(&self) -> Result<DbFolder> {
         let mut folders_uuids = Vec::new();
         let mut files = Vec::new();
         for (_, child) in self.children.iter() {
             match child {
-                FSObject::File(f) => files.push(f.to_db_file()),
+                FSObject::File(f) => files.push(f.to_db_file()?),
                 FSObject::Folder(f) => folders_uuids.push(f._uuid.clone()),
             }
         }
-        DbFolder {
+        Ok(DbFolder {
             name: self.name.clone(),
             folders_uuids,
             files,
             metadata: self.metadata.clone(),
-        }
+            revision: self.revision,
+        })
     }
 
     fn write_to_db(&mut self, batch: &mut Batch) -> Result<()> {
-        let db_folder = self.to_db_folder();
+        let db_folder = self.to_db_folder()?;
         let mut bytes = Vec::new();
-        let write_result = into_writer(&db_folder, &mut bytes);
-        if write_result.is_err() {
-            tracing::error!(
-                "Failed to serialize folder `{}` to bytes: {}",
-                self.name,
-                write_result.err().unwrap()
-            );
+        if let Err(e) = into_writer(&db_folder, &mut bytes) {
+            tracing::error!("Failed to serialize folder `{}` to bytes: {}", self.name, e);
             return Err(GodataError::new(
                 GodataErrorType::IOError,
                 "Failed to serialize folder".to_string(),
-            ));
+            )
+            .with_code("database_error"));
         }
         batch.insert(self._uuid.as_bytes(), bytes);
         Ok(())
@@ -670,13 +1307,125 @@ impl Folder {
         // split up the path
         let path_parts = virtual_path.split('/');
         let path: Vec<&str> = path_parts.collect();
-        let result = self._get(&path);
-        if result.is_err() {
-            let mut err = result.err().unwrap();
+        self._get(&path).map_err(|mut err| {
             err.message = format!("Failed to get path `{}`: {}", virtual_path, err.message);
-            return Err(err);
+            if err.details.is_none() {
+                err.details = Some(serde_json::json!({ "path": virtual_path }));
+            }
+            err
+        })
+    }
+
+    fn list(&self, virtual_path: Option<String>) -> Result<HashMap<String, Vec<String>>> {
+        let folder = match virtual_path {
+            Some(path) => {
+                let f_ = self.get(&path)?;
+                match f_ {
+                    FSObject::File(_) => {
+                        tracing::info!("Path is a file!");
+                        return Err(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            format!("Path `{}` is a file", path),
+                        )
+                        .with_code("path_is_file"));
+                    }
+                    FSObject::Folder(f) => f,
+                }
+            }
+            None => self,
+        };
+        let mut files = Vec::new();
+        let mut folders = Vec::new();
+
+        for (name, child) in folder.children.iter() {
+            match child {
+                FSObject::File(_) => files.push(name.clone()),
+                FSObject::Folder(_) => folders.push(name.clone()),
+            }
+        }
+        let mut children = HashMap::new();
+        children.insert("folders".to_string(), folders);
+        children.insert("files".to_string(), files);
+        Ok(children)
+    }
+
+    fn revision(&self, virtual_path: &str) -> Result<u64> {
+        // The revision of a file or folder. An empty path refers to this folder.
+        if virtual_path.is_empty() {
+            return Ok(self.revision);
+        }
+        Ok(self.get(virtual_path)?.revision())
+    }
+
+    fn get_file(&self, virtual_path: &str) -> Result<&File> {
+        let file = self.get(virtual_path)?;
+        match file {
+            FSObject::Folder(_) => {
+                tracing::info!("Path is a folder!");
+                Err(
+                    GodataError::new(GodataErrorType::InvalidPath, "Path is a folder".into())
+                        .with_code("path_is_folder"),
+                )
+            }
+            FSObject::File(f) => Ok(f),
+        }
+    }
+
+    fn get_folder_mut(&mut self, virtual_path: &str) -> Result<&mut Folder> {
+        // Get a mutable reference to a folder. An empty path refers to this folder.
+        if virtual_path.is_empty() {
+            return Ok(self);
+        }
+        let mut folder = self;
+        for part in virtual_path.split('/') {
+            let name = folder.name.clone();
+            folder = match folder.children.get_mut(part) {
+                Some(FSObject::Folder(f)) => f,
+                Some(FSObject::File(_)) => {
+                    return Err(GodataError::new(
+                        GodataErrorType::InvalidPath,
+                        format!("Child `{}` of folder `{}` is a file", part, name),
+                    )
+                    .with_code("path_is_file"))
+                }
+                None => {
+                    return Err(GodataError::new(
+                        GodataErrorType::NotFound,
+                        format!("Child `{}` does not exist in folder `{}`", part, name),
+                    )
+                    .with_code("path_not_found"))
+                }
+            };
+        }
+        Ok(folder)
+    }
+
+    fn walk<'a>(&'a self, prefix: &str, files: &mut Vec<(String, &'a File)>) {
+        for (name, child) in self.children.iter() {
+            let path = if prefix.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            match child {
+                FSObject::File(f) => files.push((path, f)),
+                FSObject::Folder(f) => f.walk(&path, files),
+            }
+        }
+    }
+
+    fn walk_folders<'a>(&'a self, prefix: &str, folders: &mut Vec<(String, &'a Metadata)>) {
+        for (name, child) in self.children.iter() {
+            if let FSObject::Folder(f) = child {
+                let path = if prefix.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}/{}", prefix, name)
+                };
+                folders.push((path.clone(), &f.metadata));
+                f.walk_folders(&path, folders);
+            }
         }
-        result
     }
 
     fn _get(&self, path_parts: &[&str]) -> Result<&FSObject> {
@@ -684,60 +1433,57 @@ impl Folder {
         // If path is this folder's name, return it
         // If path is a subfolder, return it from the subfolder
 
-        let path_part = path_parts.first();
-        let child = match path_part {
+        let path_part = match path_parts.first() {
             None => {
                 tracing::error!("Path part is none!");
                 return Err(GodataError::new(
                     GodataErrorType::InternalError,
                     "Invalid path part".to_string(),
-                ));
+                )
+                .with_code("invalid_path"));
             }
-            Some(&part) => self.children.get(part),
+            Some(&part) => part,
         };
 
-        if child.is_none() {
-            let msg = format!(
-                "Child `{}` does not exist in folder `{}`",
-                path_part.unwrap(),
-                self.name
-            );
-            tracing::info!(msg);
-            Err(GodataError::new(GodataErrorType::NotFound, msg))
-        } else if path_parts.len() == 1 {
-            return Ok(child.unwrap());
+        let child = match self.children.get(path_part) {
+            None => {
+                let msg = format!(
+                    "Child `{}` does not exist in folder `{}`",
+                    path_part, self.name
+                );
+                tracing::info!(msg);
+                return Err(
+                    GodataError::new(GodataErrorType::NotFound, msg).with_code("path_not_found")
+                );
+            }
+            Some(child) => child,
+        };
+        if path_parts.len() == 1 {
+            Ok(child)
         } else {
-            match child.unwrap() {
+            match child {
                 FSObject::File(_) => {
-                    let msg = format!(
-                        "Child `{}` of folder `{}` is a file",
-                        path_part.unwrap(),
-                        self.name
-                    );
+                    let msg = format!("Child `{}` of folder `{}` is a file", path_part, self.name);
                     tracing::info!(msg);
-                    return Err(GodataError::new(GodataErrorType::NotFound, msg));
-                }
-                FSObject::Folder(f) => {
-                    return f._get(&path_parts[1..]);
+                    Err(GodataError::new(GodataErrorType::NotFound, msg).with_code("path_is_file"))
                 }
+                FSObject::Folder(f) => f._get(&path_parts[1..]),
             }
         }
     }
 
     fn search_files(&self, pattern: &regex::Regex) -> Option<Vec<&File>> {
-        let file_matches = self.children.values().filter_map(|child| {
-            match child {
-                FSObject::Folder(_) => return None,
-                FSObject::File(f) => {
-                    if pattern.is_match(&f.name) {
-                        return Some(f);
-                    };
-                    return None;
-                }
-            };
+        let file_matches = self.children.values().filter_map(|child| match child {
+            FSObject::Folder(_) => None,
+            FSObject::File(f) => {
+                if pattern.is_match(&f.name) {
+                    return Some(f);
+                };
+                None
+            }
         });
         let results: Vec<&File> = file_matches.collect();
-        if results.len() == 0 {
+        if results.is_empty() {
             return None;
         }
         Some(results)
@@ -749,27 +1495,25 @@ impl Folder {
         fs_object: FSObject,
         virtual_path: &str,
         overwrite: bool,
-    ) -> Result<Option<Vec<File>>> {
+    ) -> Result<Option<FSObject>> {
         // Insert a file or folder into the folder.
         // If path is this folder's name, insert it here
         // If path is a subfolder, insert it into the subfolder
 
         // split up the path
         let mut path_parts = virtual_path.split('/');
-        if virtual_path == "" {
+        if virtual_path.is_empty() {
             // go to the end of the iterator
             _ = path_parts.next();
         }
-        let insert_result = self._insert(fs_object, path_parts, overwrite);
-        if insert_result.is_err() {
-            let mut err = insert_result.err().unwrap();
-            err.message = format!(
-                "Failed to insert at path `{}` {}",
-                virtual_path, err.message
-            );
-            return Err(err);
-        }
-        insert_result
+        self._insert(fs_object, path_parts, overwrite)
+            .map_err(|mut err| {
+                err.message = format!(
+                    "Failed to insert at path `{}` {}",
+                    virtual_path, err.message
+                );
+                err
+            })
     }
 
     fn _insert(
@@ -777,53 +1521,38 @@ impl Folder {
         fs_object: FSObject,
         mut path_parts: std::str::Split<char>,
         overwrite: bool,
-    ) -> Result<Option<Vec<File>>> {
+    ) -> Result<Option<FSObject>> {
         // Insert a file or folder into the folder.
         // If path is this folder's name, insert it here
         // If path is a subfolder, insert it into the subfolder
 
         // split up the path
-        let path_part = path_parts.next();
-        let child = match path_part {
+        let path_part = match path_parts.next() {
             None => {
                 //We're at the end, try to insert it here
-                if self.children.contains_key(fs_object.get_name()) {
-                    if !overwrite {
-                        tracing::info!("Path already exists");
-                        return Err(GodataError::new(
-                            GodataErrorType::AlreadyExists,
-                            "Something already exists at that path!".to_string(),
-                        ));
-                    } else {
-                        let previous = self.children.remove(fs_object.get_name()).unwrap();
-                        self.children
-                            .insert(fs_object.get_name().to_string(), fs_object);
-                        self._modified = true;
-                        let output = match previous {
-                            FSObject::File(f) => Some(vec![f]),
-                            FSObject::Folder(f) => {
-                                let files = drain(f);
-                                Some(files)
-                            }
-                        };
-                        return Ok(output);
-                    }
-                } else {
-                    self.children
-                        .insert(fs_object.get_name().to_string(), fs_object);
-                    self._modified = true;
-                    return Ok(None);
+                if self.children.contains_key(fs_object.get_name()) && !overwrite {
+                    tracing::info!("Path already exists");
+                    return Err(GodataError::new(
+                        GodataErrorType::AlreadyExists,
+                        "Something already exists at that path!".to_string(),
+                    )
+                    .with_code("path_exists"));
                 }
+                let previous = self
+                    .children
+                    .insert(fs_object.get_name().to_string(), fs_object);
+                self._modified = true;
+                return Ok(previous);
             }
-            Some(part) => self.children.get_mut(part),
+            Some(part) => part,
         };
 
-        match child {
+        match self.children.get_mut(path_part) {
             None => {
                 // child doesn't exist, create it
-                tracing::info!("Creating new folder `{}`", path_part.unwrap());
-                let mut folder = Folder::new(path_part.unwrap().to_string());
-                folder._insert(fs_object, path_parts, overwrite).unwrap();
+                tracing::info!("Creating new folder `{}`", path_part);
+                let mut folder = Folder::new(path_part.to_string());
+                folder._insert(fs_object, path_parts, overwrite)?;
                 self.children
                     .insert(folder.name.clone(), FSObject::Folder(folder));
                 self._modified = true;
@@ -832,13 +1561,11 @@ impl Folder {
             Some(f) => {
                 match f {
                     FSObject::File(_) => {
-                        let msg = format!(
-                            "Child `{}` of folder `{}` is a file",
-                            path_part.unwrap(),
-                            self.name
-                        );
+                        let msg =
+                            format!("Child `{}` of folder `{}` is a file", path_part, self.name);
                         tracing::info!(msg);
-                        Err(GodataError::new(GodataErrorType::AlreadyExists, msg))
+                        Err(GodataError::new(GodataErrorType::AlreadyExists, msg)
+                            .with_code("path_is_file"))
                     } // We have a file with this name, and nothing is left in the path
                     FSObject::Folder(f) => f._insert(fs_object, path_parts, overwrite), // We have a folder with this name, and we need to check the rest of the path
                 }
@@ -855,19 +1582,17 @@ impl Folder {
 
         // split up the path
         let path: Vec<&str> = virtual_path.split('/').collect();
-        if path.len() == 0 {
+        if path.is_empty() {
             return Err(GodataError::new(
                 GodataErrorType::InvalidPath,
                 "Root folder cannot be removed!".to_string(),
-            ));
+            )
+            .with_code("cannot_remove_root"));
         }
-        let delete_result = self._delete(&path);
-        if delete_result.is_err() {
-            let mut err = delete_result.err().unwrap();
+        self._delete(&path).map_err(|mut err| {
             err.message = format!("Failed to delete path `{}`: {}", virtual_path, err.message);
-            return Err(err);
-        }
-        Ok(delete_result.unwrap())
+            err
+        })
     }
 
     fn _delete(&mut self, path: &[&str]) -> Result<RemoveResult> {
@@ -876,53 +1601,55 @@ impl Folder {
         // If path is a subfolder, delete it from the subfolder
 
         // split up the path
-        let path_part = path.first();
-        if path_part.is_none() {
-            tracing::error!("Path part is none!");
-            return Err(GodataError::new(
-                GodataErrorType::InternalError,
-                "Unable to delete path".to_string(),
-            ));
-        }
-        let path_part = path_part.unwrap();
-        if !self.children.contains_key(*path_part) {
-            let msg = format!(
-                "Child `{}` does not exist in folder `{}`",
-                path_part, self.name
-            );
-            tracing::info!(msg);
-            return Err(GodataError::new(GodataErrorType::NotFound, msg));
-        }
-        if path.len() == 1 {
-            self._modified = true;
-            if self.children.len() == 1 {
-                return Ok(RemoveResult::IsEmpty);
+        let path_part = match path.first() {
+            Some(path_part) => path_part,
+            None => {
+                tracing::error!("Path part is none!");
+                return Err(GodataError::new(
+                    GodataErrorType::InternalError,
+                    "Unable to delete path".to_string(),
+                )
+                .with_code("internal_error"));
             }
-            return Ok(RemoveResult::Item(
-                self.children.remove(*path_part).unwrap(),
-            ));
-        }
-        match self.children.get_mut(*path_part).unwrap() {
-            FSObject::File(_) => {
-                let msg = format!("Child `{}` of folder `{}` is a file", path_part, self.name);
+        };
+        let child = match self.children.get_mut(*path_part) {
+            Some(child) => child,
+            None => {
+                let msg = format!(
+                    "Child `{}` does not exist in folder `{}`",
+                    path_part, self.name
+                );
                 tracing::info!(msg);
-                Err(GodataError::new(GodataErrorType::InvalidPath, msg))
+                return Err(
+                    GodataError::new(GodataErrorType::NotFound, msg).with_code("path_not_found")
+                );
             }
-            FSObject::Folder(f) => {
-                let rm_result = f._delete(&path[1..])?;
-                match rm_result {
-                    RemoveResult::IsEmpty => {
-                        if self.children.len() == 1 {
-                            return Ok(RemoveResult::IsEmpty);
-                        }
-                        Ok(RemoveResult::Item(
-                            self.children.remove(*path_part).unwrap(),
-                        ))
+        };
+        if path.len() > 1 {
+            match child {
+                FSObject::File(_) => {
+                    let msg = format!("Child `{}` of folder `{}` is a file", path_part, self.name);
+                    tracing::info!(msg);
+                    return Err(GodataError::new(GodataErrorType::InvalidPath, msg)
+                        .with_code("path_is_file"));
+                }
+                FSObject::Folder(f) => {
+                    if let RemoveResult::Item(item) = f._delete(&path[1..])? {
+                        return Ok(RemoveResult::Item(item));
                     }
-                    RemoveResult::Item(_) => Ok(rm_result),
                 }
             }
         }
+        // Either the path ends here or the child folder is now empty, so the child goes.
+        // If it was the only child this folder is empty too, and the parent removes it.
+        self._modified = true;
+        if self.children.len() == 1 {
+            return Ok(RemoveResult::IsEmpty);
+        }
+        Ok(self
+            .children
+            .remove(*path_part)
+            .map_or(RemoveResult::IsEmpty, RemoveResult::Item))
     }
 
     fn get_name(&self) -> &str {
@@ -936,6 +1663,8 @@ impl File {
             real_path,
             name,
             metadata: HashMap::new(),
+            revision: UNSAVED,
+            provenance: None,
             _uuid: Uuid::new_v4().to_string(),
         }
     }
@@ -943,13 +1672,15 @@ impl File {
         &self.name
     }
 
-    fn to_db_file(&self) -> DbFile {
-        DbFile {
+    fn to_db_file(&self) -> Result<DbFile> {
+        Ok(DbFile {
             name: self.name.clone(),
-            real_path: self.real_path.to_str().unwrap().to_string(),
+            real_path: path_to_string(&self.real_path)?,
             metadata: self.metadata.clone(),
+            revision: self.revision,
+            provenance: self.provenance.clone(),
             uuid: self._uuid.clone(),
-        }
+        })
     }
 
     fn from_db_file(db_file: DbFile) -> File {
@@ -957,6 +1688,8 @@ impl File {
             name: db_file.name,
             real_path: PathBuf::from(db_file.real_path),
             metadata: db_file.metadata,
+            revision: db_file.revision,
+            provenance: db_file.provenance,
             _uuid: db_file.uuid,
         }
     }
@@ -1,24 +1,64 @@
+use crate::archive::Compression;
+use crate::audit::AuditQuery;
+use crate::auth::{AuthManager, Grant};
+use crate::batch::{BatchOperation, OperationResult};
+use crate::diff::DiffSource;
 use crate::errors::{GodataError, GodataErrorType};
+use crate::events::{Delivery, Operation};
+use crate::hooks::Action;
+use crate::lineage::{self, Checksums, LineageQuery, ProvenanceRequest};
+use crate::metadata::Metadata;
 use crate::project::get_collection_names;
-use crate::project::ProjectManager;
+use crate::project::{FolderScan, ImportOptions, ImportSummary, Project, ProjectManager};
+use crate::request;
+use crate::revision::{etag, IfMatch};
+use crate::sync::{read, write};
 use warp::reply::Reply;
 use warp::{http::Response, hyper::Body};
 
+use futures_util::StreamExt;
 use serde::Serialize;
-use std::collections::HashMap;
 use std::convert::Infallible;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use tracing::instrument;
-use warp::http::StatusCode;
-use warp::reply::WithStatus;
+use warp::http::{header, StatusCode};
+use warp::sse::Event;
+
+fn project_not_found(collection: &str, project_name: &str) -> GodataError {
+    GodataError::new(
+        GodataErrorType::NotFound,
+        format!("No project named {project_name} in collection {collection}"),
+    )
+    .with_code("project_not_found")
+    .with_details(serde_json::json!({"collection": collection, "project": project_name}))
+}
+
+// Responses about a single entry carry its revision as an ETag
+fn with_etag(reply: impl Reply, revision: u64) -> Response<Body> {
+    warp::reply::with_header(reply, header::ETAG, etag(revision)).into_response()
+}
+
+// Provenance inputs are hashed while the project is only locked for reading, so linking
+// a file made from large inputs doesn't hold up every other write to the project
+fn provenance_checksums(
+    project: &RwLock<Project>,
+    links: &[(&str, &Path, Option<&ProvenanceRequest>)],
+) -> Result<Checksums, GodataError> {
+    if links.iter().all(|(_, _, provenance)| provenance.is_none()) {
+        return Ok(Checksums::default());
+    }
+    let paths = read(project)?.input_paths(links);
+    Ok(Checksums::compute(paths))
+}
 
 #[instrument(name = "handlers.get_version", level = "info")]
-pub(crate) fn get_version() -> Result<impl warp::Reply, Infallible> {
+pub(crate) async fn get_version() -> Result<Response<Body>, Infallible> {
     Ok(warp::reply::with_status(
         warp::reply::json(&env!("CARGO_PKG_VERSION").to_string()),
         StatusCode::OK,
-    ))
+    )
+    .into_response())
 }
 #[instrument(
     name = "handlers.list_collections",
@@ -27,9 +67,12 @@ pub(crate) fn get_version() -> Result<impl warp::Reply, Infallible> {
         show_hidden = %show_hidden
     )
 )]
-pub(crate) fn list_collections(show_hidden: bool) -> Result<impl warp::Reply, Infallible> {
-    let collections = get_collection_names(show_hidden);
-    Ok(warp::reply::json(&collections.unwrap()))
+pub(crate) async fn list_collections(show_hidden: bool) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || match get_collection_names(show_hidden) {
+        Ok(collections) => Ok(warp::reply::json(&collections).into_response()),
+        Err(e) => Ok(e.into_response()),
+    })
+    .await
 }
 
 #[
@@ -43,19 +86,19 @@ instrument(
     )
 )
 ]
-pub(crate) fn list_projects(
-    project_manager: Arc<Mutex<ProjectManager>>,
+pub(crate) async fn list_projects(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     show_hidden: bool,
-) -> Result<impl warp::Reply, Infallible> {
-    let projects = project_manager
-        .lock()
-        .unwrap()
-        .get_project_names(collection.clone(), show_hidden);
-    match projects {
-        Ok(project_list) => Ok(warp::reply::json(&project_list).into_response()),
-        Err(e) => Ok(e.into_response()),
-    }
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let projects = project_manager.get_project_names(collection.clone(), show_hidden);
+        match projects {
+            Ok(project_list) => Ok(warp::reply::json(&project_list).into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
 }
 
 #[instrument(
@@ -67,47 +110,37 @@ pub(crate) fn list_projects(
         collection = %collection
     )
 )]
-pub(crate) fn load_project(
-    project_manager: Arc<Mutex<ProjectManager>>,
-    collection: String,
-    project_name: String,
-) -> Result<impl warp::Reply, Infallible> {
-    // Preload a project into memory. The idea is that in typical use, we want the "load_project" command on the Python side to be effective instant,
-    // so we load the project into memory in a separate thread. By the time the user actually tries to USE the project, it should be loaded.
-    // This really only matters for large projects, but it's a nice feature to have.
-    let project_names = project_manager
-        .lock()
-        .unwrap()
-        .get_project_names(collection.clone(), true);
-    match project_names {
-        Ok(project_list) => {
-            if !project_list.contains(&project_name) {
-                tracing::error!("Tried to load project {project_name} in collection {collection}, but it does not exist.");
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(&format!(
-                        "No project named {project_name} in collection {collection}"
-                    )),
-                    StatusCode::NOT_FOUND,
-                )
-                .into_response());
+pub(crate) async fn load_project(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        // Preload a project into memory, so it's ready by the time the user actually
+        // tries to use it. This really only matters for large projects.
+        let project_names = project_manager.get_project_names(collection.clone(), true);
+        match project_names {
+            Ok(project_list) => {
+                if !project_list.contains(&project_name) {
+                    tracing::error!("Tried to load project {project_name} in collection {collection}, but it does not exist.");
+                    return Ok(project_not_found(&collection, &project_name).into_response());
+                }
+            }
+            Err(e) => {
+                tracing::error!(
+                    "Tried to load project in collection {collection}, but it does not exist."
+                );
+                return Ok(e.into_response());
             }
         }
-        Err(e) => {
-            tracing::error!(
-                "Tried to load project in collection {collection}, but it does not exist."
-            );
+        if let Err(e) = project_manager.load_project(&project_name, &collection) {
             return Ok(e.into_response());
         }
-    }
-    let message = format!("Sucessfully loaded project {collection}/{project_name}");
-    tracing::info!(message);
-    tokio::task::spawn(async move {
-        let _ = project_manager
-            .lock()
-            .unwrap()
-            .load_project(&project_name, &collection);
-    });
-    Ok(warp::reply::with_status(warp::reply::json(&message), StatusCode::OK).into_response())
+        let message = format!("Sucessfully loaded project {collection}/{project_name}");
+        tracing::info!(message);
+        Ok(warp::reply::with_status(warp::reply::json(&message), StatusCode::OK).into_response())
+    })
+    .await
 }
 
 #[instrument(
@@ -119,23 +152,23 @@ pub(crate) fn load_project(
         collection = %collection
     )
 )]
-pub(crate) fn drop_project(
-    project_manager: Arc<Mutex<ProjectManager>>,
+pub(crate) async fn drop_project(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
-) -> Result<impl warp::Reply, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .drop_project(&project_name, &collection);
-    match project {
-        Ok(_) => Ok(warp::reply::with_status(
-            warp::reply::json(&format!("Project {} dropped.", project_name)),
-            StatusCode::OK,
-        )
-        .into_response()),
-        Err(e) => Ok(e.into_response()),
-    }
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.drop_project(&project_name, &collection);
+        match project {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!("Project {} dropped.", project_name)),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
 }
 
 #[instrument(
@@ -146,31 +179,36 @@ pub(crate) fn drop_project(
         collection = %collection,
         project_name = %project_name,
         project_path = format!("{:?}", project_path),
-        show_hidden = %_show_hidden
+        show_hidden = %_show_hidden,
+        snapshot = format!("{:?}", snapshot)
     )
 )]
-pub(crate) fn list_project(
-    project_manager: Arc<Mutex<ProjectManager>>,
+pub(crate) async fn list_project(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
     project_path: Option<String>,
     _show_hidden: bool,
-) -> Result<impl warp::Reply, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    match project {
-        Ok(project) => {
-            let project = project.lock().unwrap();
-            let result = project.list(project_path);
-            match result {
-                Ok(list) => Ok(warp::reply::json(&list).into_response()),
-                Err(e) => Ok(e.into_response()),
+    snapshot: Option<String>,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        match project {
+            Ok(project) => {
+                let result = read(&project).and_then(|project| {
+                    let revision =
+                        project.revision(project_path.as_deref(), snapshot.as_deref())?;
+                    Ok((project.list(project_path, snapshot.as_deref())?, revision))
+                });
+                match result {
+                    Ok((list, revision)) => Ok(with_etag(warp::reply::json(&list), revision)),
+                    Err(e) => Ok(e.into_response()),
+                }
             }
+            Err(e) => Ok(e.into_response()),
         }
-        Err(e) => Ok(e.into_response()),
-    }
+    })
+    .await
 }
 
 #[instrument(
@@ -184,29 +222,28 @@ pub(crate) fn list_project(
         storage_location = format!("{:?}", storage_location)
     )
 )]
-pub(crate) fn create_project(
-    project_manager: Arc<Mutex<ProjectManager>>,
+pub(crate) async fn create_project(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
     force: bool,
     storage_location: Option<String>,
-) -> Result<impl warp::Reply, Infallible> {
-    let project = project_manager.lock().unwrap().create_project(
-        &project_name,
-        &collection,
-        force,
-        storage_location,
-    );
-    match project {
-        Ok(_) => Ok(warp::reply::with_status(
-            warp::reply::json(&format!(
-                "Project {project_name} created in collection {collection}"
-            )),
-            StatusCode::CREATED,
-        )
-        .into_response()),
-        Err(e) => Ok(e.into_response()),
-    }
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project =
+            { project_manager.create_project(&project_name, &collection, force, storage_location) };
+        match project {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "Project {project_name} created in collection {collection}"
+                )),
+                StatusCode::CREATED,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
 }
 
 #[instrument(
@@ -219,26 +256,26 @@ pub(crate) fn create_project(
         force = %force
     )
 )]
-pub(crate) fn delete_project(
-    project_manager: Arc<Mutex<ProjectManager>>,
+pub(crate) async fn delete_project(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
     force: bool,
-) -> Result<impl warp::Reply, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .delete_project(&project_name, &collection, force);
-    match project {
-        Ok(_) => Ok(warp::reply::with_status(
-            warp::reply::json(&format!(
-                "Project {project_name} deleted from collection {collection}"
-            )),
-            StatusCode::OK,
-        )
-        .into_response()),
-        Err(e) => Ok(e.into_response()),
-    }
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.delete_project(&project_name, &collection, force);
+        match project {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "Project {project_name} deleted from collection {collection}"
+                )),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
 }
 
 #[derive(Serialize)]
@@ -259,47 +296,68 @@ struct LinkResponse {
         force = %force
     )
 )]
-pub(crate) fn link_file(
-    project_manager: Arc<Mutex<ProjectManager>>,
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn link_file(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
     project_path: String,
     file_path: String,
-    metadata: HashMap<String, String>,
+    metadata: Metadata,
+    provenance: Option<ProvenanceRequest>,
     force: bool,
+    if_match: Option<IfMatch>,
+    lease: Option<String>,
 ) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
 
-    match project {
-        Err(e) => return Ok(e.into_response()),
-        Ok(project) => {
-            let parsed_file_path = PathBuf::from(&file_path);
-            let result =
-                project
-                    .lock()
-                    .unwrap()
-                    .add_file(&project_path, parsed_file_path, metadata, force);
-
-            match result {
-                Ok(previous_paths) => {
-                    let output: LinkResponse = LinkResponse {
-                        message: format!("File {file_path} linked to {project_path} in project {project_name} in collection {collection}"),
-                        removed: previous_paths.unwrap_or(Vec::new()),
-                    };
-
-                    return Ok(warp::reply::with_status(
-                        warp::reply::json(&output),
-                        StatusCode::CREATED,
-                    )
-                    .into_response());
+        match project {
+            Err(e) => Ok(e.into_response()),
+            Ok(project) => {
+                let parsed_file_path = PathBuf::from(&file_path);
+                let checksums = match provenance_checksums(
+                    &project,
+                    &[(&project_path, &parsed_file_path, provenance.as_ref())],
+                ) {
+                    Ok(checksums) => checksums,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                let result = write(&project).and_then(|mut project| {
+                    project.check_revision(&project_path, if_match.as_ref())?;
+                    project.leases.check(&project_path, lease.as_deref())?;
+                    let previous_paths = project.add_file(
+                        &project_path,
+                        parsed_file_path,
+                        metadata,
+                        provenance,
+                        &checksums,
+                        force,
+                    )?;
+                    Ok((previous_paths, project.revision(Some(&project_path), None)?))
+                });
+
+                match result {
+                    Ok((previous_paths, revision)) => {
+                        let output: LinkResponse = LinkResponse {
+                            message: format!("File {file_path} linked to {project_path} in project {project_name} in collection {collection}"),
+                            removed: previous_paths.unwrap_or(Vec::new()),
+                        };
+
+                        Ok(with_etag(
+                            warp::reply::with_status(
+                                warp::reply::json(&output),
+                                StatusCode::CREATED,
+                            ),
+                            revision,
+                        ))
+                    }
+                    Err(e) => Ok(e.into_response()),
                 }
-                Err(e) => Ok(e.into_response()),
             }
         }
-    }
+    })
+    .await
 }
 
 #[instrument(
@@ -314,46 +372,49 @@ pub(crate) fn link_file(
         recursive = %recursive
     )
 )]
-pub(crate) fn link_folder(
-    project_manager: Arc<Mutex<ProjectManager>>,
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn link_folder(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
     project_path: String,
     folder_path: String,
     recursive: bool,
+    if_match: Option<IfMatch>,
+    lease: Option<String>,
 ) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    match project {
-        Ok(project) => {
-            let parsed_folder_path = PathBuf::from(&folder_path);
-            let result =
-                project
-                    .lock()
-                    .unwrap()
-                    .add_folder(&project_path, parsed_folder_path, recursive);
-            match result {
-                Ok(_) => {
-                    let out = LinkResponse {
-                        message: format!("Folder {folder_path} linked to {project_path} in project {project_name} in collection {collection}"),
-                        removed: Vec::new(),
-                    };
-                    return Ok(warp::reply::with_status(
-                        warp::reply::json(&out),
-                        StatusCode::CREATED,
-                    )
-                    .into_response());
-                }
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        match project {
+            Ok(project) => {
+                let result = FolderScan::read(PathBuf::from(&folder_path), recursive).and_then(
+                    |folder| {
+                        let mut project = write(&project)?;
+                        project.check_revision(&project_path, if_match.as_ref())?;
+                        project.leases.check(&project_path, lease.as_deref())?;
+                        project.add_folder(&project_path, folder)?;
+                        project.revision(Some(&project_path), None)
+                    },
+                );
+                match result {
+                    Ok(revision) => {
+                        let out = LinkResponse {
+                            message: format!("Folder {folder_path} linked to {project_path} in project {project_name} in collection {collection}"),
+                            removed: Vec::new(),
+                        };
+                        Ok(with_etag(
+                            warp::reply::with_status(warp::reply::json(&out), StatusCode::CREATED),
+                            revision,
+                        ))
+                    }
 
-                Err(e) => {
-                    return Ok(e.into_response());
+                    Err(e) => Ok(e.into_response()),
                 }
-            };
+            }
+            Err(e) => Ok(e.into_response()),
         }
-        Err(e) => Ok(e.into_response()),
-    }
+    })
+    .await
 }
 
 #[instrument(
@@ -363,40 +424,39 @@ pub(crate) fn link_folder(
     fields(
         collection = %collection,
         project_name = %project_name,
-        project_path = %project_path
+        project_path = %project_path,
+        snapshot = format!("{:?}", snapshot)
     )
 )]
-pub(crate) fn get_file(
-    project_manager: Arc<Mutex<ProjectManager>>,
+pub(crate) async fn get_file(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
     project_path: String,
+    snapshot: Option<String>,
 ) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    if project.is_ok() {
-        let project = project.unwrap();
-        let result = project.lock().unwrap().get_file(&project_path);
-        match result {
-            Ok(file) => {
-                return Ok(
-                    warp::reply::with_status(warp::reply::json(&file), StatusCode::OK)
-                        .into_response(),
-                )
-            }
-
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
             Err(e) => return Ok(e.into_response()),
+        };
+        let result = read(&project).and_then(|project| {
+            let file = project.get_file(&project_path, snapshot.as_deref())?;
+            Ok((
+                file,
+                project.revision(Some(&project_path), snapshot.as_deref())?,
+            ))
+        });
+        match result {
+            Ok((file, revision)) => Ok(with_etag(
+                warp::reply::with_status(warp::reply::json(&file), StatusCode::OK),
+                revision,
+            )),
+            Err(e) => Ok(e.into_response()),
         }
-    }
-    Ok(warp::reply::with_status(
-        warp::reply::json(&format!(
-            "No project named {project_name} in collection {collection}"
-        )),
-        StatusCode::NOT_FOUND,
-    )
-    .into_response())
+    })
+    .await
 }
 
 #[instrument(
@@ -410,35 +470,29 @@ pub(crate) fn get_file(
         pattern = %pattern
     )
 )]
-pub(crate) fn get_files_with_pattern(
-    project_manager: Arc<Mutex<ProjectManager>>,
+pub(crate) async fn get_files_with_pattern(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
-    project_path: Option<&str>,
-    pattern: &str,
+    project_path: Option<String>,
+    pattern: String,
 ) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    if project.is_ok() {
-        let project = project.unwrap();
-        let result = project.lock().unwrap().get_files(project_path, pattern);
-        match result {
-            Ok(files) => {
-                return Ok(
-                    warp::reply::with_status(warp::reply::json(&files), StatusCode::OK)
-                        .into_response(),
-                )
-            }
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
             Err(e) => return Ok(e.into_response()),
+        };
+        let result =
+            read(&project).and_then(|project| project.get_files(project_path.as_deref(), &pattern));
+        match result {
+            Ok(files) => Ok(
+                warp::reply::with_status(warp::reply::json(&files), StatusCode::OK).into_response(),
+            ),
+            Err(e) => Ok(e.into_response()),
         }
-    }
-    Ok(GodataError::new(
-        GodataErrorType::NotFound,
-        format!("No project named {project_name} in collection {collection}"),
-    )
-    .into_response())
+    })
+    .await
 }
 
 #[instrument(
@@ -451,72 +505,54 @@ pub(crate) fn get_files_with_pattern(
         project_path = %project_path
     )
 )]
-pub(crate) fn generate_path(
-    project_manager: Arc<Mutex<ProjectManager>>,
+pub(crate) async fn generate_path(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
     project_path: String,
+    lease: Option<String>,
 ) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    if project.is_ok() {
-        let project = project.unwrap();
-        let result = project.lock().unwrap().generate_path(&project_path);
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = read(&project).and_then(|project| {
+            project.leases.check(&project_path, lease.as_deref())?;
+            project.generate_path(&project_path)
+        });
         match result {
-            Ok(path) => {
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(&path),
-                    StatusCode::OK,
-                ).into_response())
-            }
-
-            Err(e) => {
-                return Ok(e.into_response());
-            }
+            Ok(path) => Ok(
+                warp::reply::with_status(warp::reply::json(&path), StatusCode::OK).into_response(),
+            ),
+            Err(e) => Ok(e.into_response()),
         }
-    };
-
-    Ok(warp::reply::with_status(
-        warp::reply::json(&format!(
-            "No project named {project_name} in collection {collection}"
-        )),
-        StatusCode::NOT_FOUND,
-    ).into_response())
+    })
+    .await
 }
 
-pub(crate) fn path_exists(
-    project_manager: Arc<Mutex<ProjectManager>>,
+pub(crate) async fn path_exists(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
     project_path: String,
 ) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    if project.is_ok() {
-        let project = project.unwrap();
-        let result = project.lock().unwrap().exists(project_path);
-        if result {
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&true),
-                StatusCode::OK,
-            ).into_response());
-        } else {
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&false),
-                StatusCode::OK,
-            ).into_response());
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        match read(&project).map(|project| project.exists(project_path)) {
+            Ok(exists) => Ok(
+                warp::reply::with_status(warp::reply::json(&exists), StatusCode::OK)
+                    .into_response(),
+            ),
+            Err(e) => Ok(e.into_response()),
         }
-    }
-    Ok(warp::reply::with_status(
-        warp::reply::json(&format!(
-            "No project named {project_name} in collection {collection}"
-        )),
-        StatusCode::NOT_FOUND,
-    ).into_response())
+    })
+    .await
 }
 
 #[instrument(
@@ -531,49 +567,42 @@ pub(crate) fn path_exists(
         overwrite = %overwrite
     )
 )]
-pub(crate) fn move_(
-    project_manager: Arc<Mutex<ProjectManager>>,
+pub(crate) async fn move_(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
     project_path: String,
     new_project_path: String,
     overwrite: bool,
+    if_match: Option<IfMatch>,
 ) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    if project.is_ok() {
-        let project = project.unwrap();
-        let result = project
-            .lock()
-            .unwrap()
-            .move_(&project_path, &new_project_path, overwrite);
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = write(&project).and_then(|mut project| {
+            project.check_revision(&project_path, if_match.as_ref())?;
+            let removed = project.move_(&project_path, &new_project_path, overwrite)?;
+            Ok((removed, project.revision(Some(&new_project_path), None)?))
+        });
         match result {
-            Ok(v) => {
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(
-                        &LinkResponse {
-                            message: format!("File {project_path} moved to {new_project_path} in project {project_name} in collection {collection}"),
-                            removed: v.unwrap_or(Vec::new()),
-                        }
-                    ),
+            Ok((v, revision)) => Ok(with_etag(
+                warp::reply::with_status(
+                    warp::reply::json(&LinkResponse {
+                        message: format!("File {project_path} moved to {new_project_path} in project {project_name} in collection {collection}"),
+                        removed: v.unwrap_or(Vec::new()),
+                    }),
                     StatusCode::OK,
-                ).into_response())
-            }
+                ),
+                revision,
+            )),
 
-            Err(e) => {
-                return Ok(e.into_response());
-            
-            }
+            Err(e) => Ok(e.into_response()),
         }
-    }
-    Ok(warp::reply::with_status(
-        warp::reply::json(&format!(
-            "No project named {project_name} in collection {collection}"
-        )),
-        StatusCode::NOT_FOUND,
-    ).into_response())
+    })
+    .await
 }
 
 #[instrument(
@@ -586,38 +615,93 @@ pub(crate) fn move_(
         project_path = %project_path
     )
 )]
-pub(crate) fn remove_file(
-    project_manager: Arc<Mutex<ProjectManager>>,
+pub(crate) async fn remove_file(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
     project_path: String,
+    if_match: Option<IfMatch>,
 ) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    if project.is_ok() {
-        let project = project.unwrap();
-        let result = project.lock().unwrap().remove_file(&project_path);
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = write(&project).and_then(|mut project| {
+            project.check_revision(&project_path, if_match.as_ref())?;
+            project.remove_file(&project_path)
+        });
         match result {
             Ok(v) => {
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(&v),
-                    StatusCode::OK,
-                ).into_response())
+                Ok(warp::reply::with_status(warp::reply::json(&v), StatusCode::OK).into_response())
             }
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
 
-            Err(e) => {
-                return Ok(e.into_response());
+#[derive(Serialize)]
+struct BatchResponse {
+    applied: usize,
+    failed: usize,
+    results: Vec<OperationResult>,
+}
+
+#[instrument(
+    name = "handlers.apply_batch",
+    level = "info",
+    skip(project_manager, operations),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        operations = operations.len(),
+        atomic = %atomic
+    )
+)]
+pub(crate) async fn apply_batch(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    operations: Vec<BatchOperation>,
+    atomic: bool,
+    lease: Option<String>,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let links: Vec<_> = operations.iter().filter_map(BatchOperation::link).collect();
+        let checksums = match provenance_checksums(&project, &links) {
+            Ok(checksums) => checksums,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = write(&project).and_then(|mut project| {
+            project.apply_batch(operations, atomic, lease.as_deref(), &checksums)
+        });
+        match result {
+            Ok(results) => {
+                let failed = results
+                    .iter()
+                    .filter(|r| matches!(r, OperationResult::Failed { .. }))
+                    .count();
+                let output = BatchResponse {
+                    applied: results.len() - failed,
+                    failed,
+                    results,
+                };
+                Ok(
+                    warp::reply::with_status(warp::reply::json(&output), StatusCode::OK)
+                        .into_response(),
+                )
             }
+            Err(e) => Ok(e.into_response()),
         }
-    }
-    Ok(warp::reply::with_status(
-        warp::reply::json(&format!(
-            "No project named {project_name} in collection {collection}"
-        )),
-        StatusCode::NOT_FOUND,
-    ).into_response())
+    })
+    .await
 }
 
 #[instrument(
@@ -631,29 +715,28 @@ pub(crate) fn remove_file(
     )
 )
 ]
-pub(crate) fn export_project_tree(
-    project_manager: Arc<Mutex<ProjectManager>>,
+pub(crate) async fn export_project_tree(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
     output_path: String,
-) -> Result<WithStatus<warp::reply::Json>, Infallible> {
-    let result = project_manager.lock().unwrap().export_project(
-        &project_name,
-        &collection,
-        PathBuf::from(&output_path),
-    );
-    match result {
-        Ok(_) => Ok(warp::reply::with_status(
-            warp::reply::json(&format!(
-                "tree for project {project_name} in collection {collection} exported"
-            )),
-            StatusCode::OK,
-        )),
-        Err(e) => Ok(warp::reply::with_status(
-            warp::reply::json(&e.to_string()),
-            StatusCode::CONFLICT,
-        )),
-    }
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let result = {
+            project_manager.export_project(&project_name, &collection, PathBuf::from(&output_path))
+        };
+        match result {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "tree for project {project_name} in collection {collection} exported"
+                )),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
 }
 
 #[instrument(
@@ -663,33 +746,895 @@ pub(crate) fn export_project_tree(
     fields(
         collection = %collection,
         project_name = %project_name,
-        input_path = %input_path
+        input_path = %input_path,
+        options = format!("{:?}", options)
     )
 )
 ]
-pub(crate) fn import_project_tree(
-    project_manager: Arc<Mutex<ProjectManager>>,
+pub(crate) async fn import_project_tree(
+    project_manager: Arc<ProjectManager>,
     collection: String,
     project_name: String,
     input_path: String,
-) -> Result<WithStatus<warp::reply::Json>, Infallible> {
-    let storage_path = PathBuf::from(&input_path);
-    let result = project_manager.lock().unwrap().import_project(
-        &project_name,
-        &collection,
-        "local",
-        storage_path,
-    );
+    options: ImportOptions,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let storage_path = PathBuf::from(&input_path);
+        let result = {
+            project_manager.import_project(
+                &project_name,
+                &collection,
+                "local",
+                storage_path,
+                options,
+            )
+        };
+        Ok(import_reply(result, "tree", &collection))
+    })
+    .await
+}
+
+#[derive(Serialize)]
+struct ImportResponse {
+    message: String,
+    #[serde(flatten)]
+    summary: ImportSummary,
+}
+
+fn import_reply(
+    result: crate::errors::Result<ImportSummary>,
+    kind: &str,
+    collection: &str,
+) -> Response<Body> {
     match result {
-        Ok(_p) => Ok(warp::reply::with_status(
-            warp::reply::json(&format!(
-                "tree for project {project_name} in collection {collection} imported"
-            )),
+        Ok(summary) => warp::reply::with_status(
+            warp::reply::json(&ImportResponse {
+                message: format!(
+                    "{kind} for project {} in collection {collection} imported",
+                    summary.project
+                ),
+                summary,
+            }),
             StatusCode::OK,
-        )),
-        Err(e) => Ok(warp::reply::with_status(
-            warp::reply::json(&e.to_string()),
-            StatusCode::CONFLICT,
-        )),
+        )
+        .into_response(),
+        Err(e) => e.into_response(),
     }
 }
+
+#[instrument(
+    name = "handlers.export_project_archive",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        output_path = %output_path,
+        compression = format!("{:?}", compression),
+        include_external = %include_external
+    )
+)
+]
+pub(crate) async fn export_project_archive(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    output_path: String,
+    compression: Compression,
+    include_external: bool,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let result = {
+            project_manager.export_project_archive(
+                &project_name,
+                &collection,
+                PathBuf::from(&output_path),
+                compression,
+                include_external,
+            )
+        };
+        match result {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "project {project_name} in collection {collection} archived to {output_path}"
+                )),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.import_project_archive",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        input_path = %input_path,
+        storage_location = format!("{:?}", storage_location),
+        options = format!("{:?}", options)
+    )
+)
+]
+pub(crate) async fn import_project_archive(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    input_path: String,
+    storage_location: Option<String>,
+    options: ImportOptions,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let result = {
+            project_manager.import_project_archive(
+                &project_name,
+                &collection,
+                PathBuf::from(&input_path),
+                storage_location,
+                options,
+            )
+        };
+        Ok(import_reply(result, "archive", &collection))
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.export_project_manifest",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        output_path = %output_path,
+        checksums = %checksums
+    )
+)
+]
+pub(crate) async fn export_project_manifest(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    output_path: String,
+    checksums: bool,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let result = {
+            project_manager.export_project_manifest(
+                &project_name,
+                &collection,
+                PathBuf::from(&output_path),
+                checksums,
+            )
+        };
+        match result {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "manifest for project {project_name} in collection {collection} exported"
+                )),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.import_project_manifest",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        input_path = %input_path,
+        storage_location = format!("{:?}", storage_location),
+        verify = %verify,
+        options = format!("{:?}", options)
+    )
+)
+]
+pub(crate) async fn import_project_manifest(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    input_path: String,
+    storage_location: Option<String>,
+    verify: bool,
+    options: ImportOptions,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let result = {
+            project_manager.import_project_manifest(
+                &project_name,
+                &collection,
+                PathBuf::from(&input_path),
+                storage_location,
+                verify,
+                options,
+            )
+        };
+        Ok(import_reply(result, "manifest", &collection))
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.diff_project",
+    level = "info",
+    skip(project_manager, other),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        snapshot = format!("{:?}", snapshot)
+    )
+)]
+pub(crate) async fn diff_project(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    snapshot: Option<String>,
+    other: DiffSource,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let result = {
+            project_manager.diff_project(&project_name, &collection, snapshot.as_deref(), other)
+        };
+        match result {
+            Ok(diff) => Ok(
+                warp::reply::with_status(warp::reply::json(&diff), StatusCode::OK).into_response(),
+            ),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.create_snapshot",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        snapshot = %snapshot
+    )
+)]
+pub(crate) async fn create_snapshot(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    snapshot: String,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        match project {
+            Ok(project) => {
+                match write(&project).and_then(|mut project| project.create_snapshot(&snapshot)) {
+                    Ok(info) => Ok(warp::reply::with_status(
+                        warp::reply::json(&info),
+                        StatusCode::CREATED,
+                    )
+                    .into_response()),
+                    Err(e) => Ok(e.into_response()),
+                }
+            }
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.list_snapshots",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name
+    )
+)]
+pub(crate) async fn list_snapshots(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        match project {
+            Ok(project) => match read(&project).and_then(|project| project.list_snapshots()) {
+                Ok(snapshots) => Ok(warp::reply::with_status(
+                    warp::reply::json(&snapshots),
+                    StatusCode::OK,
+                )
+                .into_response()),
+                Err(e) => Ok(e.into_response()),
+            },
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.restore_snapshot",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        snapshot = %snapshot
+    )
+)]
+pub(crate) async fn restore_snapshot(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    snapshot: String,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        match project {
+            Ok(project) => {
+                match write(&project).and_then(|mut project| project.restore_snapshot(&snapshot)) {
+                    Ok(_) => Ok(warp::reply::with_status(
+                        warp::reply::json(&format!(
+                            "Project {project_name} restored to snapshot {snapshot}"
+                        )),
+                        StatusCode::OK,
+                    )
+                    .into_response()),
+                    Err(e) => Ok(e.into_response()),
+                }
+            }
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.delete_snapshot",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        snapshot = %snapshot
+    )
+)]
+pub(crate) async fn delete_snapshot(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    snapshot: String,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        match project {
+            Ok(project) => {
+                match write(&project).and_then(|mut project| project.delete_snapshot(&snapshot)) {
+                    Ok(_) => Ok(warp::reply::with_status(
+                        warp::reply::json(&format!(
+                            "Snapshot {snapshot} deleted from project {project_name}"
+                        )),
+                        StatusCode::OK,
+                    )
+                    .into_response()),
+                    Err(e) => Ok(e.into_response()),
+                }
+            }
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.acquire_lease",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path,
+        holder = %holder,
+        ttl = format!("{:?}", ttl)
+    )
+)]
+pub(crate) async fn acquire_lease(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+    holder: String,
+    ttl: Option<u64>,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = write(&project)
+            .and_then(|mut project| project.acquire_lease(&project_path, holder, ttl));
+        match result {
+            Ok(lease) => Ok(warp::reply::with_status(
+                warp::reply::json(&lease),
+                StatusCode::CREATED,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.list_leases",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name
+    )
+)]
+pub(crate) async fn list_leases(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = read(&project).map(|project| warp::reply::json(&project.leases.list()));
+        match result {
+            Ok(leases) => Ok(warp::reply::with_status(leases, StatusCode::OK).into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.renew_lease",
+    level = "info",
+    skip(project_manager, id),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        ttl = format!("{:?}", ttl)
+    )
+)]
+pub(crate) async fn renew_lease(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    id: String,
+    ttl: Option<u64>,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = write(&project).and_then(|mut project| project.renew_lease(&id, ttl));
+        match result {
+            Ok(lease) => Ok(
+                warp::reply::with_status(warp::reply::json(&lease), StatusCode::OK).into_response(),
+            ),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.release_lease",
+    level = "info",
+    skip(project_manager, id),
+    fields(
+        collection = %collection,
+        project_name = %project_name
+    )
+)]
+pub(crate) async fn release_lease(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    id: String,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = write(&project).and_then(|mut project| project.release_lease(&id));
+        match result {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!("Lease {id} released")),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.add_hook",
+    level = "info",
+    skip(project_manager, action),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        pattern = %pattern
+    )
+)]
+pub(crate) async fn add_hook(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    pattern: String,
+    operations: Option<Vec<Operation>>,
+    action: Action,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result =
+            write(&project).and_then(|mut project| project.add_hook(pattern, operations, action));
+        match result {
+            Ok(hook) => Ok(
+                warp::reply::with_status(warp::reply::json(&hook), StatusCode::CREATED)
+                    .into_response(),
+            ),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.list_hooks",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name
+    )
+)]
+pub(crate) async fn list_hooks(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = read(&project).map(|project| warp::reply::json(&project.hooks.list()));
+        match result {
+            Ok(hooks) => Ok(warp::reply::with_status(hooks, StatusCode::OK).into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.remove_hook",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        id = %id
+    )
+)]
+pub(crate) async fn remove_hook(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    id: String,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = write(&project).and_then(|mut project| project.remove_hook(&id));
+        match result {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!("Hook {id} removed")),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.list_deliveries",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        hook = format!("{:?}", hook)
+    )
+)]
+pub(crate) async fn list_deliveries(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    hook: Option<String>,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let result = project_manager.deliveries(&project_name, &collection, hook.as_deref());
+        match result {
+            Ok(deliveries) => Ok(warp::reply::with_status(
+                warp::reply::json(&deliveries),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.query_audit",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        query = format!("{:?}", query)
+    )
+)]
+pub(crate) async fn query_audit(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    query: AuditQuery,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = read(&project).and_then(|project| project.audit.query(&query));
+        match result {
+            Ok(page) => Ok(
+                warp::reply::with_status(warp::reply::json(&page), StatusCode::OK).into_response(),
+            ),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.get_lineage",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        query = format!("{:?}", query)
+    )
+)]
+pub(crate) async fn get_lineage(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    query: LineageQuery,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = read(&project).and_then(|project| project.lineage(&query));
+        match result {
+            Ok(lineage) => Ok(warp::reply::with_status(
+                warp::reply::json(&lineage),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.export_lineage_prov",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        query = format!("{:?}", query)
+    )
+)]
+pub(crate) async fn export_lineage_prov(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    query: LineageQuery,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = read(&project).and_then(|project| project.lineage(&query));
+        match result {
+            Ok(lineage) => {
+                let document = lineage::to_prov(&collection, &project_name, &lineage);
+                Ok(
+                    warp::reply::with_status(warp::reply::json(&document), StatusCode::OK)
+                        .into_response(),
+                )
+            }
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.stale_files",
+    level = "info",
+    skip(project_manager),
+    fields(collection = %collection, project_name = %project_name)
+)]
+pub(crate) async fn stale_files(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    project_path: Option<String>,
+    checksums: bool,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || {
+        let project = project_manager.load_project(&project_name, &collection);
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result =
+            read(&project).map(|project| project.staleness(project_path.as_deref(), checksums));
+        match result {
+            Ok(staleness) => Ok(warp::reply::with_status(
+                warp::reply::json(&staleness),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        }
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.watch_project",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        prefix = format!("{:?}", prefix)
+    )
+)]
+pub(crate) async fn watch_project(
+    project_manager: Arc<ProjectManager>,
+    collection: String,
+    project_name: String,
+    prefix: Option<String>,
+) -> Result<Response<Body>, Infallible> {
+    let events = match project_manager.subscribe(&project_name, &collection, prefix) {
+        Ok(events) => events,
+        Err(e) => return Ok(e.into_response()),
+    };
+    // Changes are sent as plain messages, only the rare notice of missed events has a name
+    let events = events.map(|delivery| match delivery {
+        Delivery::Event(event) => Event::default().json_data(&*event),
+        Delivery::Missed(missed) => Event::default()
+            .event("missed")
+            .json_data(serde_json::json!({ "missed": missed })),
+    });
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)).into_response())
+}
+
+#[instrument(
+    name = "handlers.create_token",
+    level = "info",
+    skip(auth_manager, grants)
+)]
+pub(crate) async fn create_token(
+    auth_manager: Arc<AuthManager>,
+    name: String,
+    grants: Vec<Grant>,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || match auth_manager.create_token(&name, grants) {
+        Ok(token) => Ok(
+            warp::reply::with_status(warp::reply::json(&token), StatusCode::CREATED)
+                .into_response(),
+        ),
+        Err(e) => Ok(e.into_response()),
+    })
+    .await
+}
+
+#[instrument(name = "handlers.list_tokens", level = "info", skip(auth_manager))]
+pub(crate) async fn list_tokens(
+    auth_manager: Arc<AuthManager>,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || match auth_manager.list_tokens() {
+        Ok(tokens) => Ok(
+            warp::reply::with_status(warp::reply::json(&tokens), StatusCode::OK).into_response(),
+        ),
+        Err(e) => Ok(e.into_response()),
+    })
+    .await
+}
+
+#[instrument(
+    name = "handlers.set_token_grants",
+    level = "info",
+    skip(auth_manager, grants)
+)]
+pub(crate) async fn set_token_grants(
+    auth_manager: Arc<AuthManager>,
+    id: String,
+    grants: Vec<Grant>,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || match auth_manager.set_grants(&id, grants) {
+        Ok(token) => {
+            Ok(warp::reply::with_status(warp::reply::json(&token), StatusCode::OK).into_response())
+        }
+        Err(e) => Ok(e.into_response()),
+    })
+    .await
+}
+
+#[instrument(name = "handlers.delete_token", level = "info", skip(auth_manager))]
+pub(crate) async fn delete_token(
+    auth_manager: Arc<AuthManager>,
+    id: String,
+) -> Result<Response<Body>, Infallible> {
+    request::blocking(move || match auth_manager.delete_token(&id) {
+        Ok(_) => Ok(warp::reply::with_status(
+            warp::reply::json(&format!("Token {id} deleted")),
+            StatusCode::OK,
+        )
+        .into_response()),
+        Err(e) => Ok(e.into_response()),
+    })
+    .await
+}
@@ -0,0 +1,393 @@
+// Hooks on project changes. A hook is set on a pattern for project paths, and runs when
+// a change event (see `events`) for a matching path comes in. By default that's when a
+// file or folder is linked or removed. A hook either POSTs the event as JSON to a URL,
+// or runs a local command with the event on its standard input and the main fields in
+// `GODATA_*` environment variables. Patterns are globs on the whole project path, where
+// `*` also matches across folders.
+//
+// Hooks never hold up the change that triggered them. Matching events are delivered in
+// the background, and a failed delivery is retried with a growing delay. Deliveries
+// are independent of each other, so a hook may see events out of order. Only so many
+// attempts run at once, the rest wait their turn, and once too many deliveries are
+// waiting new ones are dropped. Every attempt, and every dropped delivery, is written to
+// a delivery log, which keeps the most recent attempts in memory.
+//
+// Hooks are written to their own tree in the project database. A hook can run any
+// command as the user running the server, so changing hooks takes admin access and
+// hooks are never exported with the project.
+
+use chrono::{DateTime, Utc};
+use fnmatch_regex::glob_to_regex;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+use std::collections::VecDeque;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+use warp::http::{header, Method, Request, Uri};
+use warp::hyper::{Body, Client};
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use crate::events::{ChangeEvent, Operation};
+use crate::fsystem::{decode, encode};
+use crate::sync::lock;
+
+const HOOK_HEADER: &str = "x-godata-hook";
+const MAX_ATTEMPTS: u32 = 5;
+// Doubled after every failed attempt
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(30);
+// Attempts running at once, across all projects
+const MAX_RUNNING: usize = 16;
+// Deliveries waiting to run or be retried, across all projects
+const MAX_QUEUED: usize = 10000;
+// Number of attempts kept in the delivery log, across all projects
+const LOG_SIZE: usize = 1000;
+// Output of a failed command kept in the log
+const MAX_OUTPUT: usize = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub(crate) enum Action {
+    Http { url: String },
+    Command { command: Vec<String> },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct Hook {
+    pub(crate) id: String,
+    pub(crate) pattern: String,
+    pub(crate) operations: Vec<Operation>,
+    #[serde(flatten)]
+    pub(crate) action: Action,
+    pub(crate) created: DateTime<Utc>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DeliveryStatus {
+    Delivered,
+    Retrying,
+    Failed,
+}
+
+// One entry in the delivery log
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct DeliveryAttempt {
+    hook: String,
+    collection: String,
+    project: String,
+    operation: Operation,
+    project_path: String,
+    // Zero for a delivery that was dropped without being attempted
+    attempt: u32,
+    status: DeliveryStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    timestamp: DateTime<Utc>,
+}
+
+// Delivers events to hooks in the background. Shared by every project.
+#[derive(Clone)]
+pub(crate) struct HookRunner {
+    runtime: Handle,
+    client: Client<warp::hyper::client::HttpConnector>,
+    log: Arc<Mutex<VecDeque<DeliveryAttempt>>>,
+    running: Arc<Semaphore>,
+    queued: Arc<AtomicUsize>,
+}
+
+// The hooks of one project
+pub(crate) struct Hooks {
+    tree: Tree,
+    hooks: Vec<(Arc<Hook>, Regex)>,
+    runner: HookRunner,
+}
+
+impl Hooks {
+    pub(crate) fn load(tree: Tree, runner: HookRunner) -> Result<Hooks> {
+        let mut hooks = Vec::new();
+        for item in tree.iter() {
+            let (_, value) = item?;
+            let hook: Hook = decode(&value)?;
+            let pattern = glob_to_regex(&hook.pattern)?;
+            hooks.push((Arc::new(hook), pattern));
+        }
+        hooks.sort_by_key(|(hook, _)| hook.created);
+        Ok(Hooks {
+            tree,
+            hooks,
+            runner,
+        })
+    }
+
+    pub(crate) fn list(&self) -> Vec<&Hook> {
+        self.hooks.iter().map(|(hook, _)| hook.as_ref()).collect()
+    }
+
+    pub(crate) fn add(
+        &mut self,
+        pattern: String,
+        operations: Option<Vec<Operation>>,
+        action: Action,
+    ) -> Result<Hook> {
+        let regex = glob_to_regex(&pattern)?;
+        let operations = operations.unwrap_or_else(|| vec![Operation::Link, Operation::Remove]);
+        if operations.is_empty() {
+            return Err(GodataError::invalid_argument(
+                "operations",
+                "[]",
+                "A hook needs at least one operation to run on".to_string(),
+            ));
+        }
+        check_action(&action)?;
+        let hook = Hook {
+            id: Uuid::new_v4().to_string(),
+            pattern,
+            operations,
+            action,
+            created: Utc::now(),
+        };
+        self.tree.insert(hook.id.as_bytes(), encode(&hook)?)?;
+        tracing::info!("Added hook `{}` on pattern `{}`", hook.id, hook.pattern);
+        self.hooks.push((Arc::new(hook.clone()), regex));
+        Ok(hook)
+    }
+
+    pub(crate) fn remove(&mut self, id: &str) -> Result<Hook> {
+        let index = self.hooks.iter().position(|(hook, _)| hook.id == id);
+        let index = match index {
+            Some(index) => index,
+            None => {
+                return Err(GodataError::new(
+                    GodataErrorType::NotFound,
+                    format!("Hook `{}` does not exist", id),
+                )
+                .with_code("hook_not_found")
+                .with_details(serde_json::json!({ "hook": id })))
+            }
+        };
+        self.tree.remove(id.as_bytes())?;
+        let (hook, _) = self.hooks.remove(index);
+        tracing::info!("Removed hook `{}`", id);
+        Ok(hook.as_ref().clone())
+    }
+
+    // Hand the event to every hook it matches, without waiting for the deliveries
+    pub(crate) fn dispatch(&self, event: &Arc<ChangeEvent>) {
+        for (hook, pattern) in &self.hooks {
+            if hook.operations.contains(&event.operation())
+                && pattern.is_match(event.project_path())
+            {
+                self.runner.submit(hook.clone(), event.clone());
+            }
+        }
+    }
+}
+
+impl HookRunner {
+    // Must be created on the runtime the deliveries will run on
+    pub(crate) fn new() -> HookRunner {
+        HookRunner {
+            runtime: Handle::current(),
+            client: Client::new(),
+            log: Arc::new(Mutex::new(VecDeque::with_capacity(LOG_SIZE))),
+            running: Arc::new(Semaphore::new(MAX_RUNNING)),
+            queued: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // The most recent attempts for a project first, optionally only for one hook
+    pub(crate) fn deliveries(
+        &self,
+        collection: &str,
+        project: &str,
+        hook: Option<&str>,
+    ) -> Result<Vec<DeliveryAttempt>> {
+        let log = lock(&self.log)?;
+        Ok(log
+            .iter()
+            .rev()
+            .filter(|attempt| attempt.collection == collection && attempt.project == project)
+            .filter(|attempt| hook.is_none_or(|hook| attempt.hook == hook))
+            .cloned()
+            .collect())
+    }
+
+    fn submit(&self, hook: Arc<Hook>, event: Arc<ChangeEvent>) {
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= MAX_QUEUED {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            let error = format!("Dropped, {} deliveries are already waiting", MAX_QUEUED);
+            self.record(&hook, &event, 0, DeliveryStatus::Failed, Some(error));
+            return;
+        }
+        let runner = self.clone();
+        self.runtime.spawn(async move {
+            runner.deliver(hook, event).await;
+            runner.queued.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+
+    async fn deliver(&self, hook: Arc<Hook>, event: Arc<ChangeEvent>) {
+        let mut delay = RETRY_DELAY;
+        for attempt in 1..=MAX_ATTEMPTS {
+            // The semaphore is never closed. The wait for it doesn't count towards the
+            // timeout, and it's released while waiting to retry.
+            let permit = self.running.acquire().await.ok();
+            let result =
+                match tokio::time::timeout(ATTEMPT_TIMEOUT, self.attempt(&hook, &event)).await {
+                    Ok(result) => result,
+                    Err(_) => Err(format!(
+                        "Timed out after {} seconds",
+                        ATTEMPT_TIMEOUT.as_secs()
+                    )),
+                };
+            drop(permit);
+            let status = match &result {
+                Ok(()) => DeliveryStatus::Delivered,
+                Err(_) if attempt < MAX_ATTEMPTS => DeliveryStatus::Retrying,
+                Err(_) => DeliveryStatus::Failed,
+            };
+            self.record(&hook, &event, attempt, status, result.err());
+            if status != DeliveryStatus::Retrying {
+                return;
+            }
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    async fn attempt(&self, hook: &Hook, event: &ChangeEvent) -> std::result::Result<(), String> {
+        let body = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+        match &hook.action {
+            Action::Http { url } => self.post(&hook.id, url, body).await,
+            Action::Command { command } => run(command, event, body).await,
+        }
+    }
+
+    async fn post(&self, id: &str, url: &str, body: Vec<u8>) -> std::result::Result<(), String> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(HOOK_HEADER, id)
+            .body(Body::from(body))
+            .map_err(|e| e.to_string())?;
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("Responded with status {}", response.status()));
+        }
+        Ok(())
+    }
+
+    fn record(
+        &self,
+        hook: &Hook,
+        event: &ChangeEvent,
+        attempt: u32,
+        status: DeliveryStatus,
+        error: Option<String>,
+    ) {
+        match &error {
+            Some(error) => tracing::warn!(
+                "Attempt {} to run hook `{}` for path `{}` failed: {}",
+                attempt,
+                hook.id,
+                event.project_path(),
+                error
+            ),
+            None => tracing::info!("Ran hook `{}` for path `{}`", hook.id, event.project_path()),
+        }
+        let entry = DeliveryAttempt {
+            hook: hook.id.clone(),
+            collection: event.collection().to_string(),
+            project: event.project().to_string(),
+            operation: event.operation(),
+            project_path: event.project_path().to_string(),
+            attempt,
+            status,
+            error,
+            timestamp: Utc::now(),
+        };
+        // A poisoned log has already been reported, losing an entry is not worth more
+        if let Ok(mut log) = lock(&self.log) {
+            if log.len() == LOG_SIZE {
+                log.pop_front();
+            }
+            log.push_back(entry);
+        }
+    }
+}
+
+async fn run(
+    command: &[String],
+    event: &ChangeEvent,
+    body: Vec<u8>,
+) -> std::result::Result<(), String> {
+    let mut child = tokio::process::Command::new(&command[0])
+        .args(&command[1..])
+        .env("GODATA_COLLECTION", event.collection())
+        .env("GODATA_PROJECT", event.project())
+        .env("GODATA_OPERATION", event.operation().as_str())
+        .env("GODATA_PROJECT_PATH", event.project_path())
+        .env("GODATA_REAL_PATH", event.real_path().unwrap_or_default())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        // A command that times out is killed when the attempt is dropped
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to start `{}`: {}", command[0], e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The command doesn't have to read the event
+        let _ = stdin.write_all(&body).await;
+    }
+    let output = child.wait_with_output().await.map_err(|e| e.to_string())?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr = stderr.trim();
+    let stderr: String = match stderr.char_indices().nth_back(MAX_OUTPUT - 1) {
+        Some((start, _)) => stderr[start..].to_string(),
+        None => stderr.to_string(),
+    };
+    Err(format!("Command failed with {}: {}", output.status, stderr))
+}
+
+fn check_action(action: &Action) -> Result<()> {
+    match action {
+        Action::Http { url } => {
+            let uri = url.parse::<Uri>().ok();
+            if uri.as_ref().and_then(|uri| uri.scheme_str()) != Some("http")
+                || uri.as_ref().and_then(|uri| uri.host()).is_none()
+            {
+                return Err(GodataError::invalid_argument(
+                    "url",
+                    url,
+                    format!("Invalid hook URL `{}`, expected an http:// URL", url),
+                ));
+            }
+        }
+        Action::Command { command } => {
+            if command.first().is_none_or(|program| program.is_empty()) {
+                return Err(GodataError::invalid_argument(
+                    "command",
+                    "[]",
+                    "A hook command needs a program to run".to_string(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,235 @@
+// Advisory leases on project paths. Pipeline workers that generate outputs in parallel
+// claim a path before producing it, so two workers never write the same output. A lease
+// covers a path and everything below it, and expires unless it is renewed within its
+// time to live, so a worker that dies doesn't keep its paths forever.
+//
+// Leases are advisory. They never block reads, only linking and generating paths. A
+// request acts for a lease by sending the lease ID in the `X-Lease-Id` header, and is
+// refused if any part of its path is leased by someone else. The ID is only returned to
+// the client that acquired the lease, listings just show the holder.
+//
+// Leases are kept in memory and written to their own tree in the project database, so
+// they survive restarts but are not part of snapshots or exports.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use crate::fsystem::{decode, encode, is_within};
+
+pub(crate) const LEASE_HEADER: &str = "x-lease-id";
+// Time to live in seconds
+const DEFAULT_TTL: u64 = 60;
+const MAX_TTL: u64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct Lease {
+    pub(crate) id: String,
+    pub(crate) project_path: String,
+    pub(crate) holder: String,
+    pub(crate) ttl: u64,
+    pub(crate) acquired: DateTime<Utc>,
+    pub(crate) expires: DateTime<Utc>,
+}
+
+// What everyone else gets to see of a lease
+#[derive(Serialize, Debug)]
+pub(crate) struct LeaseInfo<'a> {
+    project_path: &'a str,
+    holder: &'a str,
+    acquired: DateTime<Utc>,
+    expires: DateTime<Utc>,
+}
+
+pub(crate) struct Leases {
+    tree: Tree,
+    leases: HashMap<String, Lease>,
+}
+
+impl Lease {
+    fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.expires > now
+    }
+
+    pub(crate) fn info(&self) -> LeaseInfo<'_> {
+        LeaseInfo {
+            project_path: &self.project_path,
+            holder: &self.holder,
+            acquired: self.acquired,
+            expires: self.expires,
+        }
+    }
+}
+
+impl Leases {
+    pub(crate) fn load(tree: Tree) -> Result<Leases> {
+        let mut leases = HashMap::new();
+        for item in tree.iter() {
+            let (_, value) = item?;
+            let lease: Lease = decode(&value)?;
+            leases.insert(lease.id.clone(), lease);
+        }
+        Ok(Leases { tree, leases })
+    }
+
+    pub(crate) fn list(&self) -> Vec<LeaseInfo<'_>> {
+        let now = Utc::now();
+        let mut leases: Vec<LeaseInfo> = self
+            .leases
+            .values()
+            .filter(|lease| lease.is_active(now))
+            .map(Lease::info)
+            .collect();
+        leases.sort_by(|a, b| a.project_path.cmp(b.project_path));
+        leases
+    }
+
+    pub(crate) fn acquire(
+        &mut self,
+        project_path: &str,
+        holder: String,
+        ttl: Option<u64>,
+    ) -> Result<Lease> {
+        let project_path = project_path.trim_matches('/');
+        if project_path.is_empty() {
+            return Err(GodataError::invalid_argument(
+                "project_path",
+                project_path,
+                "Cannot lease the root of a project".to_string(),
+            ));
+        }
+        let ttl = check_ttl(ttl)?;
+        self.prune()?;
+        self.check(project_path, None)?;
+        let now = Utc::now();
+        let lease = Lease {
+            id: Uuid::new_v4().to_string(),
+            project_path: project_path.to_string(),
+            holder,
+            ttl,
+            acquired: now,
+            expires: now + Duration::seconds(ttl as i64),
+        };
+        self.save(&lease)?;
+        tracing::info!(
+            "Leased path `{}` to `{}` for {} seconds",
+            lease.project_path,
+            lease.holder,
+            ttl
+        );
+        Ok(lease)
+    }
+
+    pub(crate) fn renew(&mut self, id: &str, ttl: Option<u64>) -> Result<Lease> {
+        let now = Utc::now();
+        let mut lease = match self.leases.get(id) {
+            Some(lease) if lease.is_active(now) => lease.clone(),
+            _ => return Err(lease_not_found(id)),
+        };
+        if ttl.is_some() {
+            lease.ttl = check_ttl(ttl)?;
+        }
+        lease.expires = now + Duration::seconds(lease.ttl as i64);
+        self.save(&lease)?;
+        Ok(lease)
+    }
+
+    pub(crate) fn release(&mut self, id: &str) -> Result<Lease> {
+        // An expired lease is cleaned up, but releasing it is still an error
+        let lease = self.leases.remove(id);
+        self.tree.remove(id)?;
+        let lease = match lease {
+            Some(lease) if lease.is_active(Utc::now()) => lease,
+            _ => return Err(lease_not_found(id)),
+        };
+        tracing::info!(
+            "Released lease on path `{}` held by `{}`",
+            lease.project_path,
+            lease.holder
+        );
+        Ok(lease)
+    }
+
+    pub(crate) fn check(&self, project_path: &str, lease_id: Option<&str>) -> Result<()> {
+        // Fail if the path overlaps a path leased by anyone but the given lease
+        let project_path = project_path.trim_matches('/');
+        let now = Utc::now();
+        let conflict = self.leases.values().find(|lease| {
+            lease.is_active(now)
+                && Some(lease.id.as_str()) != lease_id
+                && overlaps(&lease.project_path, project_path)
+        });
+        match conflict {
+            None => Ok(()),
+            Some(lease) => {
+                tracing::info!("Path `{}` is leased by `{}`", project_path, lease.holder);
+                Err(GodataError::new(
+                    GodataErrorType::Conflict,
+                    format!(
+                        "Path `{}` is leased by `{}` until {}",
+                        lease.project_path,
+                        lease.holder,
+                        lease.expires.to_rfc3339()
+                    ),
+                )
+                .with_code("path_leased")
+                .with_details(serde_json::json!({
+                    "path": project_path,
+                    "lease": lease.info(),
+                })))
+            }
+        }
+    }
+
+    fn save(&mut self, lease: &Lease) -> Result<()> {
+        self.tree.insert(lease.id.as_bytes(), encode(lease)?)?;
+        self.leases.insert(lease.id.clone(), lease.clone());
+        Ok(())
+    }
+
+    fn prune(&mut self) -> Result<()> {
+        let now = Utc::now();
+        let expired: Vec<String> = self
+            .leases
+            .values()
+            .filter(|lease| !lease.is_active(now))
+            .map(|lease| lease.id.clone())
+            .collect();
+        for id in expired {
+            self.tree.remove(id.as_bytes())?;
+            self.leases.remove(&id);
+        }
+        Ok(())
+    }
+}
+
+fn check_ttl(ttl: Option<u64>) -> Result<u64> {
+    let ttl = ttl.unwrap_or(DEFAULT_TTL);
+    if ttl == 0 || ttl > MAX_TTL {
+        return Err(GodataError::invalid_argument(
+            "ttl",
+            &ttl.to_string(),
+            format!(
+                "Lease time to live must be between 1 and {} seconds",
+                MAX_TTL
+            ),
+        ));
+    }
+    Ok(ttl)
+}
+
+fn overlaps(a: &str, b: &str) -> bool {
+    is_within(a, b) || is_within(b, a)
+}
+
+fn lease_not_found(id: &str) -> GodataError {
+    GodataError::new(
+        GodataErrorType::NotFound,
+        format!("Lease `{}` does not exist or has expired", id),
+    )
+    .with_code("lease_not_found")
+    .with_details(serde_json::json!({ "lease": id }))
+}
@@ -0,0 +1,691 @@
+// Data lineage. A file can be linked with its provenance: the project paths it was made
+// from and the tool, version and parameters that made it. The revision of every input at
+// the time is recorded with it, so it's always known which version of an input a product
+// was made from. Inputs must exist when the product is linked, and can be files or
+// folders.
+//
+// The provenance of every file together makes up the lineage graph of the project, which
+// can be walked upstream (what a file was made from) or downstream (what was made from
+// it), and exported in W3C PROV-JSON. Inputs are recorded by path, so an input that is
+// later moved or removed shows up as missing.
+//
+// A file is stale when one of its inputs has changed since it was linked: the input was
+// removed, is at a different revision, or (when asked for, as it means reading every
+// input) its contents no longer match the checksum recorded for it. Anything made from
+// a stale file is stale as well.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use crate::fsystem::{is_within, FileSystem};
+use crate::manifest::checksum;
+use crate::metadata::{Metadata, MetadataValue};
+
+// What a client says a file was made from
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ProvenanceRequest {
+    #[serde(default)]
+    inputs: Vec<String>,
+    tool: Option<String>,
+    version: Option<String>,
+    #[serde(default)]
+    parameters: Metadata,
+}
+
+// Checksums of input files by real path. Reading the inputs can take a while, so they are
+// hashed before the project is locked for writing and looked up when the file is linked.
+#[derive(Debug, Default)]
+pub(crate) struct Checksums(HashMap<PathBuf, String>);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct Input {
+    pub(crate) project_path: String,
+    // Revision of the input when the file was linked
+    pub(crate) revision: u64,
+    // Checksum of the contents of a file input, if it could be read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) checksum: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct Provenance {
+    pub(crate) inputs: Vec<Input>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tool: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) version: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) parameters: Metadata,
+    pub(crate) recorded: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum Direction {
+    Upstream,
+    Downstream,
+    #[default]
+    Both,
+}
+
+// Which part of the graph to return. Without a path that's the whole graph, otherwise the
+// path and everything reachable from it in the direction, up to `depth` steps away.
+#[derive(Debug, Default)]
+pub(crate) struct LineageQuery {
+    pub(crate) project_path: Option<String>,
+    pub(crate) direction: Direction,
+    pub(crate) depth: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct Node {
+    pub(crate) project_path: String,
+    pub(crate) exists: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) revision: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) real_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) provenance: Option<Provenance>,
+}
+
+// An input was used to make an output
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Edge {
+    pub(crate) input: String,
+    pub(crate) output: String,
+    pub(crate) revision: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct Lineage {
+    pub(crate) nodes: Vec<Node>,
+    pub(crate) edges: Vec<Edge>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum StaleReason {
+    // The input no longer exists
+    Missing,
+    // The input is at a different revision
+    Changed,
+    // The contents of the input don't match the recorded checksum
+    Modified,
+    // The input is stale itself
+    Upstream,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct StaleInput {
+    input: String,
+    reason: StaleReason,
+    recorded_revision: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revision: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct StaleFile {
+    project_path: String,
+    inputs: Vec<StaleInput>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct Staleness {
+    // Number of files with provenance that were checked
+    checked: usize,
+    stale: Vec<StaleFile>,
+}
+
+impl ProvenanceRequest {
+    pub(crate) fn inputs(&self) -> impl Iterator<Item = &str> {
+        self.inputs.iter().map(|input| input.trim_matches('/'))
+    }
+
+    // Look up the revision of every input. Inputs changed earlier in the same batch get
+    // the revision they are written at.
+    pub(crate) fn resolve(self, tree: &FileSystem, project_path: &str) -> Result<Provenance> {
+        let project_path = project_path.trim_matches('/');
+        let mut seen = BTreeSet::new();
+        let mut inputs = Vec::new();
+        for input in self.inputs {
+            let input = input.trim_matches('/').to_string();
+            if input.is_empty() || input == project_path {
+                return Err(GodataError::invalid_argument(
+                    "inputs",
+                    &input,
+                    format!("`{}` cannot be an input of `{}`", input, project_path),
+                ));
+            }
+            if !seen.insert(input.clone()) {
+                continue;
+            }
+            let revision = tree.pending_revision(&input).map_err(|_| {
+                GodataError::new(
+                    GodataErrorType::NotFound,
+                    format!("Input `{}` does not exist", input),
+                )
+                .with_code("input_not_found")
+                .with_details(json!({ "input": input }))
+            })?;
+            inputs.push(Input {
+                project_path: input,
+                revision,
+                checksum: None,
+            });
+        }
+        Ok(Provenance {
+            inputs,
+            tool: self.tool,
+            version: self.version,
+            parameters: self.parameters,
+            recorded: Utc::now(),
+        })
+    }
+}
+
+impl Checksums {
+    // Files that can't be read are left out
+    pub(crate) fn compute(paths: impl IntoIterator<Item = PathBuf>) -> Checksums {
+        let mut checksums = HashMap::new();
+        for path in paths {
+            if checksums.contains_key(&path) {
+                continue;
+            }
+            if let Ok(sum) = checksum(&path) {
+                checksums.insert(path, sum);
+            }
+        }
+        Checksums(checksums)
+    }
+
+    // An input that wasn't hashed beforehand, because it was relinked in the meantime, is
+    // read now
+    pub(crate) fn get(&self, path: &Path) -> Option<String> {
+        match self.0.get(path) {
+            Some(sum) => Some(sum.clone()),
+            None => checksum(path).ok(),
+        }
+    }
+}
+
+// Which paths and edges make up the part of the graph around a path, or the whole graph
+// when no path is given. Each file with provenance is one entry in `produced`.
+pub(crate) fn walk(
+    produced: &HashMap<String, &Provenance>,
+    query: &LineageQuery,
+) -> (BTreeSet<String>, BTreeSet<Edge>) {
+    let (direction, depth) = (query.direction, query.depth);
+    let mut paths = BTreeSet::new();
+    let mut edges = BTreeSet::new();
+    let edge = |input: &Input, output: &str| Edge {
+        input: input.project_path.clone(),
+        output: output.to_string(),
+        revision: input.revision,
+    };
+    let start = match &query.project_path {
+        Some(start) => start.trim_matches('/'),
+        None => {
+            for (output, provenance) in produced {
+                paths.insert(output.clone());
+                for input in &provenance.inputs {
+                    paths.insert(input.project_path.clone());
+                    edges.insert(edge(input, output));
+                }
+            }
+            return (paths, edges);
+        }
+    };
+    paths.insert(start.to_string());
+    if matches!(direction, Direction::Upstream | Direction::Both) {
+        let mut queue = VecDeque::from([(start.to_string(), 0)]);
+        let mut visited = BTreeSet::from([start.to_string()]);
+        while let Some((path, level)) = queue.pop_front() {
+            if depth.is_some_and(|depth| level >= depth) {
+                continue;
+            }
+            let provenance = match produced.get(&path) {
+                Some(provenance) => provenance,
+                None => continue,
+            };
+            for input in &provenance.inputs {
+                edges.insert(edge(input, &path));
+                paths.insert(input.project_path.clone());
+                if visited.insert(input.project_path.clone()) {
+                    queue.push_back((input.project_path.clone(), level + 1));
+                }
+            }
+        }
+    }
+    if matches!(direction, Direction::Downstream | Direction::Both) {
+        let mut consumers: HashMap<&str, Vec<(&str, &Input)>> = HashMap::new();
+        for (output, provenance) in produced {
+            for input in &provenance.inputs {
+                consumers
+                    .entry(input.project_path.as_str())
+                    .or_default()
+                    .push((output.as_str(), input));
+            }
+        }
+        let mut queue = VecDeque::from([(start.to_string(), 0)]);
+        let mut visited = BTreeSet::from([start.to_string()]);
+        while let Some((path, level)) = queue.pop_front() {
+            if depth.is_some_and(|depth| level >= depth) {
+                continue;
+            }
+            for (output, input) in consumers.get(path.as_str()).into_iter().flatten() {
+                edges.insert(edge(input, output));
+                paths.insert(output.to_string());
+                if visited.insert(output.to_string()) {
+                    queue.push_back((output.to_string(), level + 1));
+                }
+            }
+        }
+    }
+    (paths, edges)
+}
+
+// Which files are stale, given the current revision of a path and whether the contents of
+// an input have changed. Only files within `prefix` are reported, but staleness is
+// carried through the whole graph.
+pub(crate) fn stale(
+    produced: &HashMap<String, &Provenance>,
+    prefix: Option<&str>,
+    revision: impl Fn(&str) -> Option<u64>,
+    modified: impl Fn(&Input) -> bool,
+) -> Staleness {
+    let mut stale: BTreeMap<&str, Vec<StaleInput>> = BTreeMap::new();
+    for (output, provenance) in produced {
+        let inputs: Vec<StaleInput> = provenance
+            .inputs
+            .iter()
+            .filter_map(|input| {
+                let current = revision(&input.project_path);
+                let reason = match current {
+                    None => StaleReason::Missing,
+                    Some(current) if current != input.revision => StaleReason::Changed,
+                    Some(_) if modified(input) => StaleReason::Modified,
+                    Some(_) => return None,
+                };
+                Some(StaleInput {
+                    input: input.project_path.clone(),
+                    reason,
+                    recorded_revision: input.revision,
+                    revision: current,
+                })
+            })
+            .collect();
+        if !inputs.is_empty() {
+            stale.insert(output, inputs);
+        }
+    }
+    // Carry staleness downstream until nothing changes, which also ends on cycles
+    loop {
+        let mut upstream = Vec::new();
+        for (output, provenance) in produced {
+            for input in &provenance.inputs {
+                let listed = stale.get(output.as_str()).is_some_and(|inputs| {
+                    inputs.iter().any(|stale| stale.input == input.project_path)
+                });
+                if !listed && stale.contains_key(input.project_path.as_str()) {
+                    upstream.push((output.as_str(), input));
+                }
+            }
+        }
+        if upstream.is_empty() {
+            break;
+        }
+        for (output, input) in upstream {
+            stale.entry(output).or_default().push(StaleInput {
+                input: input.project_path.clone(),
+                reason: StaleReason::Upstream,
+                recorded_revision: input.revision,
+                revision: revision(&input.project_path),
+            });
+        }
+    }
+    let prefix = prefix.map(|prefix| prefix.trim_matches('/'));
+    let checked = produced
+        .keys()
+        .filter(|path| prefix.is_none_or(|prefix| is_within(path, prefix)))
+        .count();
+    let stale = stale
+        .into_iter()
+        .filter(|(path, _)| prefix.is_none_or(|prefix| is_within(path, prefix)))
+        .map(|(path, inputs)| StaleFile {
+            project_path: path.to_string(),
+            inputs,
+        })
+        .collect();
+    Staleness { checked, stale }
+}
+
+// The graph as a W3C PROV-JSON document. Every path is an entity, every file with
+// provenance was generated by an activity of its own, which used the inputs and was
+// associated with the tool that ran it.
+pub(crate) fn to_prov(collection: &str, project: &str, lineage: &Lineage) -> Value {
+    let mut entities = Map::new();
+    let mut activities = Map::new();
+    let mut agents = Map::new();
+    let mut relations: BTreeMap<&str, Map<String, Value>> = BTreeMap::new();
+    let mut relate = |kind: &'static str, relation: Value| {
+        let relations = relations.entry(kind).or_default();
+        let id = format!("_:{}{}", kind, relations.len() + 1);
+        relations.insert(id, relation);
+    };
+    for node in &lineage.nodes {
+        let entity = format!("entity:{}", node.project_path);
+        let mut attributes = Map::new();
+        attributes.insert("prov:label".to_string(), json!(node.project_path));
+        attributes.insert("godata:exists".to_string(), json!(node.exists));
+        if let Some(revision) = node.revision {
+            attributes.insert("godata:revision".to_string(), json!(revision));
+        }
+        if let Some(real_path) = &node.real_path {
+            attributes.insert("godata:real_path".to_string(), json!(real_path));
+        }
+        entities.insert(entity.clone(), Value::Object(attributes));
+
+        let provenance = match &node.provenance {
+            Some(provenance) => provenance,
+            None => continue,
+        };
+        let activity = format!("activity:{}", node.project_path);
+        let mut attributes = Map::new();
+        attributes.insert("prov:endTime".to_string(), json!(provenance.recorded));
+        if let Some(tool) = &provenance.tool {
+            attributes.insert("prov:label".to_string(), json!(tool));
+        }
+        let mut parameters: Vec<(&String, &MetadataValue)> = provenance.parameters.iter().collect();
+        parameters.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, value) in parameters {
+            attributes.insert(format!("parameter:{}", name), prov_value(value));
+        }
+        activities.insert(activity.clone(), Value::Object(attributes));
+        relate(
+            "wasGeneratedBy",
+            json!({ "prov:entity": entity, "prov:activity": activity }),
+        );
+
+        if let Some(tool) = &provenance.tool {
+            let agent = match &provenance.version {
+                Some(version) => format!("tool:{}@{}", tool, version),
+                None => format!("tool:{}", tool),
+            };
+            let mut attributes = Map::new();
+            attributes.insert("prov:type".to_string(), json!("prov:SoftwareAgent"));
+            attributes.insert("prov:label".to_string(), json!(tool));
+            if let Some(version) = &provenance.version {
+                attributes.insert("godata:version".to_string(), json!(version));
+            }
+            agents.insert(agent.clone(), Value::Object(attributes));
+            relate(
+                "wasAssociatedWith",
+                json!({ "prov:activity": activity, "prov:agent": agent }),
+            );
+        }
+    }
+    for edge in &lineage.edges {
+        let input = format!("entity:{}", edge.input);
+        let output = format!("entity:{}", edge.output);
+        let activity = format!("activity:{}", edge.output);
+        relate(
+            "used",
+            json!({ "prov:activity": activity, "prov:entity": input, "godata:revision": edge.revision }),
+        );
+        relate(
+            "wasDerivedFrom",
+            json!({
+                "prov:generatedEntity": output,
+                "prov:usedEntity": input,
+                "prov:activity": activity,
+            }),
+        );
+    }
+    let base = format!("urn:godata:{}/{}/", collection, project);
+    let mut document = Map::new();
+    document.insert(
+        "prefix".to_string(),
+        json!({
+            "godata": "urn:godata:",
+            "entity": format!("{}entity/", base),
+            "activity": format!("{}activity/", base),
+            "parameter": format!("{}parameter/", base),
+            "tool": "urn:godata:tool/",
+        }),
+    );
+    for (kind, values) in [
+        ("entity", entities),
+        ("activity", activities),
+        ("agent", agents),
+    ] {
+        if !values.is_empty() {
+            document.insert(kind.to_string(), Value::Object(values));
+        }
+    }
+    for (kind, values) in relations {
+        document.insert(kind.to_string(), Value::Object(values));
+    }
+    Value::Object(document)
+}
+
+// PROV-JSON attribute values are strings, numbers or booleans, anything else is written
+// out as JSON in a string
+fn prov_value(value: &MetadataValue) -> Value {
+    match value {
+        MetadataValue::Bool(value) => json!(value),
+        MetadataValue::Int(value) => json!(value),
+        MetadataValue::Float(value) => json!(value),
+        MetadataValue::String(value) => json!(value),
+        MetadataValue::Timestamp(value) => {
+            json!({ "$": value.to_rfc3339(), "type": "xsd:dateTime" })
+        }
+        other => json!(serde_json::to_string(other).unwrap_or_default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // raw -> clean -> model, and raw -> plot
+    fn graph() -> Vec<(String, Provenance)> {
+        [
+            ("clean", vec![("raw", 1)]),
+            ("model", vec![("clean", 2)]),
+            ("plot", vec![("raw", 1)]),
+        ]
+        .into_iter()
+        .map(|(output, inputs)| (output.to_string(), provenance(&inputs)))
+        .collect()
+    }
+
+    fn provenance(inputs: &[(&str, u64)]) -> Provenance {
+        Provenance {
+            inputs: inputs
+                .iter()
+                .map(|(path, revision)| Input {
+                    project_path: path.to_string(),
+                    revision: *revision,
+                    checksum: None,
+                })
+                .collect(),
+            tool: None,
+            version: None,
+            parameters: Metadata::new(),
+            recorded: Utc::now(),
+        }
+    }
+
+    fn produced(graph: &[(String, Provenance)]) -> HashMap<String, &Provenance> {
+        graph.iter().map(|(path, p)| (path.clone(), p)).collect()
+    }
+
+    fn query(path: &str, direction: Direction, depth: Option<usize>) -> LineageQuery {
+        LineageQuery {
+            project_path: Some(path.to_string()),
+            direction,
+            depth,
+        }
+    }
+
+    fn paths(paths: &BTreeSet<String>) -> Vec<&str> {
+        paths.iter().map(|path| path.as_str()).collect()
+    }
+
+    #[test]
+    fn walks_the_whole_graph() {
+        let graph = graph();
+        let (nodes, edges) = walk(&produced(&graph), &LineageQuery::default());
+        assert_eq!(paths(&nodes), ["clean", "model", "plot", "raw"]);
+        assert_eq!(edges.len(), 3);
+    }
+
+    #[test]
+    fn walks_upstream_and_downstream() {
+        let graph = graph();
+        let produced = produced(&graph);
+        let (nodes, _) = walk(&produced, &query("model", Direction::Upstream, None));
+        assert_eq!(paths(&nodes), ["clean", "model", "raw"]);
+        let (nodes, edges) = walk(&produced, &query("raw", Direction::Downstream, None));
+        assert_eq!(paths(&nodes), ["clean", "model", "plot", "raw"]);
+        assert!(edges.contains(&Edge {
+            input: "clean".to_string(),
+            output: "model".to_string(),
+            revision: 2,
+        }));
+        let (nodes, _) = walk(&produced, &query("clean", Direction::Both, None));
+        assert_eq!(paths(&nodes), ["clean", "model", "raw"]);
+    }
+
+    #[test]
+    fn walks_up_to_depth() {
+        let graph = graph();
+        let produced = produced(&graph);
+        let (nodes, edges) = walk(&produced, &query("raw", Direction::Downstream, Some(1)));
+        assert_eq!(paths(&nodes), ["clean", "plot", "raw"]);
+        assert_eq!(edges.len(), 2);
+        let (nodes, edges) = walk(&produced, &query("/model/", Direction::Upstream, Some(0)));
+        assert_eq!(paths(&nodes), ["model"]);
+        assert!(edges.is_empty());
+    }
+
+    #[test]
+    fn walks_cycles() {
+        let graph = vec![
+            ("a".to_string(), provenance(&[("b", 1)])),
+            ("b".to_string(), provenance(&[("a", 1)])),
+        ];
+        let (nodes, edges) = walk(&produced(&graph), &query("a", Direction::Both, None));
+        assert_eq!(paths(&nodes), ["a", "b"]);
+        assert_eq!(edges.len(), 2);
+    }
+
+    fn stale_inputs(staleness: &Staleness) -> Vec<(&str, &str, StaleReason)> {
+        staleness
+            .stale
+            .iter()
+            .flat_map(|file| {
+                file.inputs.iter().map(|input| {
+                    (
+                        file.project_path.as_str(),
+                        input.input.as_str(),
+                        input.reason,
+                    )
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nothing_is_stale_when_inputs_are_unchanged() {
+        let graph = graph();
+        let revisions = HashMap::from([("raw", 1), ("clean", 2), ("model", 3), ("plot", 3)]);
+        let staleness = stale(
+            &produced(&graph),
+            None,
+            |path| revisions.get(path).copied(),
+            |_| false,
+        );
+        assert_eq!(staleness.checked, 3);
+        assert!(staleness.stale.is_empty());
+    }
+
+    #[test]
+    fn staleness_is_carried_downstream() {
+        let graph = graph();
+        let revisions = HashMap::from([("raw", 4), ("clean", 2), ("model", 3), ("plot", 3)]);
+        let staleness = stale(
+            &produced(&graph),
+            None,
+            |path| revisions.get(path).copied(),
+            |_| false,
+        );
+        assert_eq!(
+            stale_inputs(&staleness),
+            [
+                ("clean", "raw", StaleReason::Changed),
+                ("model", "clean", StaleReason::Upstream),
+                ("plot", "raw", StaleReason::Changed),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_and_modified_inputs_are_stale() {
+        let graph = graph();
+        let revisions = HashMap::from([("clean", 2), ("model", 3), ("plot", 3)]);
+        let staleness = stale(
+            &produced(&graph),
+            Some("/model"),
+            |path| revisions.get(path).copied(),
+            |_| false,
+        );
+        // Only files within the prefix are reported
+        assert_eq!(staleness.checked, 1);
+        assert_eq!(
+            stale_inputs(&staleness),
+            [("model", "clean", StaleReason::Upstream)]
+        );
+
+        let revisions = HashMap::from([("raw", 1), ("clean", 2), ("model", 3), ("plot", 3)]);
+        let staleness = stale(
+            &produced(&graph),
+            None,
+            |path| revisions.get(path).copied(),
+            |input| input.project_path == "clean",
+        );
+        assert_eq!(
+            stale_inputs(&staleness),
+            [("model", "clean", StaleReason::Modified)]
+        );
+    }
+
+    #[test]
+    fn staleness_ends_on_cycles() {
+        let graph = vec![
+            ("a".to_string(), provenance(&[("b", 1), ("raw", 1)])),
+            ("b".to_string(), provenance(&[("a", 1)])),
+        ];
+        let revisions = HashMap::from([("a", 1), ("b", 1), ("raw", 2)]);
+        let staleness = stale(
+            &produced(&graph),
+            None,
+            |path| revisions.get(path).copied(),
+            |_| false,
+        );
+        assert_eq!(
+            stale_inputs(&staleness),
+            [
+                ("a", "raw", StaleReason::Changed),
+                ("a", "b", StaleReason::Upstream),
+                ("b", "a", StaleReason::Upstream),
+            ]
+        );
+    }
+}
@@ -1,34 +1,34 @@
+use crate::config;
 use crate::errors::{GodataError, GodataErrorType, Result};
-use directories::BaseDirs;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-pub(crate) fn get_main_dir() -> PathBuf {
-    let base_dir: BaseDirs = BaseDirs::new().unwrap();
-    let user_data_dir: &Path = base_dir.data_dir();
-    let package_root: PathBuf = user_data_dir.join("godata");
+pub(crate) fn get_main_dir() -> Result<PathBuf> {
+    let package_root: PathBuf = config::get().data_dir();
     if !package_root.exists() {
-        std::fs::create_dir_all(&package_root).unwrap();
+        std::fs::create_dir_all(&package_root)?;
     }
-    package_root
+    Ok(package_root)
 }
 
 fn create_collection_dir(name: &str) -> Result<PathBuf> {
-    let main_directory = get_main_dir();
+    let main_directory = get_main_dir()?;
     let collection_path = main_directory.join(name);
     if !collection_path.exists() {
-        std::fs::create_dir_all(&collection_path).unwrap();
+        std::fs::create_dir_all(&collection_path)?;
         return Ok(collection_path);
     }
 
     Err(GodataError::new(
         GodataErrorType::AlreadyExists,
         format!("Collection `{}` already exists", name),
-    ))
+    )
+    .with_code("collection_exists")
+    .with_details(serde_json::json!({"collection": name})))
 }
 
 pub(crate) fn load_collection_dir(name: &str) -> Result<PathBuf> {
-    let main_directory = get_main_dir();
+    let main_directory = get_main_dir()?;
     let collection_path = main_directory.join(name);
     if collection_path.exists() {
         return Ok(collection_path);
@@ -37,11 +37,13 @@ pub(crate) fn load_collection_dir(name: &str) -> Result<PathBuf> {
     Err(GodataError::new(
         GodataErrorType::NotFound,
         format!("Collection `{}` does not exist", name),
-    ))
+    )
+    .with_code("collection_not_found")
+    .with_details(serde_json::json!({"collection": name})))
 }
 
 fn delete_collection_dir(name: &str) -> Result<()> {
-    let main_directory = get_main_dir();
+    let main_directory = get_main_dir()?;
     let collection_path = main_directory.join(name);
     if collection_path.exists() {
         std::fs::remove_dir_all(&collection_path)?;
@@ -51,7 +53,9 @@ fn delete_collection_dir(name: &str) -> Result<()> {
     Err(GodataError::new(
         GodataErrorType::NotFound,
         format!("Collection `{}` does not exist", name),
-    ))
+    )
+    .with_code("collection_not_found")
+    .with_details(serde_json::json!({"collection": name})))
 }
 
 pub(crate) fn create_project_dir(
@@ -59,26 +63,24 @@ pub(crate) fn create_project_dir(
     collection_name: &str,
     force: bool,
 ) -> Result<PathBuf> {
-    let mut collection_dir = load_collection_dir(collection_name);
-    if collection_dir.is_err() {
-        if force {
-            collection_dir = create_collection_dir(collection_name);
-        } else {
-            return Err(collection_dir.err().unwrap());
-        }
-    }
-    let collection_dir = collection_dir.unwrap();
+    let collection_dir = match load_collection_dir(collection_name) {
+        Ok(collection_dir) => collection_dir,
+        Err(_) if force => create_collection_dir(collection_name)?,
+        Err(e) => return Err(e),
+    };
 
     let project_path = collection_dir.join(name);
     if !project_path.exists() {
-        std::fs::create_dir_all(&project_path).unwrap();
+        std::fs::create_dir_all(&project_path)?;
         return Ok(project_path);
     }
 
     Err(GodataError::new(
         GodataErrorType::AlreadyExists,
         format!("Project `{}` already exists", name),
-    ))
+    )
+    .with_code("project_exists")
+    .with_details(serde_json::json!({"collection": collection_name, "project": name})))
 }
 
 pub(crate) fn load_project_dir(name: &str, collection_name: &str) -> Result<PathBuf> {
@@ -91,7 +93,9 @@ pub(crate) fn load_project_dir(name: &str, collection_name: &str) -> Result<Path
     Err(GodataError::new(
         GodataErrorType::NotFound,
         format!("Project `{}` does not exist", name),
-    ))
+    )
+    .with_code("project_not_found")
+    .with_details(serde_json::json!({"collection": collection_name, "project": name})))
 }
 
 pub(crate) fn delete_project_dir(name: &str, collection_name: &str) -> Result<()> {
@@ -103,7 +107,9 @@ pub(crate) fn delete_project_dir(name: &str, collection_name: &str) -> Result<()
         return Err(GodataError::new(
             GodataErrorType::NotFound,
             format!("Project `{}` does not exist", name),
-        ));
+        )
+        .with_code("project_not_found")
+        .with_details(serde_json::json!({"collection": collection_name, "project": name})));
     }
     // Check if this folder has any subdirectories
     for entry in fs::read_dir(&collection_dir)? {
@@ -119,11 +125,9 @@ pub(crate) fn delete_project_dir(name: &str, collection_name: &str) -> Result<()
 }
 
 pub(crate) fn get_default_storage_dir() -> Result<PathBuf> {
-    let base_dirs = BaseDirs::new().unwrap();
-    let home = base_dirs.home_dir();
-    let main_dir = home.join("godata");
+    let main_dir = config::get().storage_dir();
     if !main_dir.exists() {
-        std::fs::create_dir_all(&main_dir).unwrap();
+        std::fs::create_dir_all(&main_dir)?;
     }
     Ok(main_dir)
 }
@@ -142,3 +146,15 @@ pub(crate) fn get_default_project_storage_dir(
     let project_dir = collection_dir.join(name);
     Ok(project_dir)
 }
+
+// Paths are stored as strings, so anything that isn't valid UTF-8 is rejected up front
+pub(crate) fn path_to_string(path: &Path) -> Result<String> {
+    match path.to_str() {
+        Some(path) => Ok(path.to_string()),
+        None => Err(GodataError::new(
+            GodataErrorType::InvalidPath,
+            format!("Path `{}` is not valid UTF-8", path.display()),
+        )
+        .with_code("invalid_path_encoding")),
+    }
+}
@@ -1,7 +1,6 @@
-use crate::locations::get_default_storage_dir;
+use crate::config;
 use chrono::Utc;
 use std::path::PathBuf;
-use tracing_appender;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_subscriber::{layer::SubscriberExt, Registry};
 
@@ -16,7 +15,7 @@ pub(crate) fn init_logging() -> tracing_appender::non_blocking::WorkerGuard {
     let (non_blocking, guard) = tracing_appender::non_blocking(file);
 
     let env_filter = tracing_subscriber::EnvFilter::builder()
-        .with_default_directive(tracing::Level::INFO.into())
+        .with_default_directive(config::get().log_level().unwrap().into())
         .from_env_lossy();
     // The subscriber should be an append-only file
 
@@ -28,21 +27,23 @@ pub(crate) fn init_logging() -> tracing_appender::non_blocking::WorkerGuard {
         .with(formatter);
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
-    return guard;
+    guard
 }
 
 fn get_log_location() -> PathBuf {
-    let storage_dir = get_default_storage_dir().unwrap();
-    let log_dir = storage_dir.join("logs");
+    let log_dir = config::get().log_dir();
     std::fs::create_dir_all(&log_dir).unwrap();
     let timestamp = Utc::now().format("%Y-%m-%d-%H-%M-%S");
     let log_file = log_dir.join(format!("godata-{}.log", timestamp));
-    clean_logfiles(&log_dir);
+    clean_logfiles(&log_dir, config::get().log.retention_days);
     log_file
 }
 
-fn clean_logfiles(log_dir: &PathBuf) {
-    // Logfiles from more than 30 days ago are deleted
+fn clean_logfiles(log_dir: &PathBuf, retention_days: u32) {
+    // Logfiles older than the retention period are deleted
+    if retention_days == 0 {
+        return;
+    }
     let files = std::fs::read_dir(log_dir).unwrap();
     for file in files {
         let file = file.unwrap();
@@ -51,7 +52,7 @@ fn clean_logfiles(log_dir: &PathBuf) {
         // convert the modified time to a DateTime<Utc>
         let modified: chrono::DateTime<Utc> = chrono::DateTime::from(modified);
         let duration = Utc::now().signed_duration_since(modified);
-        if duration.num_days() > 30 {
+        if duration.num_days() > retention_days as i64 {
             std::fs::remove_file(file.path()).unwrap();
         }
     }
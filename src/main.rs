@@ -1,14 +1,32 @@
+mod archive;
+mod audit;
+mod auth;
+mod batch;
+mod config;
+mod diff;
 mod errors;
+mod events;
 mod fsystem;
 mod handlers;
+mod hooks;
+mod lease;
+mod lineage;
 mod locations;
 mod log;
+mod manifest;
+mod metadata;
 mod project;
+mod request;
+mod revision;
 mod routes;
 mod server;
 mod storage;
+mod sync;
+mod tls;
 
 use clap::Parser;
+use std::net::IpAddr;
+use std::path::PathBuf;
 // Allow the server to return its version with a --version flag
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 #[derive(Parser)]
@@ -19,6 +37,76 @@ struct Opts {
     debug: bool,
     #[clap(short, long)]
     port: Option<u16>,
+    /// Path to the server config file
+    #[clap(short, long)]
+    config: Option<PathBuf>,
+    /// Address to listen on in TCP mode, may be given more than once
+    #[clap(long, value_parser = config::parse_address)]
+    bind: Vec<IpAddr>,
+    #[clap(long)]
+    tls_cert: Option<PathBuf>,
+    #[clap(long)]
+    tls_key: Option<PathBuf>,
+    #[clap(long)]
+    socket_path: Option<PathBuf>,
+    /// Permissions of the unix socket, in octal
+    #[clap(long, value_parser = config::parse_mode)]
+    socket_mode: Option<u32>,
+    /// Another user allowed to connect to the unix socket, may be given more than once
+    #[clap(long = "allow-uid")]
+    allowed_uids: Vec<u32>,
+    /// Require an API token on every request
+    #[clap(long)]
+    auth: bool,
+    /// Create an admin token with the given name, print it and exit
+    #[clap(long, value_name = "NAME")]
+    create_token: Option<String>,
+    #[clap(long)]
+    data_dir: Option<PathBuf>,
+    #[clap(long)]
+    storage_dir: Option<PathBuf>,
+    #[clap(long)]
+    log_level: Option<String>,
+}
+
+impl Opts {
+    fn apply(&self, config: &mut config::Config) {
+        if self.port.is_some() {
+            config.server.port = self.port;
+        }
+        if !self.bind.is_empty() {
+            config.server.bind = self.bind.clone();
+        }
+        if self.tls_cert.is_some() {
+            config.server.tls.cert = self.tls_cert.clone();
+        }
+        if self.tls_key.is_some() {
+            config.server.tls.key = self.tls_key.clone();
+        }
+        if self.socket_path.is_some() {
+            config.server.socket_path = self.socket_path.clone();
+        }
+        if let Some(mode) = self.socket_mode {
+            config.server.socket_mode = mode;
+        }
+        if !self.allowed_uids.is_empty() {
+            config.server.allowed_uids = self.allowed_uids.clone();
+        }
+        if self.auth {
+            config.auth.enabled = true;
+        }
+        if self.data_dir.is_some() {
+            config.paths.data_dir = self.data_dir.clone();
+        }
+        if self.storage_dir.is_some() {
+            config.paths.storage_dir = self.storage_dir.clone();
+        }
+        if let Some(level) = &self.log_level {
+            config.log.level = level.clone();
+        } else if self.debug {
+            config.log.level = "debug".to_string();
+        }
+    }
 }
 
 #[tokio::main]
@@ -28,7 +116,41 @@ async fn main() {
         println!("{}", VERSION);
         return;
     }
+    let config = config::Config::load(opts.config.clone()).and_then(|mut config| {
+        opts.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    });
+    match config {
+        Ok(config) => config::init(config),
+        Err(e) => {
+            eprintln!("Failed to load server configuration: {}", e.message);
+            std::process::exit(1);
+        }
+    }
     let _log_guard = log::init_logging();
-    let srv = server::get_server(opts.port);
+    if let Some(name) = opts.create_token {
+        create_admin_token(&name);
+        return;
+    }
+    let srv = server::get_server();
     srv.start().await;
 }
+
+fn create_admin_token(name: &str) {
+    let grants = vec![auth::Grant {
+        scope: "*".to_string(),
+        level: auth::Level::Admin,
+    }];
+    let token = auth::AuthManager::get_manager(true).and_then(|m| m.create_token(name, grants));
+    match token {
+        Ok(token) => {
+            println!("Created admin token `{}` ({})", name, token.info.id);
+            println!("{}", token.token);
+        }
+        Err(e) => {
+            eprintln!("Failed to create token: {}", e.message);
+            std::process::exit(1);
+        }
+    }
+}
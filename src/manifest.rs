@@ -0,0 +1,99 @@
+// A portable, human-readable description of a project tree. Unlike the sled export,
+// the manifest does not depend on the database format, so it can be reviewed, diffed
+// and used to re-create a project with any version of the server.
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use crate::metadata::Metadata;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::Path;
+
+pub(crate) const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct Manifest {
+    pub(crate) version: u32,
+    pub(crate) project: String,
+    pub(crate) collection: String,
+    pub(crate) created: String,
+    #[serde(default)]
+    pub(crate) folders: Vec<ManifestFolder>,
+    #[serde(default)]
+    pub(crate) files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct ManifestFolder {
+    pub(crate) path: String,
+    #[serde(default)]
+    pub(crate) metadata: Metadata,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct ManifestFile {
+    pub(crate) path: String,
+    // Relative to the storage root for internal files, absolute for external files
+    pub(crate) real_path: String,
+    #[serde(default)]
+    pub(crate) metadata: Metadata,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) checksum: Option<String>,
+}
+
+impl Manifest {
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        if path.exists() {
+            return Err(GodataError::new(
+                GodataErrorType::AlreadyExists,
+                format!("Output file `{}` already exists", path.display()),
+            )
+            .with_code("output_exists"));
+        }
+        let writer = BufWriter::new(fs::File::create(path)?);
+        serde_json::to_writer_pretty(writer, self).map_err(|e| {
+            GodataError::new(
+                GodataErrorType::IOError,
+                format!("Failed to write manifest: {}", e),
+            )
+            .with_code("io_error")
+        })
+    }
+
+    pub(crate) fn read(path: &Path) -> Result<Manifest> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        let manifest: Manifest = serde_json::from_reader(reader).map_err(|e| {
+            GodataError::new(
+                GodataErrorType::InvalidArgument,
+                format!("Failed to parse manifest `{}`: {}", path.display(), e),
+            )
+            .with_code("invalid_manifest")
+        })?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidArgument,
+                format!(
+                    "Manifest version {} is newer than the latest supported version {}",
+                    manifest.version, MANIFEST_VERSION
+                ),
+            )
+            .with_code("unsupported_manifest_version"));
+        }
+        Ok(manifest)
+    }
+}
+
+pub(crate) fn checksum(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
@@ -0,0 +1,287 @@
+// Typed metadata values for files and folders. Values are stored in the project
+// database as CBOR and returned as plain JSON. Timestamps have no native JSON type, so
+// they are written to JSON as RFC 3339 strings and stored with the CBOR date/time tag.
+// A string in a JSON request always stays a string, so it is returned exactly as sent.
+//
+// Before metadata was typed every value was stored as a string, which is also how
+// query parameters arrive. Those strings are typed with `infer`, which only converts a
+// value when it can be written back exactly as it was (so "42" becomes an integer but
+// "042" and "True" stay strings). This is the only way a timestamp is created.
+
+use chrono::{DateTime, FixedOffset};
+use ciborium::tag::Required;
+use serde::de::{self, EnumAccess, IgnoredAny, MapAccess, SeqAccess, VariantAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+
+pub(crate) type Metadata = HashMap<String, MetadataValue>;
+
+// Bumped whenever stored metadata needs to be rewritten. Databases without a version
+// predate typed metadata.
+pub(crate) const METADATA_VERSION: u32 = 1;
+
+// The CBOR tag for an RFC 3339 date/time string
+const TIMESTAMP_TAG: u64 = 0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum MetadataValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Timestamp(DateTime<FixedOffset>),
+    String(String),
+    List(Vec<MetadataValue>),
+    Map(HashMap<String, MetadataValue>),
+}
+
+impl MetadataValue {
+    pub(crate) fn infer(value: &str) -> MetadataValue {
+        match value {
+            "true" => return MetadataValue::Bool(true),
+            "false" => return MetadataValue::Bool(false),
+            _ => (),
+        }
+        if let Ok(int) = value.parse::<i64>() {
+            if int.to_string() == value {
+                return MetadataValue::Int(int);
+            }
+        }
+        if let Ok(float) = value.parse::<f64>() {
+            // Python writes floats with a trailing `.0`, which is what Debug formatting does
+            let exact = float.to_string() == value || format!("{:?}", float) == value;
+            if float.is_finite() && exact {
+                return MetadataValue::Float(float);
+            }
+        }
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+            if timestamp.to_rfc3339() == value {
+                return MetadataValue::Timestamp(timestamp);
+            }
+        }
+        MetadataValue::String(value.to_string())
+    }
+
+    fn migrate(&mut self) {
+        match self {
+            MetadataValue::String(value) => *self = MetadataValue::infer(value),
+            MetadataValue::List(values) => values.iter_mut().for_each(MetadataValue::migrate),
+            MetadataValue::Map(values) => migrate(values),
+            _ => (),
+        }
+    }
+}
+
+impl Serialize for MetadataValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            MetadataValue::Bool(value) => serializer.serialize_bool(*value),
+            MetadataValue::Int(value) => serializer.serialize_i64(*value),
+            MetadataValue::Float(value) => serializer.serialize_f64(*value),
+            MetadataValue::Timestamp(value) if serializer.is_human_readable() => {
+                serializer.serialize_str(&value.to_rfc3339())
+            }
+            MetadataValue::Timestamp(value) => {
+                Required::<String, TIMESTAMP_TAG>(value.to_rfc3339()).serialize(serializer)
+            }
+            MetadataValue::String(value) => serializer.serialize_str(value),
+            MetadataValue::List(values) => values.serialize(serializer),
+            MetadataValue::Map(values) => values.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for MetadataValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = MetadataValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a boolean, number, string, list or map")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Self::Value, E> {
+        Ok(MetadataValue::Bool(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        Ok(MetadataValue::Int(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        // Integers too big for an i64 lose precision, as they would in JavaScript
+        Ok(match i64::try_from(value) {
+            Ok(value) => MetadataValue::Int(value),
+            Err(_) => MetadataValue::Float(value as f64),
+        })
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
+        Ok(MetadataValue::Float(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        Ok(MetadataValue::String(value.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Self::Value, E> {
+        Ok(MetadataValue::String(value))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(MetadataValue::List(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut values = HashMap::new();
+        while let Some((key, value)) = map.next_entry()? {
+            values.insert(key, value);
+        }
+        Ok(MetadataValue::Map(values))
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        // ciborium reads a tagged value as an enum whose variant holds the tag and the value
+        let (_, variant) = data.variant::<IgnoredAny>()?;
+        variant.tuple_variant(2, TaggedVisitor)
+    }
+}
+
+struct TaggedVisitor;
+
+impl<'de> Visitor<'de> for TaggedVisitor {
+    type Value = MetadataValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a tagged CBOR value")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let tag: u64 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let value: MetadataValue = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        match (tag, value) {
+            (TIMESTAMP_TAG, MetadataValue::String(value)) => DateTime::parse_from_rfc3339(&value)
+                .map(MetadataValue::Timestamp)
+                .map_err(de::Error::custom),
+            // Tags we don't know about are ignored, as ciborium does elsewhere
+            (_, value) => Ok(value),
+        }
+    }
+}
+
+impl From<String> for MetadataValue {
+    fn from(value: String) -> Self {
+        MetadataValue::String(value)
+    }
+}
+
+pub(crate) fn migrate(metadata: &mut Metadata) {
+    // Type any values that were stored as strings by an older version of the server
+    metadata.values_mut().for_each(MetadataValue::migrate);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsystem::{decode, encode};
+
+    fn string(value: &str) -> MetadataValue {
+        MetadataValue::String(value.to_string())
+    }
+
+    #[test]
+    fn infers_values_that_round_trip() {
+        assert_eq!(MetadataValue::infer("true"), MetadataValue::Bool(true));
+        assert_eq!(MetadataValue::infer("false"), MetadataValue::Bool(false));
+        assert_eq!(MetadataValue::infer("42"), MetadataValue::Int(42));
+        assert_eq!(MetadataValue::infer("-7"), MetadataValue::Int(-7));
+        assert_eq!(MetadataValue::infer("1.5"), MetadataValue::Float(1.5));
+        assert_eq!(MetadataValue::infer("2.0"), MetadataValue::Float(2.0));
+        let timestamp = "2024-01-01T12:30:00+02:00";
+        assert_eq!(
+            MetadataValue::infer(timestamp),
+            MetadataValue::Timestamp(DateTime::parse_from_rfc3339(timestamp).unwrap())
+        );
+    }
+
+    #[test]
+    fn keeps_strings_that_would_change() {
+        for value in [
+            "True",
+            "FALSE",
+            "042",
+            "+1",
+            "1e3",
+            "NaN",
+            "inf",
+            "2024-01-01T12:30:00Z",
+            "2024-01-01t12:30:00+02:00",
+            "",
+            "text",
+        ] {
+            assert_eq!(MetadataValue::infer(value), string(value), "{}", value);
+        }
+    }
+
+    #[test]
+    fn json_strings_stay_strings() {
+        let value: MetadataValue = serde_json::from_str("\"2024-01-01T12:30:00Z\"").unwrap();
+        assert_eq!(value, string("2024-01-01T12:30:00Z"));
+        assert_eq!(
+            serde_json::to_string(&value).unwrap(),
+            "\"2024-01-01T12:30:00Z\""
+        );
+    }
+
+    #[test]
+    fn stored_values_keep_their_type() {
+        let timestamp = "2024-01-01T12:30:00+02:00";
+        let metadata = Metadata::from([
+            ("when".to_string(), MetadataValue::infer(timestamp)),
+            ("text".to_string(), string(timestamp)),
+            ("count".to_string(), MetadataValue::Int(3)),
+            (
+                "nested".to_string(),
+                MetadataValue::List(vec![MetadataValue::infer(timestamp)]),
+            ),
+        ]);
+        let stored: Metadata = decode(&encode(&metadata).unwrap()).unwrap();
+        assert_eq!(stored, metadata);
+        assert!(matches!(stored["when"], MetadataValue::Timestamp(_)));
+        assert_eq!(
+            serde_json::to_value(&stored["when"]).unwrap(),
+            serde_json::json!(timestamp)
+        );
+    }
+
+    #[test]
+    fn migrates_nested_strings() {
+        let mut metadata = Metadata::from([
+            ("count".to_string(), string("3")),
+            (
+                "list".to_string(),
+                MetadataValue::List(vec![string("true"), string("x")]),
+            ),
+        ]);
+        migrate(&mut metadata);
+        assert_eq!(metadata["count"], MetadataValue::Int(3));
+        assert_eq!(
+            metadata["list"],
+            MetadataValue::List(vec![MetadataValue::Bool(true), string("x")])
+        );
+    }
+}
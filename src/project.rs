@@ -1,21 +1,44 @@
+use chrono::Utc;
 use fnmatch_regex::glob_to_regex;
+use futures_util::Stream;
+use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
+use crate::archive::{external_archive_path, unpack, ArchiveWriter, Compression};
+use crate::audit::{AuditAction, AuditEntry, AuditLog};
+use crate::batch::{BatchOperation, OperationResult};
+use crate::diff::{diff, diff_metadata, DiffEntries, DiffEntry, DiffSource, ProjectDiff};
 use crate::errors::{GodataError, GodataErrorType, Result};
-use crate::fsystem::{is_empty, FileSystem};
+use crate::events::{Change, Delivery, EventBus, Operation, Publisher};
+use crate::fsystem::{is_empty, read_tree, validate_tree, FileSystem, SnapshotInfo};
+use crate::hooks::{Action, DeliveryAttempt, Hook, HookRunner, Hooks};
+use crate::lease::{Lease, Leases};
+use crate::lineage::{
+    self, Checksums, Lineage, LineageQuery, Node, Provenance, ProvenanceRequest, Staleness,
+};
 use crate::locations::{
-    create_project_dir, delete_project_dir, load_collection_dir, load_project_dir,
+    create_project_dir, delete_project_dir, load_collection_dir, load_project_dir, path_to_string,
 };
+use crate::manifest::{checksum, Manifest, ManifestFile, ManifestFolder, MANIFEST_VERSION};
+use crate::metadata::Metadata;
+use crate::revision::IfMatch;
 use crate::storage::{LocalEndpoint, StorageEndpoint, StorageManager};
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use crate::sync::{lock, read, write};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use uuid::Uuid;
 
 pub struct Project {
     pub(crate) tree: FileSystem,
+    pub(crate) leases: Leases,
+    pub(crate) hooks: Hooks,
+    pub(crate) audit: AuditLog,
+    events: Publisher,
     _name: String,
     _collection: String,
-    _endpoint: Box<dyn StorageEndpoint + Send>,
+    _endpoint: Box<dyn StorageEndpoint + Send + Sync>,
 }
 
 impl Project {
@@ -24,84 +47,229 @@ impl Project {
         &mut self,
         project_path: &str,
         real_path: PathBuf,
-        metadata: HashMap<String, String>,
+        metadata: Metadata,
+        provenance: Option<ProvenanceRequest>,
+        checksums: &Checksums,
         overwrite: bool,
     ) -> Result<Option<Vec<String>>> {
-        let relpath = self._endpoint.get_relative_path(&real_path);
-        let previous_entry = self
-            .tree
-            .insert(project_path, relpath, metadata, overwrite)?;
-        if previous_entry.is_none() {
-            return Ok(None);
-        }
-        let previous_entries = previous_entry.unwrap();
-        if previous_entries.is_empty() {
-            return Ok(None);
-        }
-        let output: Vec<String> = previous_entries
-            .into_iter()
-            .map(|x| self._endpoint.resolve(&x.real_path))
-            .filter(|x| self._endpoint.is_internal(x))
-            .map(|x| x.to_str().unwrap().to_string())
-            .collect();
+        self.change(|project| {
+            let relpath = project._endpoint.get_relative_path(&real_path);
+            let mut provenance = provenance
+                .map(|provenance| provenance.resolve(&project.tree, project_path))
+                .transpose()?;
+            for input in provenance.iter_mut().flat_map(|p| p.inputs.iter_mut()) {
+                input.checksum = project
+                    .file_path(&input.project_path)
+                    .and_then(|path| checksums.get(&path));
+            }
+            let before = project.state(project_path);
+            let previous = match project.tree.get(project_path) {
+                Ok(file) => file.metadata.clone(),
+                Err(_) => Metadata::new(),
+            };
+            let change = Change::new(Operation::Link, project_path)
+                .with_real_path(Some(path_to_string(&project._endpoint.resolve(&relpath))?))
+                .with_metadata(diff_metadata(&previous, &metadata));
+            let previous_entry =
+                project
+                    .tree
+                    .insert(project_path, relpath, metadata, provenance, overwrite)?;
+            let revision = project.tree.revision(project_path).ok();
+            project.publish(change.with_revision(revision));
+            project.record(AuditAction::Link, project_path, before)?;
+            let previous_entries = match previous_entry {
+                Some(entries) if !entries.is_empty() => entries,
+                _ => return Ok(None),
+            };
+            let output = previous_entries
+                .into_iter()
+                .map(|x| project._endpoint.resolve(&x.real_path))
+                .filter(|x| project._endpoint.is_internal(x))
+                .map(|x| path_to_string(&x))
+                .collect::<Result<Vec<String>>>()?;
 
-        Ok(Some(output))
+            Ok(Some(output))
+        })
     }
 
     #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
     pub(crate) fn duplicate_tree(&mut self, output_path: PathBuf) -> Result<()> {
         let export = self.tree.export()?;
-        let db = sled::open(output_path);
-        if db.is_err() {
-            let err = db.err().unwrap();
-            tracing::error!("Sled failed to open database, error: {:?}", err);
-            return Err(err.into());
-        }
-        let db = db.unwrap();
+        let db = match sled::open(output_path) {
+            Ok(db) => db,
+            Err(err) => {
+                tracing::error!("Sled failed to open database, error: {:?}", err);
+                return Err(err.into());
+            }
+        };
         db.import(export);
+        db.flush()?;
         Ok(())
     }
 
     #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
-    pub(crate) fn add_folder(
+    pub(crate) fn write_archive(
         &mut self,
-        project_path: &str,
-        real_path: PathBuf,
-        recursive: bool,
+        output_path: &Path,
+        compression: Compression,
+        include_external: bool,
     ) -> Result<()> {
-        let mut folders: Vec<PathBuf> = Vec::new();
-        let files = std::fs::read_dir(real_path)?
-            .filter(|x| x.is_ok())
-            .filter_map(|x| {
-                let path = x.unwrap().path();
-                if path.is_file() {
-                    Some(path)
-                } else {
-                    if recursive {
-                        folders.push(path);
-                    }
-                    None
-                }
-            });
-        self.tree.insert_many(files, project_path)?;
-        if recursive {
-            for folder in folders {
-                let folder_name = folder.file_name().unwrap().to_str().unwrap().to_string();
-                let folder_project_path = format!("{}/{}", project_path, folder_name);
-                self.add_folder(&folder_project_path, folder, recursive)?;
+        // The tree is copied to a staging area so external paths can be rewritten
+        // without touching the live project.
+        let staging_dir = std::env::temp_dir().join(format!("godata-export-{}", Uuid::new_v4()));
+        let tree_path = staging_dir.join(".tree");
+        self.duplicate_tree(tree_path.clone())?;
+        let result =
+            self.write_archive_from(&tree_path, output_path, compression, include_external);
+        if let Err(e) = std::fs::remove_dir_all(&staging_dir) {
+            tracing::warn!("Failed to remove staging directory: {}", e);
+        }
+        if result.is_err() && output_path.exists() {
+            let _ = std::fs::remove_file(output_path);
+        }
+        result
+    }
+
+    fn write_archive_from(
+        &self,
+        tree_path: &Path,
+        output_path: &Path,
+        compression: Compression,
+        include_external: bool,
+    ) -> Result<()> {
+        let mut writer = ArchiveWriter::create(output_path, compression)?;
+        let mut tree = FileSystem::load(&self._name, tree_path.to_path_buf())?;
+        let files: Vec<(String, PathBuf)> = tree
+            .walk()
+            .into_iter()
+            .map(|(vpath, f)| (vpath, self._endpoint.resolve(&f.real_path)))
+            .collect();
+
+        let mut stored: HashSet<PathBuf> = HashSet::new();
+        for (vpath, real_path) in files {
+            let archive_path = if self._endpoint.is_internal(&real_path) {
+                self._endpoint.get_relative_path(&real_path)
+            } else if include_external {
+                let archive_path = external_archive_path(&real_path);
+                tree.set_real_path(&vpath, archive_path.clone())?;
+                archive_path
+            } else {
+                tracing::warn!(
+                    "File at `{}` is external to the project and will not be archived",
+                    vpath
+                );
+                continue;
+            };
+            if stored.insert(archive_path.clone()) {
+                writer.append_file(&archive_path, &real_path)?;
             }
         }
+        tree.flush()?;
+        drop(tree);
+        writer.append_dir(Path::new(".tree"), tree_path)?;
+        writer.finish()
+    }
 
-        Ok(())
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn manifest(&self, checksums: bool) -> Result<Manifest> {
+        let mut folders: Vec<ManifestFolder> = self
+            .tree
+            .walk_folders()
+            .into_iter()
+            .map(|(path, metadata)| ManifestFolder {
+                path,
+                metadata: metadata.clone(),
+            })
+            .collect();
+
+        let mut files = Vec::new();
+        for (path, file) in self.tree.walk() {
+            let real_path = self._endpoint.resolve(&file.real_path);
+            let checksum = if !checksums {
+                None
+            } else if real_path.is_file() {
+                Some(checksum(&real_path)?)
+            } else {
+                tracing::warn!("File for `{}` does not exist, skipping checksum", path);
+                None
+            };
+            files.push(ManifestFile {
+                path,
+                real_path: path_to_string(&file.real_path)?,
+                metadata: file.metadata.clone(),
+                checksum,
+            });
+        }
+        // Sorted so that manifests of the same tree are identical and diff cleanly
+        folders.sort_by(|a, b| a.path.cmp(&b.path));
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(Manifest {
+            version: MANIFEST_VERSION,
+            project: self._name.clone(),
+            collection: self._collection.clone(),
+            created: Utc::now().to_rfc3339(),
+            folders,
+            files,
+        })
+    }
+
+    pub(crate) fn diff_entries(&self, snapshot: Option<&str>) -> Result<DiffEntries> {
+        let view;
+        let files = match snapshot {
+            None => self.tree.walk(),
+            Some(name) => {
+                view = self.tree.open_snapshot(name)?;
+                view.walk()
+            }
+        };
+        let entries = files
+            .into_iter()
+            .map(|(path, f)| {
+                let entry = DiffEntry {
+                    real_path: self._endpoint.resolve(&f.real_path),
+                    metadata: f.metadata.clone(),
+                };
+                (path, entry)
+            })
+            .collect();
+        Ok(entries)
     }
 
     #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
-    pub(crate) fn get_file(&self, project_path: &str) -> Result<HashMap<String, String>> {
-        let file = self.tree.get(project_path)?;
+    pub(crate) fn add_folder(&mut self, project_path: &str, folder: FolderScan) -> Result<()> {
+        self.change(|project| {
+            let before = project.state(project_path);
+            let change = Change::new(Operation::Link, project_path)
+                .with_real_path(Some(path_to_string(&folder.path)?));
+            project
+                .tree
+                .insert_many(folder.files.into_iter(), project_path)?;
+            let revision = project.tree.revision(project_path).ok();
+            project.publish(change.with_revision(revision));
+            project.record(AuditAction::Link, project_path, before)?;
+            for (folder_name, subfolder) in folder.folders {
+                let folder_project_path = format!("{}/{}", project_path, folder_name);
+                project.add_folder(&folder_project_path, subfolder)?;
+            }
+            Ok(())
+        })
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn get_file(&self, project_path: &str, snapshot: Option<&str>) -> Result<Metadata> {
+        let view;
+        let file = match snapshot {
+            None => self.tree.get(project_path)?,
+            Some(name) => {
+                view = self.tree.open_snapshot(name)?;
+                view.get(project_path)?
+            }
+        };
         let fpath = self._endpoint.resolve(&file.real_path);
         let mut meta = file.metadata.clone();
 
-        meta.insert("real_path".to_string(), fpath.to_str().unwrap().to_string());
+        meta.insert("real_path".to_string(), path_to_string(&fpath)?.into());
 
         Ok(meta)
     }
@@ -111,7 +279,7 @@ impl Project {
         &self,
         folder_path: Option<&str>,
         pattern: &str,
-    ) -> Result<HashMap<String, HashMap<String, String>>> {
+    ) -> Result<HashMap<String, Metadata>> {
         let pattern = glob_to_regex(pattern)?;
         let matching_files = self.tree.get_many(folder_path, &pattern)?;
 
@@ -120,34 +288,160 @@ impl Project {
             .map(|f| {
                 let mut meta = f.metadata.clone();
                 let real_path = self._endpoint.resolve(&f.real_path);
-                meta.insert(
-                    "real_path".to_string(),
-                    real_path.to_str().unwrap().to_string(),
-                );
-                (f.name.clone(), meta)
+                meta.insert("real_path".to_string(), path_to_string(&real_path)?.into());
+                Ok((f.name.clone(), meta))
             })
-            .collect::<HashMap<_, _>>();
+            .collect::<Result<HashMap<_, _>>>()?;
         Ok(results)
     }
 
+    pub(crate) fn revision(
+        &self,
+        project_path: Option<&str>,
+        snapshot: Option<&str>,
+    ) -> Result<u64> {
+        let project_path = project_path.unwrap_or_default();
+        match snapshot {
+            None => self.tree.revision(project_path),
+            Some(name) => self.tree.open_snapshot(name)?.revision(project_path),
+        }
+    }
+
+    pub(crate) fn check_revision(
+        &self,
+        project_path: &str,
+        if_match: Option<&IfMatch>,
+    ) -> Result<()> {
+        // Fail unless the entry at the path matches the `If-Match` condition, if any
+        let if_match = match if_match {
+            Some(if_match) => if_match,
+            None => return Ok(()),
+        };
+        let current = match self.tree.revision(project_path) {
+            Ok(revision) => Some(revision),
+            Err(e) if e.error_type == GodataErrorType::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        if_match.check(project_path, current)
+    }
+
     pub(crate) fn list(
         &self,
         project_path: Option<String>,
+        snapshot: Option<&str>,
     ) -> Result<HashMap<String, Vec<String>>> {
-        let list = self.tree.list(project_path)?;
+        let list = match snapshot {
+            None => self.tree.list(project_path)?,
+            Some(name) => self.tree.open_snapshot(name)?.list(project_path)?,
+        };
         Ok(list)
     }
 
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn create_snapshot(&mut self, name: &str) -> Result<SnapshotInfo> {
+        let info = self.tree.create_snapshot(name)?;
+        self.audit.record(
+            AuditEntry::new(AuditAction::CreateSnapshot, None)
+                .with_after(serde_json::to_value(&info).ok()),
+        )?;
+        Ok(info)
+    }
+
+    pub(crate) fn list_snapshots(&self) -> Result<Vec<SnapshotInfo>> {
+        self.tree.list_snapshots()
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn restore_snapshot(&mut self, name: &str) -> Result<()> {
+        let before = self.state("");
+        self.tree.restore_snapshot(name)?;
+        self.audit.record(
+            AuditEntry::new(AuditAction::RestoreSnapshot, None)
+                .with_before(before)
+                .with_after(Some(serde_json::json!({ "snapshot": name }))),
+        )
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn delete_snapshot(&mut self, name: &str) -> Result<()> {
+        self.tree.delete_snapshot(name)?;
+        self.audit.record(
+            AuditEntry::new(AuditAction::DeleteSnapshot, None)
+                .with_before(Some(serde_json::json!({ "snapshot": name }))),
+        )
+    }
+
+    pub(crate) fn acquire_lease(
+        &mut self,
+        project_path: &str,
+        holder: String,
+        ttl: Option<u64>,
+    ) -> Result<Lease> {
+        let lease = self.leases.acquire(project_path, holder, ttl)?;
+        self.record_lease(AuditAction::AcquireLease, &lease)?;
+        Ok(lease)
+    }
+
+    pub(crate) fn renew_lease(&mut self, id: &str, ttl: Option<u64>) -> Result<Lease> {
+        let lease = self.leases.renew(id, ttl)?;
+        self.record_lease(AuditAction::RenewLease, &lease)?;
+        Ok(lease)
+    }
+
+    pub(crate) fn release_lease(&mut self, id: &str) -> Result<()> {
+        let lease = self.leases.release(id)?;
+        self.record_lease(AuditAction::ReleaseLease, &lease)
+    }
+
+    fn record_lease(&mut self, action: AuditAction, lease: &Lease) -> Result<()> {
+        // Lease IDs act as credentials, so only what everyone can see is recorded
+        let state = serde_json::to_value(lease.info()).ok();
+        let entry = AuditEntry::new(action, Some(&lease.project_path));
+        let entry = match action {
+            AuditAction::ReleaseLease => entry.with_before(state),
+            _ => entry.with_after(state),
+        };
+        self.audit.record(entry)
+    }
+
+    pub(crate) fn add_hook(
+        &mut self,
+        pattern: String,
+        operations: Option<Vec<Operation>>,
+        action: Action,
+    ) -> Result<Hook> {
+        let hook = self.hooks.add(pattern, operations, action)?;
+        self.audit.record(
+            AuditEntry::new(AuditAction::AddHook, None)
+                .with_after(serde_json::to_value(&hook).ok()),
+        )?;
+        Ok(hook)
+    }
+
+    pub(crate) fn remove_hook(&mut self, id: &str) -> Result<()> {
+        let hook = self.hooks.remove(id)?;
+        self.audit.record(
+            AuditEntry::new(AuditAction::RemoveHook, None)
+                .with_before(serde_json::to_value(&hook).ok()),
+        )
+    }
+
     #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
     pub(crate) fn remove_file(&mut self, project_path: &str) -> Result<Vec<PathBuf>> {
-        let removed_internal_paths = self.tree.remove(project_path)?;
-        // filter out paths that are not internal
-        let need_to_remove: Vec<PathBuf> = removed_internal_paths
-            .into_iter()
-            .map(|x| self._endpoint.resolve(&x.real_path))
-            .filter(|x| self._endpoint.is_internal(x))
-            .collect();
-        Ok(need_to_remove)
+        self.change(|project| {
+            let real_path = project.real_path(project_path);
+            let before = project.state(project_path);
+            let removed_internal_paths = project.tree.remove(project_path)?;
+            project.publish(Change::new(Operation::Remove, project_path).with_real_path(real_path));
+            project.record(AuditAction::Remove, project_path, before)?;
+            // filter out paths that are not internal
+            let need_to_remove: Vec<PathBuf> = removed_internal_paths
+                .into_iter()
+                .map(|x| project._endpoint.resolve(&x.real_path))
+                .filter(|x| project._endpoint.is_internal(x))
+                .collect();
+            Ok(need_to_remove)
+        })
     }
 
     #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
@@ -157,18 +451,316 @@ impl Project {
         to: &str,
         overwrite: bool,
     ) -> Result<Option<Vec<String>>> {
-        let result = self.tree.move_(from, to, overwrite)?;
-        if result.is_none() {
-            return Ok(None);
+        self.change(|project| {
+            let real_path = project.real_path(from);
+            let before = project.state(from);
+            let result = project.tree.move_(from, to, overwrite)?;
+            let change = Change::new(Operation::Move, to)
+                .with_source(from)
+                .with_real_path(real_path)
+                .with_revision(project.tree.revision(to).ok());
+            project.publish(change);
+            project.audit.record(
+                AuditEntry::new(AuditAction::Move, Some(to))
+                    .with_source(from)
+                    .with_before(before)
+                    .with_after(project.state(to)),
+            )?;
+            let result = match result {
+                Some(result) => result,
+                None => return Ok(None),
+            };
+            let moved = result
+                .into_iter()
+                .map(|x| project._endpoint.resolve(&x.real_path))
+                .filter(|x| project._endpoint.is_internal(x))
+                .map(|x| path_to_string(&x))
+                .collect::<Result<Vec<String>>>()?;
+            Ok(Some(moved))
+        })
+    }
+
+    #[instrument(skip(self, operations), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn apply_batch(
+        &mut self,
+        operations: Vec<BatchOperation>,
+        atomic: bool,
+        lease: Option<&str>,
+        checksums: &Checksums,
+    ) -> Result<Vec<OperationResult>> {
+        // The whole batch is one transaction. A failed operation has already undone its
+        // own changes (see `change`), so a batch that carries on never keeps a part of it.
+        self.begin();
+        let mut results = Vec::with_capacity(operations.len());
+        for (index, operation) in operations.into_iter().enumerate() {
+            match self.apply_operation(operation, lease, checksums) {
+                Ok(removed) => results.push(OperationResult::Applied { removed }),
+                Err(e) if atomic => {
+                    self.abort();
+                    tracing::info!("Operation {} failed, abandoning batch", index);
+                    return Err(GodataError::new(
+                        e.error_type,
+                        format!(
+                            "Operation {} failed, no operations were applied: {}",
+                            index, e.message
+                        ),
+                    )
+                    .with_code("batch_failed")
+                    .with_details(serde_json::json!({"index": index, "error": e})));
+                }
+                Err(e) => results.push(OperationResult::Failed { error: e }),
+            }
+        }
+        self.commit()?;
+        Ok(results)
+    }
+
+    // Applies a change to the tree as one transaction. The tree and the audit entries for
+    // the change are saved together, and its events only go out once both are, so a
+    // change that fails at any point leaves nothing behind. Changes made while a batch is
+    // being applied are part of the batch's transaction instead.
+    fn change<T>(&mut self, f: impl FnOnce(&mut Project) -> Result<T>) -> Result<T> {
+        if self.audit.is_held() {
+            return f(self);
+        }
+        self.begin();
+        match f(self) {
+            Ok(value) => {
+                self.commit()?;
+                Ok(value)
+            }
+            Err(e) => {
+                self.abort();
+                Err(e)
+            }
+        }
+    }
+
+    fn begin(&mut self) {
+        self.tree.begin();
+        self.events.hold();
+        self.audit.hold();
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let tree = &self.tree;
+        let entries = match self.audit.stage(|path| tree.pending_revision(path).ok()) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.abort();
+                return Err(e);
+            }
+        };
+        let journal = self.audit.tree().clone();
+        if let Err(e) = self.tree.commit_with(Some((&journal, entries))) {
+            // The tree has already been rolled back
+            self.events.discard();
+            self.audit.discard();
+            return Err(e);
+        }
+        self.audit.committed();
+        let tree = &self.tree;
+        for event in self.events.release(|path| tree.revision(path).ok()) {
+            self.hooks.dispatch(&event);
+        }
+        Ok(())
+    }
+
+    fn abort(&mut self) {
+        self.tree.abort();
+        self.events.discard();
+        self.audit.discard();
+    }
+
+    fn apply_operation(
+        &mut self,
+        operation: BatchOperation,
+        lease: Option<&str>,
+        checksums: &Checksums,
+    ) -> Result<Vec<String>> {
+        if let Some(revision) = operation.revision() {
+            self.check_revision(operation.target_path(), Some(&revision.into()))?;
+        }
+        if let BatchOperation::Link { project_path, .. } = &operation {
+            self.leases.check(project_path, lease)?;
+        }
+        match operation {
+            BatchOperation::Link {
+                project_path,
+                real_path,
+                metadata,
+                provenance,
+                force,
+                ..
+            } => self
+                .add_file(
+                    &project_path,
+                    PathBuf::from(real_path),
+                    metadata,
+                    provenance,
+                    checksums,
+                    force,
+                )
+                .map(Option::unwrap_or_default),
+            BatchOperation::Move {
+                source_path,
+                destination_path,
+                overwrite,
+                ..
+            } => self
+                .move_(&source_path, &destination_path, overwrite)
+                .map(Option::unwrap_or_default),
+            BatchOperation::Remove { project_path, .. } => self
+                .remove_file(&project_path)?
+                .iter()
+                .map(|path| path_to_string(path))
+                .collect(),
+            BatchOperation::Metadata {
+                project_path,
+                metadata,
+                replace,
+                ..
+            } => {
+                let state = self.state(&project_path);
+                let (before, after) =
+                    self.tree
+                        .update_metadata(&project_path, metadata, replace)?;
+                let change = Change::new(Operation::Metadata, &project_path)
+                    .with_metadata(diff_metadata(&before, &after))
+                    .with_revision(self.tree.revision(&project_path).ok());
+                self.publish(change);
+                self.record(AuditAction::Metadata, &project_path, state)?;
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    fn publish(&mut self, change: Change) {
+        // Held back while a change is being applied, its events go out when it commits
+        if let Some(event) = self.events.publish(change) {
+            self.hooks.dispatch(&event);
+        }
+    }
+
+    fn record(
+        &mut self,
+        action: AuditAction,
+        project_path: &str,
+        before: Option<serde_json::Value>,
+    ) -> Result<()> {
+        let after = self.state(project_path);
+        self.audit.record(
+            AuditEntry::new(action, Some(project_path))
+                .with_before(before)
+                .with_after(after),
+        )
+    }
+
+    // What a path looks like, as recorded in the audit trail
+    fn state(&self, project_path: &str) -> Option<serde_json::Value> {
+        let revision = self.tree.revision(project_path).ok()?;
+        match self.tree.get(project_path) {
+            Ok(file) => Some(serde_json::json!({
+                "type": "file",
+                "real_path": path_to_string(&self._endpoint.resolve(&file.real_path)).ok(),
+                "metadata": file.metadata,
+                "provenance": file.provenance,
+                "revision": revision,
+            })),
+            Err(_) => Some(serde_json::json!({ "type": "folder", "revision": revision })),
+        }
+    }
+
+    pub(crate) fn lineage(&self, query: &LineageQuery) -> Result<Lineage> {
+        let files = self.tree.walk();
+        let produced: HashMap<String, &Provenance> = files
+            .iter()
+            .filter_map(|(path, file)| Some((path.clone(), file.provenance.as_deref()?)))
+            .collect();
+        let (paths, edges) = lineage::walk(&produced, query);
+        if let Some(path) = &query.project_path {
+            // A removed input is still part of the graph, anything else has to exist
+            if edges.is_empty() {
+                self.tree.revision(path.trim_matches('/'))?;
+            }
         }
-        let result = result.unwrap();
-        let moved: Vec<String> = result
+        let nodes = paths
             .into_iter()
-            .map(|x| self._endpoint.resolve(&x.real_path))
-            .filter(|x| self._endpoint.is_internal(x))
-            .map(|x| x.to_str().unwrap().to_string())
+            .map(|path| {
+                let revision = self.tree.revision(&path).ok();
+                Node {
+                    exists: revision.is_some(),
+                    revision,
+                    real_path: self.real_path(&path),
+                    provenance: produced.get(&path).map(|provenance| (*provenance).clone()),
+                    project_path: path,
+                }
+            })
+            .collect();
+        Ok(Lineage {
+            nodes,
+            edges: edges.into_iter().collect(),
+        })
+    }
+
+    // Contents are only compared with `checksums`, as that means reading every input
+    pub(crate) fn staleness(&self, prefix: Option<&str>, checksums: bool) -> Staleness {
+        let files = self.tree.walk();
+        let produced: HashMap<String, &Provenance> = files
+            .iter()
+            .filter_map(|(path, file)| Some((path.clone(), file.provenance.as_deref()?)))
             .collect();
-        Ok(Some(moved))
+        lineage::stale(
+            &produced,
+            prefix,
+            |path| self.tree.revision(path).ok(),
+            |input| match (&input.checksum, checksums) {
+                (Some(recorded), true) => {
+                    self.checksum(&input.project_path).as_ref() != Some(recorded)
+                }
+                _ => false,
+            },
+        )
+    }
+
+    fn checksum(&self, project_path: &str) -> Option<String> {
+        // The checksum of the contents of a file, or nothing for folders and unreadable files
+        checksum(&self.file_path(project_path)?).ok()
+    }
+
+    // The real paths of the inputs of files about to be linked, to be hashed before the
+    // project is locked for writing. An input that is linked in the same request is
+    // hashed at its new real path.
+    pub(crate) fn input_paths(
+        &self,
+        links: &[(&str, &Path, Option<&ProvenanceRequest>)],
+    ) -> Vec<PathBuf> {
+        let linked: HashMap<&str, &Path> = links
+            .iter()
+            .map(|(project_path, real_path, _)| (project_path.trim_matches('/'), *real_path))
+            .collect();
+        links
+            .iter()
+            .filter_map(|(_, _, provenance)| *provenance)
+            .flat_map(|provenance| provenance.inputs())
+            .filter_map(|input| match linked.get(input) {
+                Some(real_path) => Some(
+                    self._endpoint
+                        .resolve(&self._endpoint.get_relative_path(real_path)),
+                ),
+                None => self.file_path(input),
+            })
+            .collect()
+    }
+
+    fn file_path(&self, project_path: &str) -> Option<PathBuf> {
+        // The resolved real path of a file, or nothing for folders and missing paths
+        let file = self.tree.get(project_path).ok()?;
+        Some(self._endpoint.resolve(&file.real_path))
+    }
+
+    fn real_path(&self, project_path: &str) -> Option<String> {
+        path_to_string(&self.file_path(project_path)?).ok()
     }
 
     pub(crate) fn exists(&self, project_path: String) -> bool {
@@ -177,35 +769,130 @@ impl Project {
 
     pub(crate) fn generate_path(&self, project_path: &str) -> Result<String> {
         let path = self._endpoint.generate_path(project_path)?;
-        Ok(path.to_str().unwrap().to_owned())
+        path_to_string(&path)
     }
 }
 
+// The files in a folder on disk, and with `recursive` the folders below it. Large folders
+// take a while to read, so they are scanned before the project is locked for writing.
+#[derive(Debug)]
+pub(crate) struct FolderScan {
+    path: PathBuf,
+    files: Vec<PathBuf>,
+    folders: Vec<(String, FolderScan)>,
+}
+
+impl FolderScan {
+    pub(crate) fn read(path: PathBuf, recursive: bool) -> Result<FolderScan> {
+        let mut files = Vec::new();
+        let mut folders = Vec::new();
+        for entry in std::fs::read_dir(&path)?.filter_map(|x| x.ok()) {
+            let entry_path = entry.path();
+            if entry_path.is_file() {
+                files.push(entry_path);
+            } else if recursive {
+                let name = path_to_string(Path::new(&entry.file_name()))?;
+                folders.push((name, FolderScan::read(entry_path, recursive)?));
+            }
+        }
+        Ok(FolderScan {
+            path,
+            files,
+            folders,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ConflictStrategy {
+    #[default]
+    Error,
+    Merge,
+    Replace,
+    Rename,
+}
+
+impl ConflictStrategy {
+    pub(crate) fn parse(value: Option<&str>) -> Result<ConflictStrategy> {
+        match value {
+            None | Some("error") => Ok(ConflictStrategy::Error),
+            Some("merge") => Ok(ConflictStrategy::Merge),
+            Some("replace") => Ok(ConflictStrategy::Replace),
+            Some("rename") => Ok(ConflictStrategy::Rename),
+            Some(other) => Err(GodataError::new(
+                GodataErrorType::InvalidArgument,
+                format!(
+                    "Unknown conflict strategy `{}`, expected one of `error`, `merge`, `replace` or `rename`",
+                    other
+                ),
+            ).with_code("invalid_conflict_strategy")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ImportOptions {
+    pub(crate) strategy: ConflictStrategy,
+    // Create the collection if it does not exist
+    pub(crate) force: bool,
+}
+
+enum ImportTarget {
+    New(String),
+    Merge(String),
+    Replace(String),
+}
+
+#[derive(Serialize)]
+pub(crate) struct ImportSummary {
+    pub(crate) project: String,
+    pub(crate) imported: usize,
+    pub(crate) skipped: Vec<String>,
+}
+
 pub fn get_project_manager() -> Result<ProjectManager> {
     let storage_manager = StorageManager::get_manager()?;
     Ok(ProjectManager {
         storage_manager,
-        projects: HashMap::new(),
-        counts: HashMap::new(),
+        events: EventBus::new(),
+        hooks: HookRunner::new(),
+        projects: RwLock::new(HashMap::new()),
+        loading: Mutex::new(()),
     })
 }
 
+// The manager is shared by every request. The map of loaded projects is only locked
+// long enough to find, add or remove a project, and each project has its own
+// read/write lock, so requests for different projects never wait on each other and
+// any number of requests can read the same project at once.
 pub struct ProjectManager {
     storage_manager: StorageManager,
-    projects: HashMap<String, Arc<Mutex<Project>>>,
-    counts: HashMap<String, usize>,
+    projects: RwLock<HashMap<String, LoadedProject>>,
+    // Held while opening a project, so the same database is never opened twice, and
+    // while removing one, so it isn't opened again while its files are deleted
+    loading: Mutex<()>,
+    events: EventBus,
+    hooks: HookRunner,
+}
+
+struct LoadedProject {
+    project: Arc<RwLock<Project>>,
+    // The number of clients that have loaded the project and not dropped it
+    count: AtomicUsize,
 }
 
 impl ProjectManager {
     #[instrument(skip(self))]
     pub fn create_project(
-        &mut self,
+        &self,
         name: &str,
         collection: &str,
         force: bool,
         storage_location: Option<String>,
-    ) -> Result<Arc<Mutex<Project>>> {
+    ) -> Result<Arc<RwLock<Project>>> {
         let key = format!("{}/{}", collection, name);
+        let _loading = lock(&self.loading)?;
         let project_dir = create_project_dir(name, collection, force)?;
         let tree = FileSystem::new(name.to_string(), project_dir)?;
         let base_path = match storage_location {
@@ -216,14 +903,23 @@ impl ProjectManager {
             .add(name, collection, "local", base_path.clone())?;
         let endpoint = LocalEndpoint::new(base_path);
         let p = Project {
+            leases: Leases::load(tree.leases()?)?,
+            hooks: Hooks::load(tree.hooks()?, self.hooks.clone())?,
+            audit: AuditLog::load(tree.audit()?)?,
+            events: self.events.publisher(collection, name)?,
             tree,
             _name: name.to_string(),
             _collection: collection.to_string(),
             _endpoint: Box::new(endpoint),
         };
-        let project = Arc::new(Mutex::new(p));
-        self.projects.insert(key.clone(), project.clone());
-        self.counts.insert(key, 1);
+        let project = Arc::new(RwLock::new(p));
+        write(&self.projects)?.insert(
+            key,
+            LoadedProject {
+                project: project.clone(),
+                count: AtomicUsize::new(1),
+            },
+        );
         Ok(project)
     }
 
@@ -234,117 +930,601 @@ impl ProjectManager {
         collection: &str,
         endpoint: &str,
         path: PathBuf,
-    ) -> Result<PathBuf> {
+        options: ImportOptions,
+    ) -> Result<ImportSummary> {
+        let target = self.plan_import(name, collection, options.strategy)?;
+        self.import_tree(target, collection, endpoint, path, options.force)
+    }
+
+    fn import_tree(
+        &self,
+        target: ImportTarget,
+        collection: &str,
+        endpoint: &str,
+        path: PathBuf,
+        force: bool,
+    ) -> Result<ImportSummary> {
         // The assumption is that the path points to a folder which contains the project data
         // Aditionally, it should contain a .tree folder which contains the tree data
-
-        let project_dir = create_project_dir(name, collection, true)?;
+        // The tree is validated before anything is created, so a bad export never leaves
+        // a half-imported project behind.
         let tree_path = path.join(".tree");
-        let db = sled::open(tree_path)?;
+        let file_count = validate_tree(&tree_path)?;
 
-        let _root = db.get("root").unwrap().unwrap();
+        let target = match target {
+            ImportTarget::Merge(target) => {
+                let entries = tree_entries(&path)?;
+                return self.merge_files(&target, collection, entries, None);
+            }
+            ImportTarget::Replace(target) => {
+                self.replace_tree(&target, collection, endpoint, &tree_path, path)?;
+                target
+            }
+            ImportTarget::New(target) => {
+                let project_dir = create_project_dir(&target, collection, force)?;
+                let result = copy_tree(&tree_path, &project_dir).and_then(|_| {
+                    self.storage_manager
+                        .add(&target, collection, endpoint, path.clone())
+                });
+                if let Err(e) = result {
+                    tracing::error!("Failed to import project tree: {}", e);
+                    let _ = delete_project_dir(&target, collection);
+                    return Err(e);
+                }
+                target
+            }
+        };
+        Ok(ImportSummary {
+            project: target,
+            imported: file_count,
+            skipped: Vec::new(),
+        })
+    }
 
-        let db_export = db.export();
-        let final_db = sled::open(&project_dir)?;
-        final_db.import(db_export);
+    fn replace_tree(
+        &self,
+        name: &str,
+        collection: &str,
+        endpoint: &str,
+        tree_path: &Path,
+        path: PathBuf,
+    ) -> Result<()> {
+        // The tree is imported next to the existing project and only swapped in once it
+        // is complete, so a failed import leaves the project as it was. Only the tree
+        // and storage record are replaced. Data files are never deleted, since they may
+        // be the very files being imported.
+        let project_dir = load_project_dir(name, collection)?;
+        let hidden = |purpose: &str| {
+            project_dir.with_file_name(format!(".{}.{}-{}", name, purpose, Uuid::new_v4()))
+        };
+        let staged = hidden("import");
+        let replaced = hidden("replaced");
+        if let Err(e) = copy_tree(tree_path, &staged) {
+            tracing::error!("Failed to import project tree: {}", e);
+            let _ = std::fs::remove_dir_all(&staged);
+            return Err(e);
+        }
+
+        let _loading = lock(&self.loading)?;
+        self.unload(name, collection)?;
+        let previous = self.storage_manager.get(name, collection)?;
+        let result = self
+            .storage_manager
+            .set(name, collection, endpoint, path)
+            .and_then(|_| Ok(std::fs::rename(&project_dir, &replaced)?))
+            .and_then(|_| match std::fs::rename(&staged, &project_dir) {
+                Ok(()) => Ok(()),
+                Err(e) => {
+                    std::fs::rename(&replaced, &project_dir)?;
+                    Err(e.into())
+                }
+            });
+        if let Err(e) = result {
+            tracing::error!("Failed to replace project tree: {}", e);
+            let _ = self
+                .storage_manager
+                .set(name, collection, &previous.0, previous.1);
+            let _ = std::fs::remove_dir_all(&staged);
+            return Err(e);
+        }
+        if let Err(e) = std::fs::remove_dir_all(&replaced) {
+            tracing::warn!("Failed to remove {}: {}", replaced.display(), e);
+        }
+        Ok(())
+    }
+
+    fn plan_import(
+        &self,
+        name: &str,
+        collection: &str,
+        strategy: ConflictStrategy,
+    ) -> Result<ImportTarget> {
+        // Decide where an import should go, without changing anything
+        if load_project_dir(name, collection).is_err() {
+            return Ok(ImportTarget::New(name.to_string()));
+        }
+        match strategy {
+            ConflictStrategy::Error => Err(GodataError::new(
+                GodataErrorType::AlreadyExists,
+                format!("Project `{}/{}` already exists", collection, name),
+            )
+            .with_code("project_exists")
+            .with_details(serde_json::json!({"collection": collection, "project": name}))),
+            ConflictStrategy::Merge => Ok(ImportTarget::Merge(name.to_string())),
+            ConflictStrategy::Replace => Ok(ImportTarget::Replace(name.to_string())),
+            ConflictStrategy::Rename => {
+                let target = (1..)
+                    .map(|i| format!("{}-{}", name, i))
+                    .find(|candidate| load_project_dir(candidate, collection).is_err())
+                    .unwrap();
+                tracing::info!("Project `{}` exists, importing as `{}`", name, target);
+                Ok(ImportTarget::New(target))
+            }
+        }
+    }
+
+    #[instrument(skip(self, entries))]
+    fn merge_files(
+        &self,
+        name: &str,
+        collection: &str,
+        entries: Vec<(String, PathBuf, Metadata)>,
+        staging: Option<&Path>,
+    ) -> Result<ImportSummary> {
+        // Add files to an existing project. Paths that already exist are left alone.
+        // Files unpacked into `staging` are moved into the project's storage first.
+        let project = self.load_project(name, collection)?;
+        let mut project = write(&project)?;
+        let mut summary = ImportSummary {
+            project: name.to_string(),
+            imported: 0,
+            skipped: Vec::new(),
+        };
+        for (vpath, real_path, metadata) in entries {
+            if project.exists(vpath.clone()) {
+                summary.skipped.push(vpath);
+                continue;
+            }
+            let real_path = match staging.and_then(|staging| real_path.strip_prefix(staging).ok()) {
+                Some(relative) => {
+                    let destination = free_path(PathBuf::from(
+                        project.generate_path(&path_to_string(relative)?)?,
+                    ));
+                    move_file(&real_path, &destination)?;
+                    destination
+                }
+                None => real_path,
+            };
+            match project.add_file(
+                &vpath,
+                real_path,
+                metadata,
+                None,
+                &Checksums::default(),
+                false,
+            ) {
+                Ok(_) => summary.imported += 1,
+                Err(e) if e.error_type == GodataErrorType::AlreadyExists => {
+                    summary.skipped.push(vpath)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(summary)
+    }
 
-        self.storage_manager.add(name, collection, endpoint, path)?;
-        Ok(project_dir)
+    #[instrument(skip(self))]
+    pub fn export_project(&self, name: &str, collection: &str, output_path: PathBuf) -> Result<()> {
+        let output_tree_path = output_path.join(".tree");
+        let project = self.load_project(name, collection)?;
+        let mut project = write(&project)?;
+        project.duplicate_tree(output_tree_path)?;
+        Ok(())
     }
 
     #[instrument(skip(self))]
-    pub fn export_project(
-        &mut self,
+    pub fn export_project_archive(
+        &self,
         name: &str,
         collection: &str,
         output_path: PathBuf,
+        compression: Compression,
+        include_external: bool,
     ) -> Result<()> {
-        let output_tree_path = output_path.join(".tree");
         let project = self.load_project(name, collection)?;
-        let mut project = project.lock().unwrap();
-        project.duplicate_tree(output_tree_path)?;
+        let mut project = write(&project)?;
+        project.write_archive(&output_path, compression, include_external)
+    }
+
+    #[instrument(skip(self))]
+    pub fn import_project_archive(
+        &self,
+        name: &str,
+        collection: &str,
+        archive_path: PathBuf,
+        storage_location: Option<String>,
+        options: ImportOptions,
+    ) -> Result<ImportSummary> {
+        // An archive for a new project is unpacked into its storage directory, which is
+        // then imported like any other exported project folder. One for an existing
+        // project is unpacked into a staging directory, and its files are moved into the
+        // project from there.
+        let target = match self.plan_import(name, collection, options.strategy)? {
+            ImportTarget::New(target) => target,
+            ImportTarget::Merge(target) => {
+                if storage_location.is_some() {
+                    return Err(GodataError::invalid_argument(
+                        "storage_location",
+                        &storage_location.unwrap_or_default(),
+                        "Files merged into an existing project go to its own storage".to_string(),
+                    ));
+                }
+                return self.import_staged(&target, collection, &archive_path, false, None);
+            }
+            ImportTarget::Replace(target) => {
+                return self.import_staged(
+                    &target,
+                    collection,
+                    &archive_path,
+                    true,
+                    storage_location,
+                );
+            }
+        };
+        let storage_dir = match storage_location {
+            Some(path) => PathBuf::from(path),
+            None => crate::locations::get_default_project_storage_dir(&target, collection)?,
+        };
+        if storage_dir.exists() && std::fs::read_dir(&storage_dir)?.next().is_some() {
+            return Err(GodataError::new(
+                GodataErrorType::Conflict,
+                format!(
+                    "Storage location `{}` already exists and is not empty, choose a different storage_location",
+                    storage_dir.display()
+                ),
+            ).with_code("storage_not_empty"));
+        }
+        std::fs::create_dir_all(&storage_dir)?;
+        let result = unpack(&archive_path, &storage_dir).and_then(|_| {
+            self.import_tree(
+                ImportTarget::New(target),
+                collection,
+                "local",
+                storage_dir.clone(),
+                options.force,
+            )
+        });
+        match result {
+            Ok(summary) => {
+                std::fs::remove_dir_all(storage_dir.join(".tree"))?;
+                Ok(summary)
+            }
+            Err(e) => {
+                tracing::error!("Failed to import archive: {}", e);
+                let _ = std::fs::remove_dir_all(&storage_dir);
+                Err(e)
+            }
+        }
+    }
+
+    fn import_staged(
+        &self,
+        target: &str,
+        collection: &str,
+        archive_path: &Path,
+        replace: bool,
+        storage_location: Option<String>,
+    ) -> Result<ImportSummary> {
+        // Merge an archive into an existing project, or with `replace` into a new project
+        // that takes its place
+        let staging = std::env::temp_dir().join(format!("godata-import-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&staging)?;
+        let result = unpack(archive_path, &staging).and_then(|_| {
+            validate_tree(&staging.join(".tree"))?;
+            let entries = tree_entries(&staging)?;
+            if replace {
+                self.abandon_project(target, collection)?;
+                self.create_project(target, collection, true, storage_location)?;
+            }
+            self.merge_files(target, collection, entries, Some(&staging))
+        });
+        if let Err(e) = std::fs::remove_dir_all(&staging) {
+            tracing::warn!("Failed to remove {}: {}", staging.display(), e);
+        }
+        result
+    }
+
+    #[instrument(skip(self))]
+    pub fn export_project_manifest(
+        &self,
+        name: &str,
+        collection: &str,
+        output_path: PathBuf,
+        checksums: bool,
+    ) -> Result<()> {
+        let project = self.load_project(name, collection)?;
+        let project = read(&project)?;
+        project.manifest(checksums)?.write(&output_path)
+    }
+
+    #[instrument(skip(self))]
+    pub fn import_project_manifest(
+        &self,
+        name: &str,
+        collection: &str,
+        manifest_path: PathBuf,
+        storage_location: Option<String>,
+        verify: bool,
+        options: ImportOptions,
+    ) -> Result<ImportSummary> {
+        // Relative paths in the manifest are resolved against the storage location,
+        // which defaults to the folder containing the manifest.
+        let manifest = Manifest::read(&manifest_path)?;
+        let storage_root = match storage_location {
+            Some(path) => PathBuf::from(path),
+            None => manifest_path
+                .parent()
+                .map(|p| p.to_path_buf())
+                .unwrap_or_default(),
+        };
+        let resolve = |real_path: &str| -> PathBuf {
+            let real_path = PathBuf::from(real_path);
+            if real_path.is_absolute() {
+                real_path
+            } else {
+                storage_root.join(real_path)
+            }
+        };
+
+        if verify {
+            let mut mismatched = Vec::new();
+            for file in manifest.files.iter() {
+                let expected = match &file.checksum {
+                    Some(c) => c,
+                    None => continue,
+                };
+                let real_path = resolve(&file.real_path);
+                if !real_path.is_file() || &checksum(&real_path)? != expected {
+                    mismatched.push(file.path.clone());
+                }
+            }
+            if !mismatched.is_empty() {
+                return Err(GodataError::new(
+                    GodataErrorType::InvalidPath,
+                    format!(
+                        "Files are missing or do not match their checksums: {}",
+                        mismatched.join(", ")
+                    ),
+                )
+                .with_code("checksum_mismatch"));
+            }
+        }
+
+        let (target, create_collection) =
+            match self.plan_import(name, collection, options.strategy)? {
+                ImportTarget::Merge(target) => {
+                    let entries = manifest
+                        .files
+                        .iter()
+                        .map(|f| (f.path.clone(), resolve(&f.real_path), f.metadata.clone()))
+                        .collect();
+                    return self.merge_files(&target, collection, entries, None);
+                }
+                ImportTarget::Replace(target) => {
+                    self.abandon_project(&target, collection)?;
+                    (target, true)
+                }
+                ImportTarget::New(target) => (target, options.force),
+            };
+
+        let project = self.create_project(
+            &target,
+            collection,
+            create_collection,
+            Some(path_to_string(&storage_root)?),
+        )?;
+        let result = {
+            let mut project = write(&project)?;
+            manifest
+                .files
+                .iter()
+                .try_for_each(|file| {
+                    project
+                        .add_file(
+                            &file.path,
+                            resolve(&file.real_path),
+                            file.metadata.clone(),
+                            None,
+                            &Checksums::default(),
+                            false,
+                        )
+                        .map(|_| ())
+                })
+                .and_then(|_| {
+                    manifest
+                        .folders
+                        .iter()
+                        .filter(|folder| !folder.metadata.is_empty())
+                        .try_for_each(|folder| {
+                            project
+                                .tree
+                                .set_folder_metadata(&folder.path, folder.metadata.clone())
+                        })
+                })
+        };
+        drop(project);
+        if let Err(e) = result {
+            tracing::error!("Failed to import manifest: {}", e);
+            self.abandon_project(&target, collection)?;
+            return Err(e);
+        }
+        Ok(ImportSummary {
+            project: target,
+            imported: manifest.files.len(),
+            skipped: Vec::new(),
+        })
+    }
+
+    #[instrument(skip(self, other))]
+    pub fn diff_project(
+        &self,
+        name: &str,
+        collection: &str,
+        snapshot: Option<&str>,
+        other: DiffSource,
+    ) -> Result<ProjectDiff> {
+        let project = self.load_project(name, collection)?;
+        let base = read(&project)?.diff_entries(snapshot)?;
+        let other = match other {
+            DiffSource::Project { name, collection } => {
+                let project = self.load_project(&name, &collection)?;
+                let entries = read(&project)?.diff_entries(None)?;
+                entries
+            }
+            DiffSource::Snapshot(snapshot) => read(&project)?.diff_entries(Some(&snapshot))?,
+            DiffSource::Tree(path) => {
+                let tree_path = path.join(".tree");
+                validate_tree(&tree_path)?;
+                let tree = read_tree(&tree_path)?;
+                let endpoint = LocalEndpoint::new(path);
+                tree.walk()
+                    .into_iter()
+                    .map(|(vpath, f)| {
+                        let entry = DiffEntry {
+                            real_path: endpoint.resolve(&f.real_path),
+                            metadata: f.metadata.clone(),
+                        };
+                        (vpath, entry)
+                    })
+                    .collect()
+            }
+            DiffSource::Manifest(path) => {
+                let manifest = Manifest::read(&path)?;
+                let endpoint =
+                    LocalEndpoint::new(path.parent().map(|p| p.to_path_buf()).unwrap_or_default());
+                manifest
+                    .files
+                    .into_iter()
+                    .map(|f| {
+                        let entry = DiffEntry {
+                            real_path: endpoint.resolve(Path::new(&f.real_path)),
+                            metadata: f.metadata,
+                        };
+                        (f.path, entry)
+                    })
+                    .collect()
+            }
+        };
+        Ok(diff(&base, &other))
+    }
+
+    #[instrument(skip(self))]
+    fn abandon_project(&self, name: &str, collection: &str) -> Result<()> {
+        // Remove a project's tree and storage record, leaving its data untouched
+        let _loading = lock(&self.loading)?;
+        self.unload(name, collection)?;
+        delete_project_dir(name, collection)?;
+        self.storage_manager.forget(name, collection)
+    }
+
+    fn unload(&self, name: &str, collection: &str) -> Result<()> {
+        // Drop a project from the cache and wait for anyone still using it. Callers hold
+        // `loading`, so it can't be loaded again until they are done with its files.
+        let key = format!("{}/{}", collection, name);
+        let loaded = write(&self.projects)?.remove(&key);
+        if let Some(loaded) = loaded {
+            drop(write(&loaded.project)?);
+        }
         Ok(())
     }
 
     #[instrument(skip(self))]
-    pub fn load_project(&mut self, name: &str, collection: &str) -> Result<Arc<Mutex<Project>>> {
+    pub fn load_project(&self, name: &str, collection: &str) -> Result<Arc<RwLock<Project>>> {
         let key = format!("{}/{}", collection, name);
-        if self.projects.contains_key(&key) {
-            let count = self.counts.get(&key).unwrap_or(&0);
-            self.counts.insert(key.clone(), count + 1);
-            return Ok(self.projects.get(&key).unwrap().clone());
+        if let Some(project) = self.loaded_project(&key)? {
+            return Ok(project);
+        }
+        let _loading = lock(&self.loading)?;
+        // Someone else may have loaded it while we were waiting
+        if let Some(project) = self.loaded_project(&key)? {
+            return Ok(project);
         }
         let project_dir = load_project_dir(name, collection)?;
         let storage_dir = self.storage_manager.get(name, collection)?;
         let tree = FileSystem::load(name, project_dir)?;
         let endpoint = LocalEndpoint::new(storage_dir.1);
 
-        let count = self.counts.get(&key).unwrap_or(&0);
-        self.counts.insert(key.clone(), count + 1);
-
         let project = Project {
+            leases: Leases::load(tree.leases()?)?,
+            hooks: Hooks::load(tree.hooks()?, self.hooks.clone())?,
+            audit: AuditLog::load(tree.audit()?)?,
+            events: self.events.publisher(collection, name)?,
             tree,
             _name: name.to_string(),
             _collection: collection.to_string(),
             _endpoint: Box::new(endpoint),
         };
-        let project = Arc::new(Mutex::new(project));
-        self.projects.insert(key, project.clone());
+        let project = Arc::new(RwLock::new(project));
+        write(&self.projects)?.insert(
+            key,
+            LoadedProject {
+                project: project.clone(),
+                count: AtomicUsize::new(1),
+            },
+        );
         Ok(project)
     }
 
+    fn loaded_project(&self, key: &str) -> Result<Option<Arc<RwLock<Project>>>> {
+        Ok(read(&self.projects)?.get(key).map(|loaded| {
+            loaded.count.fetch_add(1, Ordering::SeqCst);
+            loaded.project.clone()
+        }))
+    }
+
     #[instrument(skip(self))]
-    pub(crate) fn drop_project(&mut self, name: &str, collection: &str) -> Result<()> {
+    pub(crate) fn drop_project(&self, name: &str, collection: &str) -> Result<()> {
         let key = format!("{}/{}", collection, name);
-        let count = self.counts.get(&key);
-        if count.is_none() {
-            let message = format!(
-                "Tried to drop a project `{}` that was not in the cache",
-                key
-            );
-            tracing::error!(message);
-            return Err(GodataError::new(GodataErrorType::NotFound, message));
-        }
-        let count = count.unwrap();
-        if count == &1 {
+        let mut projects = write(&self.projects)?;
+        let count = match projects.get(&key) {
+            Some(loaded) => loaded.count.load(Ordering::SeqCst),
+            None => {
+                let message = format!(
+                    "Tried to drop a project `{}` that was not in the cache",
+                    key
+                );
+                tracing::error!(message);
+                return Err(GodataError::new(GodataErrorType::NotFound, message)
+                    .with_code("project_not_loaded"));
+            }
+        };
+        if count <= 1 {
             tracing::info!(
                 "Last connection to project `{}` dropped, removing from cache",
                 key
             );
-            self.projects.remove(&key);
-            self.counts.remove(&key);
-        } else if count < &0 {
-            self.counts.remove(&key);
-            tracing::error!(
-                "Count for project `{}` is negative, this should not happen",
-                key
-            );
-            return Err(GodataError::new(
-                GodataErrorType::InternalError,
-                format!("Tried to drop a project `{}` that does not exist", key),
-            ));
-        } else {
+            projects.remove(&key);
+        } else if let Some(loaded) = projects.get(&key) {
             tracing::info!("Dropping connection to project `{}`", key);
-            self.counts.insert(key, count - 1);
+            loaded.count.fetch_sub(1, Ordering::SeqCst);
         }
         Ok(())
     }
 
     #[instrument(skip(self))]
-    pub fn delete_project(&mut self, name: &str, collection: &str, force: bool) -> Result<()> {
+    pub fn delete_project(&self, name: &str, collection: &str, force: bool) -> Result<()> {
         let key = format!("{}/{}", collection, name);
-        let pobj = self.projects.remove(&key);
-        if let Some(obj) = pobj {
-            let obj = obj.lock().unwrap();
-            drop(obj);
+        let pobj = write(&self.projects)?.remove(&key);
+        if let Some(loaded) = pobj {
+            // Wait for anyone still using the project before deleting it
+            drop(write(&loaded.project)?);
         }
 
         let project_dir = load_project_dir(name, collection)?;
         let storage_dir = self.storage_manager.get(name, collection);
-        let project_is_empty = is_empty(&project_dir);
+        let project_is_empty = is_empty(&project_dir)?;
         let mut storage_is_empty = storage_dir.is_err();
-        if storage_dir.is_ok() {
-            let storage_dir = storage_dir.unwrap();
+        if let Ok(storage_dir) = storage_dir {
             let storage_path = storage_dir.1;
             let mut files_in_storage = std::fs::read_dir(storage_path)?;
             storage_is_empty = files_in_storage.next().is_none();
@@ -365,47 +1545,121 @@ impl ProjectManager {
         Err(GodataError::new(
             GodataErrorType::NotPermitted,
             "Project is not empty".to_string(),
-        ))
+        )
+        .with_code("project_not_empty"))
+    }
+
+    pub(crate) fn subscribe(
+        &self,
+        name: &str,
+        collection: &str,
+        prefix: Option<String>,
+    ) -> Result<impl Stream<Item = Delivery>> {
+        // Changes to a project can be watched whether or not it is loaded
+        load_project_dir(name, collection)?;
+        self.events
+            .subscribe(collection.to_string(), name.to_string(), prefix)
+    }
+
+    pub(crate) fn deliveries(
+        &self,
+        name: &str,
+        collection: &str,
+        hook: Option<&str>,
+    ) -> Result<Vec<DeliveryAttempt>> {
+        load_project_dir(name, collection)?;
+        self.hooks.deliveries(collection, name, hook)
+    }
+
+    pub(crate) fn close_events(&self) {
+        self.events.close();
     }
 
     #[instrument(skip(self))]
     pub fn get_project_names(&self, collection: String, show_hidden: bool) -> Result<Vec<String>> {
-        let collection_dir = load_collection_dir(&collection);
-        if collection_dir.is_err() {
-            return Err(GodataError::new(
-                GodataErrorType::NotFound,
-                format!("Collection `{}` does not exist", collection),
-            ));
-        }
-        let collection_dir = collection_dir.unwrap();
-
-        let mut names = Vec::new();
-        for entry in std::fs::read_dir(collection_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir()
-                && (!path.file_name().unwrap().to_str().unwrap().starts_with('.') || show_hidden)
-            {
-                let name = path.file_name().unwrap().to_str().unwrap().to_string();
-                names.push(name);
+        let collection_dir = match load_collection_dir(&collection) {
+            Ok(collection_dir) => collection_dir,
+            Err(_) => {
+                return Err(GodataError::new(
+                    GodataErrorType::NotFound,
+                    format!("Collection `{}` does not exist", collection),
+                )
+                .with_code("collection_not_found")
+                .with_details(serde_json::json!({"collection": collection})))
             }
-        }
-        Ok(names)
+        };
+        folder_names(&collection_dir, show_hidden)
     }
 }
 
 pub fn get_collection_names(show_hidden: bool) -> Result<Vec<String>> {
-    let main_dir = crate::locations::get_main_dir();
+    let main_dir = crate::locations::get_main_dir()?;
+    folder_names(&main_dir, show_hidden)
+}
+
+fn folder_names(dir: &Path, show_hidden: bool) -> Result<Vec<String>> {
     let mut names = Vec::new();
-    for entry in std::fs::read_dir(main_dir)? {
+    for entry in std::fs::read_dir(dir)? {
         let entry = entry?;
-        let path = entry.path();
-        if path.is_dir()
-            && (!path.file_name().unwrap().to_str().unwrap().starts_with('.') || show_hidden)
-        {
-            let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        // Godata never creates folders with names that aren't valid UTF-8
+        let name = match entry.file_name().to_str() {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        if entry.path().is_dir() && (!name.starts_with('.') || show_hidden) {
             names.push(name);
         }
     }
     Ok(names)
 }
+
+fn tree_entries(path: &Path) -> Result<Vec<(String, PathBuf, Metadata)>> {
+    // The files in an exported tree, with their real paths resolved against the export
+    let tree = read_tree(&path.join(".tree"))?;
+    let source = LocalEndpoint::new(path.to_path_buf());
+    Ok(tree
+        .walk()
+        .into_iter()
+        .map(|(vpath, f)| (vpath, source.resolve(&f.real_path), f.metadata.clone()))
+        .collect())
+}
+
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    // Copy an exported tree into a new project database
+    let db = sled::open(from)?;
+    let copy = sled::open(to)?;
+    copy.import(db.export());
+    copy.flush()?;
+    Ok(())
+}
+
+fn free_path(path: PathBuf) -> PathBuf {
+    // The path itself if nothing is there, otherwise the first free `name-<n>.ext` next to it
+    if !path.exists() {
+        return path;
+    }
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|i| path.with_file_name(format!("{}-{}{}", stem, i, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    // Staging directories can be on another filesystem, where a rename fails
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,183 @@
+// Per-request tracing. Every request gets an ID, either the one the client sent in the
+// `X-Request-Id` header or a fresh UUID. The ID is attached to the request span, echoed
+// back in the response headers and kept in a task-local so error bodies can include it.
+// The client's identity is kept the same way, so changes can be recorded against it (see
+// `audit`). It's made up of what the connection says about the peer, the name of the
+// token the request was authorized with and the name the client gives itself in the
+// `X-Godata-Client` header. Only the last one can be made up by the client.
+//
+// This is also the last line of defence against panics: a handler that panics is logged
+// and answered with an internal error instead of dropping the connection.
+//
+// Handlers do their work through `blocking`, which moves it off the async runtime, since
+// sled and filesystem calls would otherwise stall every other request on the same worker.
+
+use crate::errors::{GodataError, GodataErrorType};
+use futures_util::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::cell::RefCell;
+use std::convert::Infallible;
+use std::panic::AssertUnwindSafe;
+use std::time::Instant;
+use tracing::Instrument;
+use warp::http::{HeaderValue, Request, Response};
+use warp::hyper::service::Service;
+use warp::hyper::Body;
+use warp::Reply;
+
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+const CLIENT_HEADER: &str = "x-godata-client";
+// Longer client-provided IDs are replaced rather than echoed back
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+// Longer client names are cut
+const MAX_CLIENT_NAME_LENGTH: usize = 256;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub(crate) struct Client {
+    // Name of the token the request was authorized with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) token: Option<String>,
+    // User ID of a peer on the unix socket
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) uid: Option<u32>,
+    // Address of a peer over TCP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) address: Option<String>,
+    // What the client calls itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) name: Option<String>,
+}
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+    static CLIENT: RefCell<Client>;
+}
+
+pub(crate) fn current_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+pub(crate) fn current_client() -> Client {
+    CLIENT
+        .try_with(|client| client.borrow().clone())
+        .unwrap_or_default()
+}
+
+// Called once the request's token is known
+pub(crate) fn set_token(name: &str) {
+    let _ = CLIENT.try_with(|client| client.borrow_mut().token = Some(name.to_string()));
+}
+
+fn client_name(request: &Request<Body>) -> Option<String> {
+    let name = request.headers().get(CLIENT_HEADER)?.to_str().ok()?.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some(name.chars().take(MAX_CLIENT_NAME_LENGTH).collect())
+}
+
+fn request_id(request: &Request<Body>) -> String {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id.chars().all(|c| c.is_ascii_graphic())
+        })
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+pub(crate) async fn handle<S>(
+    mut service: S,
+    mut client: Client,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+{
+    let id = request_id(&request);
+    client.name = client_name(&request);
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %request.method(),
+        path = %request.uri().path(),
+    );
+    let start = Instant::now();
+    let call = async move {
+        match AssertUnwindSafe(service.call(request)).catch_unwind().await {
+            Ok(response) => response,
+            Err(panic) => Ok(panic_response(panic)),
+        }
+    };
+    let mut response = REQUEST_ID
+        .scope(id.clone(), CLIENT.scope(RefCell::new(client), call))
+        .instrument(span.clone())
+        .await?;
+    span.in_scope(|| {
+        tracing::info!(
+            status = response.status().as_u16(),
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "finished processing request"
+        )
+    });
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(response)
+}
+
+fn panic_response(panic: Box<dyn Any + Send>) -> Response<Body> {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(|m| m.as_str()))
+        .unwrap_or("unknown panic");
+    tracing::error!("Request handler panicked: {}", message);
+    GodataError::new(
+        GodataErrorType::InternalError,
+        "The server failed while processing this request".to_string(),
+    )
+    .with_code("handler_panicked")
+    .into_response()
+}
+
+pub(crate) async fn blocking<F, R>(f: F) -> Result<Response<Body>, Infallible>
+where
+    F: FnOnce() -> Result<R, Infallible> + Send + 'static,
+    R: Reply,
+{
+    // The request ID, client and span are carried over to the blocking thread
+    let id = current_id();
+    let client = current_client();
+    let span = tracing::Span::current();
+    let task = tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let f = move || CLIENT.sync_scope(RefCell::new(client), f);
+        let response = match id {
+            Some(id) => REQUEST_ID.sync_scope(id, f),
+            None => f(),
+        };
+        response.map(Reply::into_response)
+    });
+    match task.await {
+        Ok(response) => response,
+        Err(e) => match e.try_into_panic() {
+            Ok(panic) => Ok(panic_response(panic)),
+            Err(e) => {
+                tracing::error!("Blocking task failed: {}", e);
+                Ok(GodataError::new(
+                    GodataErrorType::InternalError,
+                    "The server failed while processing this request".to_string(),
+                )
+                .with_code("task_failed")
+                .into_response())
+            }
+        },
+    }
+}
@@ -0,0 +1,108 @@
+// Optimistic concurrency for writes to a project. Every file and folder has a revision
+// (see `fsystem`), which is sent to clients as a strong ETag such as `"42"`. A client
+// that sends the ETag back in an `If-Match` header only changes the entry if nobody has
+// changed it in the meantime. A stale writer gets a 412 carrying the current revision,
+// instead of overwriting someone else's result.
+//
+// As in HTTP, `If-Match: *` matches any existing entry and no ETag matches a path that
+// doesn't exist. Weak ETags never match.
+//
+// A move is checked against the source, the entry the client read. The destination isn't
+// checked: without `overwrite` the move fails if anything is there, with it whatever is
+// there is replaced.
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+
+pub(crate) const IF_MATCH_HEADER: &str = "if-match";
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum IfMatch {
+    Any,
+    Revisions(Vec<u64>),
+}
+
+impl IfMatch {
+    pub(crate) fn parse(header: &str) -> IfMatch {
+        if header.trim() == "*" {
+            return IfMatch::Any;
+        }
+        // Anything that isn't one of our ETags can't match, so it's dropped here
+        let revisions = header
+            .split(',')
+            .filter_map(|tag| {
+                let tag = tag.trim();
+                tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok()
+            })
+            .collect();
+        IfMatch::Revisions(revisions)
+    }
+
+    pub(crate) fn check(&self, path: &str, current: Option<u64>) -> Result<()> {
+        let matches = match (self, current) {
+            (_, None) => false,
+            (IfMatch::Any, Some(_)) => true,
+            (IfMatch::Revisions(revisions), Some(current)) => revisions.contains(&current),
+        };
+        if matches {
+            return Ok(());
+        }
+        tracing::info!("Precondition failed for path `{}`", path);
+        let message = match current {
+            Some(current) => format!(
+                "Path `{}` has changed, it is now at revision {}",
+                path, current
+            ),
+            None => format!("Path `{}` does not exist", path),
+        };
+        Err(
+            GodataError::new(GodataErrorType::PreconditionFailed, message)
+                .with_code("revision_mismatch")
+                .with_details(serde_json::json!({"path": path, "revision": current})),
+        )
+    }
+}
+
+impl From<u64> for IfMatch {
+    fn from(revision: u64) -> Self {
+        IfMatch::Revisions(vec![revision])
+    }
+}
+
+pub(crate) fn etag(revision: u64) -> String {
+    format!("\"{}\"", revision)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_etags() {
+        assert_eq!(IfMatch::parse("*"), IfMatch::Any);
+        assert_eq!(
+            IfMatch::parse("\"1\", \"42\""),
+            IfMatch::Revisions(vec![1, 42])
+        );
+        // Weak and malformed tags can never match
+        assert_eq!(
+            IfMatch::parse("W/\"1\", 2, \"x\""),
+            IfMatch::Revisions(vec![])
+        );
+    }
+
+    #[test]
+    fn matches_current_revision() {
+        let if_match = IfMatch::parse("\"3\", \"5\"");
+        assert!(if_match.check("a", Some(3)).is_ok());
+        assert!(if_match.check("a", Some(5)).is_ok());
+        let error = if_match.check("a", Some(4)).unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::PreconditionFailed);
+    }
+
+    #[test]
+    fn nothing_matches_a_missing_path() {
+        assert!(IfMatch::Any.check("a", Some(1)).is_ok());
+        assert!(IfMatch::Any.check("a", None).is_err());
+        assert!(IfMatch::from(1).check("a", None).is_err());
+    }
+}
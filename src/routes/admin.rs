@@ -0,0 +1,82 @@
+use crate::auth::{AuthManager, Grant};
+use crate::handlers;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::instrument;
+use warp::Filter;
+
+// Token bodies are small, anything bigger than this is not a token request
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+#[derive(Deserialize)]
+struct CreateTokenBody {
+    name: String,
+    #[serde(default)]
+    grants: Vec<Grant>,
+}
+
+#[derive(Deserialize)]
+struct GrantsBody {
+    grants: Vec<Grant>,
+}
+
+pub(super) fn routes(
+    auth_manager: Arc<AuthManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    create_token(auth_manager.clone())
+        .or(list_tokens(auth_manager.clone()))
+        .or(set_token_grants(auth_manager.clone()))
+        .or(delete_token(auth_manager.clone()))
+}
+
+#[instrument(skip(auth_manager))]
+fn create_token(
+    auth_manager: Arc<AuthManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "tokens")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json())
+        .and_then(move |body: CreateTokenBody| {
+            let auth_manager = auth_manager.clone();
+            async move { handlers::create_token(auth_manager, body.name, body.grants).await }
+        })
+}
+
+#[instrument(skip(auth_manager))]
+fn list_tokens(
+    auth_manager: Arc<AuthManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "tokens")
+        .and(warp::get())
+        .and_then(move || {
+            let auth_manager = auth_manager.clone();
+            async move { handlers::list_tokens(auth_manager.clone()).await }
+        })
+}
+
+#[instrument(skip(auth_manager))]
+fn set_token_grants(
+    auth_manager: Arc<AuthManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "tokens" / String / "grants")
+        .and(warp::put())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json())
+        .and_then(move |id, body: GrantsBody| {
+            let auth_manager = auth_manager.clone();
+            async move { handlers::set_token_grants(auth_manager, id, body.grants).await }
+        })
+}
+
+#[instrument(skip(auth_manager))]
+fn delete_token(
+    auth_manager: Arc<AuthManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "tokens" / String)
+        .and(warp::delete())
+        .and_then(move |id| {
+            let auth_manager = auth_manager.clone();
+            async move { handlers::delete_token(auth_manager, id).await }
+        })
+}
@@ -0,0 +1,85 @@
+use crate::audit::AuditQuery;
+use crate::errors::GodataError;
+use crate::handlers;
+use crate::project::ProjectManager;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::instrument;
+use warp::Filter;
+use warp::Reply;
+
+pub(super) fn routes(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    query_audit(project_manager)
+}
+
+fn audit_query(mut params: HashMap<String, String>) -> Result<AuditQuery, GodataError> {
+    Ok(AuditQuery {
+        project_path: params.remove("project_path"),
+        action: parse(
+            &mut params,
+            "action",
+            "an action such as link or remove",
+            |value| serde_json::from_value(serde_json::Value::String(value.to_string())).ok(),
+        )?,
+        client: params.remove("client"),
+        since: parse(&mut params, "since", "an RFC 3339 timestamp", timestamp)?,
+        until: parse(&mut params, "until", "an RFC 3339 timestamp", timestamp)?,
+        before: parse(&mut params, "before", "a sequence number", |value| {
+            u64::from_str(value).ok()
+        })?,
+        limit: parse(&mut params, "limit", "a number", |value| {
+            usize::from_str(value).ok()
+        })?,
+    })
+}
+
+fn parse<T>(
+    params: &mut HashMap<String, String>,
+    name: &str,
+    expected: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<Option<T>, GodataError> {
+    let value = match params.remove(name) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    match parse(&value) {
+        Some(parsed) => Ok(Some(parsed)),
+        None => Err(GodataError::invalid_argument(
+            name,
+            &value,
+            format!("Invalid {} argument {}, expected {}", name, value, expected),
+        )),
+    }
+}
+
+fn timestamp(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+#[instrument(skip(project_manager))]
+fn query_audit(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "audit")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let query = match audit_query(params) {
+                        Ok(query) => query,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::query_audit(project_manager, collection, project_name, query).await
+                }
+            },
+        )
+}
@@ -0,0 +1,35 @@
+use crate::handlers;
+use crate::project::ProjectManager;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::instrument;
+use warp::Filter;
+
+pub(super) fn routes(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    watch_project(project_manager)
+}
+
+#[instrument(skip(project_manager))]
+fn watch_project(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "events")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    handlers::watch_project(
+                        project_manager,
+                        collection,
+                        project_name,
+                        params.remove("prefix"),
+                    )
+                    .await
+                }
+            },
+        )
+}
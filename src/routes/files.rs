@@ -1,17 +1,20 @@
-use crate::errors::{GodataError, GodataErrorType};
+use super::{body, flag, if_match, lease, required, sized_body, FromQuery, MAX_BATCH_BODY_SIZE};
+use crate::batch::BatchOperation;
+use crate::errors::GodataError;
 use crate::handlers;
+use crate::lineage::ProvenanceRequest;
+use crate::metadata::{Metadata, MetadataValue};
 use crate::project::ProjectManager;
+use crate::revision::IfMatch;
+use serde::Deserialize;
 use std::collections::HashMap;
-use std::convert::Infallible;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use tracing::instrument;
-use warp::http::StatusCode;
 use warp::Filter;
 use warp::Reply;
-use warp::{http::Response, hyper::Body};
 
 pub(super) fn routes(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     project_list(project_manager.clone())
         .or(project_link(project_manager.clone()))
@@ -20,83 +23,165 @@ pub(super) fn routes(
         .or(project_generate_path(project_manager.clone()))
         .or(project_remove_file(project_manager.clone()))
         .or(move_file(project_manager.clone()))
+        .or(batch(project_manager.clone()))
+}
+
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum LinkType {
+    #[default]
+    File,
+    Folder,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LinkBody {
+    project_path: String,
+    real_path: String,
+    #[serde(rename = "type", default)]
+    link_type: LinkType,
+    #[serde(default)]
+    force: bool,
+    #[serde(default)]
+    recursive: bool,
+    #[serde(default)]
+    metadata: Metadata,
+    // Only in a JSON body, see `lineage`
+    provenance: Option<ProvenanceRequest>,
+}
+
+impl FromQuery for LinkBody {
+    // Any query parameter that isn't an option is treated as metadata. Query values are
+    // always strings, so their types are inferred.
+    fn from_query(mut params: HashMap<String, String>) -> Result<Self, GodataError> {
+        let force = flag("force", params.remove("force").as_deref())?;
+        let project_path = required(&mut params, "project_path")?;
+        let real_path = required(&mut params, "real_path")?;
+        let link_type = match params.remove("type").as_deref() {
+            None | Some("file") => LinkType::File,
+            Some("folder") => LinkType::Folder,
+            Some(other) => {
+                tracing::error!("Request included invalid type argument {}", other);
+                return Err(GodataError::invalid_argument(
+                    "type",
+                    other,
+                    format!("Invalid type argument {}", other),
+                ));
+            }
+        };
+        let recursive = match link_type {
+            LinkType::Folder => flag("recursive", params.remove("recursive").as_deref())?,
+            LinkType::File => false,
+        };
+        Ok(LinkBody {
+            project_path,
+            real_path,
+            link_type,
+            force,
+            recursive,
+            metadata: params
+                .into_iter()
+                .map(|(key, value)| (key, MetadataValue::infer(&value)))
+                .collect(),
+            provenance: None,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MoveBody {
+    source_path: String,
+    destination_path: String,
+    #[serde(default)]
+    overwrite: bool,
+}
+
+impl FromQuery for MoveBody {
+    fn from_query(mut params: HashMap<String, String>) -> Result<Self, GodataError> {
+        Ok(MoveBody {
+            source_path: required(&mut params, "source_path")?,
+            destination_path: required(&mut params, "destination_path")?,
+            overwrite: flag("overwrite", params.remove("overwrite").as_deref())?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchBody {
+    operations: Vec<BatchOperation>,
+    #[serde(default)]
+    atomic: bool,
+}
+
+impl FromQuery for BatchBody {
+    // Operations can't be expressed as query parameters
+    fn from_query(_params: HashMap<String, String>) -> Result<Self, GodataError> {
+        Err(GodataError::missing_argument("operations"))
+    }
 }
 
 #[instrument(skip(project_manager))]
 fn project_link(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("projects" / String / String / "files")
         .and(warp::post())
-        .and(warp::query::<HashMap<String, String>>())
-        .map(
+        .and(body::<LinkBody>())
+        .and(if_match())
+        .and(lease())
+        .and_then(
             move |collection,
                   project_name,
-                  mut params: HashMap<String, String>|
-                  -> Result<Response<Body>, _> {
-                let force = match params.remove("force") {
-                    Some(force) => force.parse::<bool>().unwrap(),
-                    None => false,
-                };
-                let ppath = match params.remove("project_path") {
-                    Some(project_path) => project_path.to_owned(),
-                    None => {
-                        tracing::error!("Query missing project_path argument");
-                        return Ok::<Response<Body>, Infallible>(
-                            warp::reply::with_status(
-                                warp::reply::json(&"Missing project_path argument".to_string()),
-                                StatusCode::BAD_REQUEST,
-                            )
-                            .into_response(),
-                        );
-                    } // invalid request
-                };
-                let rpath = match params.remove("real_path") {
-                    Some(storage_location) => storage_location.to_owned(),
-                    None => {
-                        tracing::error!("Query missing real_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing real_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
-                        )
-                        .into_response());
-                    } // invalid request
-                };
-
-                let type_ = match params.remove("type") {
-                    Some(type_) => type_.to_owned(),
-                    None => "file".to_owned(),
-                };
-                if type_ == "file" {
-                    handlers::link_file(
-                        project_manager.clone(),
-                        collection,
-                        project_name,
-                        ppath,
-                        rpath,
-                        params,
-                        force,
-                    )
-                } else if type_ == "folder" {
-                    let recursive = match params.get("recursive") {
-                        Some(recursive) => recursive.parse::<bool>().unwrap(),
-                        None => false,
+                  body: Result<LinkBody, GodataError>,
+                  if_match: Option<IfMatch>,
+                  lease: Option<String>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let body = match body {
+                        Ok(body) => body,
+                        Err(e) => return Ok(e.into_response()),
                     };
-                    return handlers::link_folder(
-                        project_manager.clone(),
-                        collection,
-                        project_name,
-                        ppath,
-                        rpath,
-                        recursive,
-                    );
-                } else {
-                    tracing::error!("Request included invalid type argument {}", type_);
-                    return Ok(warp::reply::with_status(
-                        warp::reply::json(&format!("Invalid type argument {}", type_)),
-                        StatusCode::BAD_REQUEST,
-                    )
-                    .into_response()); // invalid request
+                    match body.link_type {
+                        LinkType::File => {
+                            handlers::link_file(
+                                project_manager,
+                                collection,
+                                project_name,
+                                body.project_path,
+                                body.real_path,
+                                body.metadata,
+                                body.provenance,
+                                body.force,
+                                if_match,
+                                lease,
+                            )
+                            .await
+                        }
+                        LinkType::Folder if body.provenance.is_some() => {
+                            Ok(GodataError::invalid_argument(
+                                "provenance",
+                                &body.project_path,
+                                "Provenance can only be recorded for files".to_string(),
+                            )
+                            .into_response())
+                        }
+                        LinkType::Folder => {
+                            handlers::link_folder(
+                                project_manager,
+                                collection,
+                                project_name,
+                                body.project_path,
+                                body.real_path,
+                                body.recursive,
+                                if_match,
+                                lease,
+                            )
+                            .await
+                        }
+                    }
                 }
             },
         )
@@ -104,32 +189,45 @@ fn project_link(
 
 #[instrument(skip(project_manager))]
 fn project_list(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("projects" / String / String / "list")
         .and(warp::get())
         .and(warp::query::<HashMap<String, String>>())
-        .map(
+        .and_then(
             move |collection, project_name, params: HashMap<String, String>| {
-                let show_hidden = match params.get("show_hidden") {
-                    Some(show_hidden) => show_hidden.parse::<bool>().unwrap(),
-                    None => false,
-                };
-                match params.get("project_path") {
-                    Some(path) => handlers::list_project(
-                        project_manager.clone(),
-                        collection,
-                        project_name,
-                        Some(path.to_owned()),
-                        show_hidden,
-                    ),
-                    None => handlers::list_project(
-                        project_manager.clone(),
-                        collection,
-                        project_name,
-                        None,
-                        show_hidden,
-                    ),
+                let project_manager = project_manager.clone();
+                async move {
+                    let show_hidden =
+                        match flag("show_hidden", params.get("show_hidden").map(|v| v.as_str())) {
+                            Ok(show_hidden) => show_hidden,
+                            Err(e) => return Ok(e.into_response()),
+                        };
+                    let snapshot = params.get("snapshot").cloned();
+                    match params.get("project_path") {
+                        Some(path) => {
+                            handlers::list_project(
+                                project_manager,
+                                collection,
+                                project_name,
+                                Some(path.to_owned()),
+                                show_hidden,
+                                snapshot,
+                            )
+                            .await
+                        }
+                        None => {
+                            handlers::list_project(
+                                project_manager,
+                                collection,
+                                project_name,
+                                None,
+                                show_hidden,
+                                snapshot,
+                            )
+                            .await
+                        }
+                    }
                 }
             },
         )
@@ -137,35 +235,41 @@ fn project_list(
 
 #[instrument(skip(project_manager))]
 fn projects_get(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("projects" / String / String / "files")
         .and(warp::get())
         .and(warp::query::<HashMap<String, String>>())
-        .map(
+        .and_then(
             move |collection, project_name, params: HashMap<String, String>| {
-                let project_path = params.get("project_path");
-                match (params.get("pattern"), project_path) {
-                    (None, Some(ppath)) => handlers::get_file(
-                        project_manager.clone(),
-                        collection,
-                        project_name,
-                        ppath.to_owned(),
-                    ),
-                    (Some(pattern), ppath) => handlers::get_files_with_pattern(
-                        project_manager.clone(),
-                        collection,
-                        project_name,
-                        ppath.map(|p| p.as_str()),
-                        pattern,
-                    ),
-                    (None, None) => {
-                        tracing::error!("Query missing project_path argument");
-                        Ok(GodataError::new(
-                            GodataErrorType::InvalidPath,
-                            "Missing project_path argument".to_string(),
-                        )
-                        .into_response())
+                let project_manager = project_manager.clone();
+                async move {
+                    let project_path = params.get("project_path");
+                    match (params.get("pattern"), project_path) {
+                        (None, Some(ppath)) => {
+                            handlers::get_file(
+                                project_manager,
+                                collection,
+                                project_name,
+                                ppath.to_owned(),
+                                params.get("snapshot").cloned(),
+                            )
+                            .await
+                        }
+                        (Some(pattern), ppath) => {
+                            handlers::get_files_with_pattern(
+                                project_manager,
+                                collection,
+                                project_name,
+                                ppath.cloned(),
+                                pattern.to_owned(),
+                            )
+                            .await
+                        }
+                        (None, None) => {
+                            tracing::error!("Query missing project_path argument");
+                            Ok(GodataError::missing_argument("project_path").into_response())
+                        }
                     }
                 }
             },
@@ -174,137 +278,162 @@ fn projects_get(
 
 #[instrument(skip(project_manager))]
 fn projects_path_exists(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("projects" / String / String / "exists")
         .and(warp::get())
         .and(warp::query::<HashMap<String, String>>())
-        .map(
+        .and_then(
             move |collection, project_name, params: HashMap<String, String>| {
-                let project_path = match params.get("project_path") {
-                    Some(project_path) => project_path.to_owned(),
-                    None => {
-                        tracing::error!("Query missing project_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing project_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
-                        )
-                        .into_response());
-                    } // invalid request
-                };
-                handlers::path_exists(
-                    project_manager.clone(),
-                    collection,
-                    project_name,
-                    project_path,
-                )
+                let project_manager = project_manager.clone();
+                async move {
+                    let project_path = match params.get("project_path") {
+                        Some(project_path) => project_path.to_owned(),
+                        None => {
+                            tracing::error!("Query missing project_path argument");
+                            return Ok(
+                                GodataError::missing_argument("project_path").into_response()
+                            );
+                        } // invalid request
+                    };
+                    handlers::path_exists(project_manager, collection, project_name, project_path)
+                        .await
+                }
             },
         )
 }
 
 #[instrument(skip(project_manager))]
 fn project_generate_path(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("projects" / String / String / "generate")
         .and(warp::get())
         .and(warp::query::<HashMap<String, String>>())
-        .map(
-            move |collection, project_name, params: HashMap<String, String>| {
-                let project_path = match params.get("project_path") {
-                    Some(project_path) => project_path.to_owned(),
-                    None => {
-                        tracing::error!("Query missing project_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing project_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
-                        )
-                        .into_response());
-                    } // invalid request
-                };
-                handlers::generate_path(
-                    project_manager.clone(),
-                    collection,
-                    project_name,
-                    project_path,
-                )
+        .and(lease())
+        .and_then(
+            move |collection, project_name, params: HashMap<String, String>, lease| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let project_path = match params.get("project_path") {
+                        Some(project_path) => project_path.to_owned(),
+                        None => {
+                            tracing::error!("Query missing project_path argument");
+                            return Ok(
+                                GodataError::missing_argument("project_path").into_response()
+                            );
+                        } // invalid request
+                    };
+                    handlers::generate_path(
+                        project_manager,
+                        collection,
+                        project_name,
+                        project_path,
+                        lease,
+                    )
+                    .await
+                }
             },
         )
 }
 
 #[instrument(skip(project_manager))]
 fn project_remove_file(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("projects" / String / String / "files")
         .and(warp::delete())
         .and(warp::query::<HashMap<String, String>>())
-        .map(
-            move |collection, project_name, params: HashMap<String, String>| {
-                let project_path = match params.get("project_path") {
-                    Some(project_path) => project_path.to_owned(),
-                    None => {
-                        tracing::error!("Query missing project_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing project_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
-                        )
-                        .into_response());
-                    } // invalid request
-                };
-                handlers::remove_file(
-                    project_manager.clone(),
-                    collection,
-                    project_name,
-                    project_path,
-                )
+        .and(if_match())
+        .and_then(
+            move |collection,
+                  project_name,
+                  params: HashMap<String, String>,
+                  if_match: Option<IfMatch>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let project_path = match params.get("project_path") {
+                        Some(project_path) => project_path.to_owned(),
+                        None => {
+                            tracing::error!("Query missing project_path argument");
+                            return Ok(
+                                GodataError::missing_argument("project_path").into_response()
+                            );
+                        } // invalid request
+                    };
+                    handlers::remove_file(
+                        project_manager,
+                        collection,
+                        project_name,
+                        project_path,
+                        if_match,
+                    )
+                    .await
+                }
             },
         )
 }
 
 #[instrument(skip(project_manager))]
 fn move_file(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("projects" / String / String / "files" / "move")
         .and(warp::post())
-        .and(warp::query::<HashMap<String, String>>())
-        .map(
-            move |collection, project_name, params: HashMap<String, String>| {
-                let project_path = match params.get("source_path") {
-                    Some(project_path) => project_path.to_owned(),
-                    None => {
-                        tracing::error!("Query missing project_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing project_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
-                        )
-                        .into_response());
-                    } // invalid request
-                };
-                let new_path = match params.get("destination_path") {
-                    Some(new_path) => new_path.to_owned(),
-                    None => {
-                        tracing::error!("Query missing new_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing new_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
-                        )
-                        .into_response());
-                    } // invalid request
-                };
-                let overwrite = match params.get("overwrite") {
-                    Some(overwrite) => overwrite.parse::<bool>().unwrap(),
-                    None => false,
-                };
-                handlers::move_(
-                    project_manager.clone(),
-                    collection,
-                    project_name,
-                    project_path,
-                    new_path,
-                    overwrite,
-                )
+        .and(body::<MoveBody>())
+        .and(if_match())
+        .and_then(
+            move |collection,
+                  project_name,
+                  body: Result<MoveBody, GodataError>,
+                  if_match: Option<IfMatch>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let body = match body {
+                        Ok(body) => body,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::move_(
+                        project_manager,
+                        collection,
+                        project_name,
+                        body.source_path,
+                        body.destination_path,
+                        body.overwrite,
+                        if_match,
+                    )
+                    .await
+                }
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn batch(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "batch")
+        .and(warp::post())
+        .and(sized_body::<BatchBody>(MAX_BATCH_BODY_SIZE))
+        .and(lease())
+        .and_then(
+            move |collection, project_name, body: Result<BatchBody, GodataError>, lease| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let body = match body {
+                        Ok(body) => body,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::apply_batch(
+                        project_manager,
+                        collection,
+                        project_name,
+                        body.operations,
+                        body.atomic,
+                        lease,
+                    )
+                    .await
+                }
             },
         )
 }
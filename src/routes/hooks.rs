@@ -0,0 +1,170 @@
+use super::{body, required, FromQuery};
+use crate::errors::{GodataError, GodataErrorType};
+use crate::events::Operation;
+use crate::handlers;
+use crate::hooks::Action;
+use crate::project::ProjectManager;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::instrument;
+use warp::Filter;
+use warp::Reply;
+
+pub(super) fn routes(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    add_hook(project_manager.clone())
+        .or(list_hooks(project_manager.clone()))
+        .or(remove_hook(project_manager.clone()))
+        .or(list_deliveries(project_manager.clone()))
+}
+
+// A hook either posts to a URL or runs a command
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HookBody {
+    pattern: String,
+    operations: Option<Vec<Operation>>,
+    url: Option<String>,
+    command: Option<Vec<String>>,
+}
+
+impl FromQuery for HookBody {
+    fn from_query(mut params: HashMap<String, String>) -> Result<Self, GodataError> {
+        let operations = match params.remove("operations") {
+            None => None,
+            Some(value) => Some(
+                value
+                    .split(',')
+                    .map(|op| {
+                        serde_json::from_value(serde_json::Value::String(op.trim().to_string()))
+                            .map_err(|_| {
+                                GodataError::invalid_argument(
+                                    "operations",
+                                    &value,
+                                    format!(
+                                        "Invalid operation {}, expected link, move, remove or metadata",
+                                        op
+                                    ),
+                                )
+                            })
+                    })
+                    .collect::<Result<Vec<Operation>, GodataError>>()?,
+            ),
+        };
+        Ok(HookBody {
+            pattern: required(&mut params, "pattern")?,
+            operations,
+            url: params.remove("url"),
+            // Arguments can't contain spaces when the command is given in the query
+            command: params
+                .remove("command")
+                .map(|command| command.split_whitespace().map(String::from).collect()),
+        })
+    }
+}
+
+impl HookBody {
+    fn action(&mut self) -> Result<Action, GodataError> {
+        match (self.url.take(), self.command.take()) {
+            (Some(url), None) => Ok(Action::Http { url }),
+            (None, Some(command)) => Ok(Action::Command { command }),
+            (Some(_), Some(_)) => Err(GodataError::new(
+                GodataErrorType::InvalidArgument,
+                "A hook takes either a url or a command, not both".to_string(),
+            )
+            .with_code("invalid_argument")),
+            (None, None) => Err(GodataError::missing_argument("url")),
+        }
+    }
+}
+
+#[instrument(skip(project_manager))]
+fn add_hook(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "hooks")
+        .and(warp::post())
+        .and(body::<HookBody>())
+        .and_then(
+            move |collection, project_name, body: Result<HookBody, GodataError>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let mut body = match body {
+                        Ok(body) => body,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    let action = match body.action() {
+                        Ok(action) => action,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::add_hook(
+                        project_manager,
+                        collection,
+                        project_name,
+                        body.pattern,
+                        body.operations,
+                        action,
+                    )
+                    .await
+                }
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn list_hooks(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "hooks")
+        .and(warp::get())
+        .and_then(move |collection, project_name| {
+            let project_manager = project_manager.clone();
+            async move { handlers::list_hooks(project_manager, collection, project_name).await }
+        })
+}
+
+#[instrument(skip(project_manager))]
+fn remove_hook(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "hooks")
+        .and(warp::delete())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let id = match required(&mut params, "id") {
+                        Ok(id) => id,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::remove_hook(project_manager, collection, project_name, id).await
+                }
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn list_deliveries(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "hooks" / "deliveries")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    handlers::list_deliveries(
+                        project_manager,
+                        collection,
+                        project_name,
+                        params.remove("hook"),
+                    )
+                    .await
+                }
+            },
+        )
+}
@@ -0,0 +1,159 @@
+use super::{body, required, FromQuery};
+use crate::errors::GodataError;
+use crate::handlers;
+use crate::project::ProjectManager;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::instrument;
+use warp::Filter;
+use warp::Reply;
+
+pub(super) fn routes(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    acquire_lease(project_manager.clone())
+        .or(list_leases(project_manager.clone()))
+        .or(renew_lease(project_manager.clone()))
+        .or(release_lease(project_manager.clone()))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AcquireBody {
+    project_path: String,
+    holder: String,
+    ttl: Option<u64>,
+}
+
+impl FromQuery for AcquireBody {
+    fn from_query(mut params: HashMap<String, String>) -> Result<Self, GodataError> {
+        Ok(AcquireBody {
+            project_path: required(&mut params, "project_path")?,
+            holder: required(&mut params, "holder")?,
+            ttl: ttl(params.remove("ttl"))?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RenewBody {
+    id: String,
+    ttl: Option<u64>,
+}
+
+impl FromQuery for RenewBody {
+    fn from_query(mut params: HashMap<String, String>) -> Result<Self, GodataError> {
+        Ok(RenewBody {
+            id: required(&mut params, "id")?,
+            ttl: ttl(params.remove("ttl"))?,
+        })
+    }
+}
+
+fn ttl(value: Option<String>) -> Result<Option<u64>, GodataError> {
+    match value {
+        None => Ok(None),
+        Some(value) => value.parse().map(Some).map_err(|_| {
+            GodataError::invalid_argument(
+                "ttl",
+                &value,
+                format!(
+                    "Invalid ttl argument {}, expected a number of seconds",
+                    value
+                ),
+            )
+        }),
+    }
+}
+
+#[instrument(skip(project_manager))]
+fn acquire_lease(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "leases")
+        .and(warp::post())
+        .and(body::<AcquireBody>())
+        .and_then(
+            move |collection, project_name, body: Result<AcquireBody, GodataError>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let body = match body {
+                        Ok(body) => body,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::acquire_lease(
+                        project_manager,
+                        collection,
+                        project_name,
+                        body.project_path,
+                        body.holder,
+                        body.ttl,
+                    )
+                    .await
+                }
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn list_leases(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "leases")
+        .and(warp::get())
+        .and_then(move |collection, project_name| {
+            let project_manager = project_manager.clone();
+            async move { handlers::list_leases(project_manager, collection, project_name).await }
+        })
+}
+
+#[instrument(skip(project_manager))]
+fn renew_lease(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "leases" / "renew")
+        .and(warp::post())
+        .and(body::<RenewBody>())
+        .and_then(
+            move |collection, project_name, body: Result<RenewBody, GodataError>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let body = match body {
+                        Ok(body) => body,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::renew_lease(
+                        project_manager,
+                        collection,
+                        project_name,
+                        body.id,
+                        body.ttl,
+                    )
+                    .await
+                }
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn release_lease(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "leases")
+        .and(warp::delete())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let id = match required(&mut params, "id") {
+                        Ok(id) => id,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::release_lease(project_manager, collection, project_name, id).await
+                }
+            },
+        )
+}
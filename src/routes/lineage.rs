@@ -0,0 +1,129 @@
+use super::flag;
+use crate::errors::GodataError;
+use crate::handlers;
+use crate::lineage::{Direction, LineageQuery};
+use crate::project::ProjectManager;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::instrument;
+use warp::Filter;
+use warp::Reply;
+
+pub(super) fn routes(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    get_lineage(project_manager.clone())
+        .or(export_lineage_prov(project_manager.clone()))
+        .or(stale_files(project_manager))
+}
+
+fn lineage_query(mut params: HashMap<String, String>) -> Result<LineageQuery, GodataError> {
+    let direction = match params.remove("direction").as_deref() {
+        None | Some("both") => Direction::Both,
+        Some("upstream") => Direction::Upstream,
+        Some("downstream") => Direction::Downstream,
+        Some(other) => {
+            return Err(GodataError::invalid_argument(
+                "direction",
+                other,
+                format!(
+                    "Invalid direction argument {}, expected upstream, downstream or both",
+                    other
+                ),
+            ))
+        }
+    };
+    let depth = match params.remove("depth") {
+        None => None,
+        Some(value) => match usize::from_str(&value) {
+            Ok(depth) if depth > 0 => Some(depth),
+            _ => {
+                return Err(GodataError::invalid_argument(
+                    "depth",
+                    &value,
+                    format!(
+                        "Invalid depth argument {}, expected a positive number",
+                        value
+                    ),
+                ))
+            }
+        },
+    };
+    Ok(LineageQuery {
+        project_path: params.remove("project_path"),
+        direction,
+        depth,
+    })
+}
+
+#[instrument(skip(project_manager))]
+fn get_lineage(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "lineage")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let query = match lineage_query(params) {
+                        Ok(query) => query,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::get_lineage(project_manager, collection, project_name, query).await
+                }
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn export_lineage_prov(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "lineage" / "prov")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let query = match lineage_query(params) {
+                        Ok(query) => query,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::export_lineage_prov(project_manager, collection, project_name, query)
+                        .await
+                }
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn stale_files(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "lineage" / "stale")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let checksums = match flag("checksums", params.remove("checksums").as_deref()) {
+                        Ok(checksums) => checksums,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::stale_files(
+                        project_manager,
+                        collection,
+                        project_name,
+                        params.remove("project_path"),
+                        checksums,
+                    )
+                    .await
+                }
+            },
+        )
+}
@@ -1,14 +1,134 @@
+mod admin;
+mod audit;
+mod events;
 mod files;
+mod hooks;
+mod leases;
+mod lineage;
 mod projects;
+mod snapshots;
 
+use crate::auth::{self, AuthManager};
+use crate::errors::{handle_rejection, GodataError, GodataErrorType};
+use crate::lease::LEASE_HEADER;
 use crate::project::ProjectManager;
-use std::sync::{Arc, Mutex};
-use warp::Filter;
+use crate::revision::{IfMatch, IF_MATCH_HEADER};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use warp::hyper::body::Bytes;
+use warp::{Filter, Rejection};
 
-pub fn routes(
-    project_manager: Arc<Mutex<ProjectManager>>,
-) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    projects::routes(project_manager.clone())
-        .or(files::routes(project_manager.clone()))
+// Request bodies only carry paths, options and metadata
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+// Batches can carry many thousands of operations
+const MAX_BATCH_BODY_SIZE: u64 = 64 * 1024 * 1024;
+
+pub(crate) fn routes(
+    project_manager: Arc<ProjectManager>,
+    auth_manager: Arc<AuthManager>,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = Infallible> + Clone {
+    auth::authorize(auth_manager.clone())
+        .and(
+            projects::routes(project_manager.clone())
+                .or(files::routes(project_manager.clone()))
+                .or(snapshots::routes(project_manager.clone()))
+                .or(leases::routes(project_manager.clone()))
+                .or(events::routes(project_manager.clone()))
+                .or(hooks::routes(project_manager.clone()))
+                .or(audit::routes(project_manager.clone()))
+                .or(lineage::routes(project_manager.clone()))
+                .or(admin::routes(auth_manager.clone())),
+        )
+        .recover(handle_rejection)
+}
+
+// Boolean query parameters are optional and default to false
+fn flag(name: &str, value: Option<&str>) -> Result<bool, GodataError> {
+    match value {
+        None => Ok(false),
+        Some(value) => value.parse::<bool>().map_err(|_| {
+            GodataError::invalid_argument(
+                name,
+                value,
+                format!(
+                    "Invalid {} argument {}, expected true or false",
+                    name, value
+                ),
+            )
+        }),
+    }
+}
+
+fn required(params: &mut HashMap<String, String>, name: &str) -> Result<String, GodataError> {
+    match params.remove(name) {
+        Some(value) => Ok(value),
+        None => {
+            tracing::error!("Query missing {} argument", name);
+            Err(GodataError::missing_argument(name))
+        }
+    }
+}
+
+// Writes to an entry can be made conditional on its current revision
+fn if_match() -> impl Filter<Extract = (Option<IfMatch>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(IF_MATCH_HEADER)
+        .map(|header: Option<String>| header.map(|header| IfMatch::parse(&header)))
 }
 
+// The lease a request is acting for, if any
+fn lease() -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(LEASE_HEADER)
+}
+
+// Mutating endpoints take their arguments as a JSON body. Older clients send them as
+// query parameters instead, which is still accepted when the request has no body.
+trait FromQuery: Sized {
+    fn from_query(params: HashMap<String, String>) -> Result<Self, GodataError>;
+}
+
+fn body<T>() -> impl Filter<Extract = (Result<T, GodataError>,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + FromQuery + Send,
+{
+    sized_body(MAX_BODY_SIZE)
+}
+
+fn sized_body<T>(
+    limit: u64,
+) -> impl Filter<Extract = (Result<T, GodataError>,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + FromQuery + Send,
+{
+    warp::query::<HashMap<String, String>>()
+        .and(
+            warp::header::optional::<u64>("content-length")
+                .and_then(move |length: Option<u64>| async move {
+                    match length {
+                        Some(length) if length > limit => Err(warp::reject::custom(
+                            GodataError::new(
+                                GodataErrorType::InvalidArgument,
+                                format!("Request body is larger than the limit of {} bytes", limit),
+                            )
+                            .with_code("payload_too_large"),
+                        )),
+                        _ => Ok(()),
+                    }
+                })
+                .untuple_one(),
+        )
+        .and(warp::body::bytes())
+        .map(|params: HashMap<String, String>, bytes: Bytes| {
+            if bytes.iter().all(|b| b.is_ascii_whitespace()) {
+                return T::from_query(params);
+            }
+            serde_json::from_slice(&bytes).map_err(|e| {
+                GodataError::new(
+                    GodataErrorType::InvalidArgument,
+                    format!("Invalid request body: {}", e),
+                )
+                .with_code("invalid_body")
+            })
+        })
+}
@@ -1,13 +1,19 @@
+use super::{body, flag, required, FromQuery};
+use crate::archive::Compression;
+use crate::diff::DiffSource;
+use crate::errors::{GodataError, GodataErrorType};
 use crate::handlers;
-use crate::project::ProjectManager;
+use crate::project::{ConflictStrategy, ImportOptions, ProjectManager};
+use serde::Deserialize;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::path::PathBuf;
+use std::sync::Arc;
 use tracing::instrument;
-use warp::http::StatusCode;
 use warp::Filter;
+use warp::Reply;
 
 pub(super) fn routes(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     list_collections()
         .or(get_version())
@@ -18,159 +24,385 @@ pub(super) fn routes(
         .or(drop_project(project_manager.clone()))
         .or(project_export_tree(project_manager.clone()))
         .or(import_project_tree(project_manager.clone()))
+        .or(diff_project(project_manager.clone()))
 }
 
 fn get_version() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("version")
         .and(warp::get())
-        .map(handlers::get_version)
+        .and_then(handlers::get_version)
 }
 
 fn list_collections() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("collections")
         .and(warp::get())
         .and(warp::query::<HashMap<String, bool>>())
-        .map(move |p: HashMap<String, bool>| match p.get("show_hidden") {
-            Some(show_hidden) => handlers::list_collections(*show_hidden),
-            None => handlers::list_collections(false),
+        .and_then(move |p: HashMap<String, bool>| async move {
+            match p.get("show_hidden") {
+                Some(show_hidden) => handlers::list_collections(*show_hidden).await,
+                None => handlers::list_collections(false).await,
+            }
         })
 }
 
 fn list_projects(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("projects" / String)
         .and(warp::get())
         .and(warp::query::<HashMap<String, bool>>())
-        .map(
-            move |collection, p: HashMap<String, bool>| match p.get("show_hidden") {
-                Some(show_hidden) => {
-                    handlers::list_projects(project_manager.clone(), collection, *show_hidden)
+        .and_then(move |collection, p: HashMap<String, bool>| {
+            let project_manager = project_manager.clone();
+            async move {
+                match p.get("show_hidden") {
+                    Some(show_hidden) => {
+                        handlers::list_projects(project_manager, collection, *show_hidden).await
+                    }
+                    None => handlers::list_projects(project_manager, collection, false).await,
                 }
-                None => handlers::list_projects(project_manager.clone(), collection, false),
-            },
-        )
+            }
+        })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CreateBody {
+    #[serde(default)]
+    force: bool,
+    storage_location: Option<String>,
+}
+
+impl FromQuery for CreateBody {
+    fn from_query(mut params: HashMap<String, String>) -> Result<Self, GodataError> {
+        Ok(CreateBody {
+            force: flag("force", params.remove("force").as_deref())?,
+            storage_location: params.remove("storage_location"),
+        })
+    }
 }
 
 #[instrument(skip(project_manager))]
 fn create_project(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("create" / String / String)
         .and(warp::post())
-        .and(warp::query::<HashMap<String, String>>())
-        .map(
-            move |collection, project_name, params: HashMap<String, String>| {
-                let force = match params.get("force") {
-                    Some(force) => force.parse::<bool>().unwrap(),
-                    None => false,
-                };
-                let storage_location = params
-                    .get("storage_location")
-                    .map(|storage_location| storage_location.to_owned());
-                handlers::create_project(
-                    project_manager.clone(),
-                    collection,
-                    project_name,
-                    force,
-                    storage_location,
-                )
+        .and(body::<CreateBody>())
+        .and_then(
+            move |collection, project_name, body: Result<CreateBody, GodataError>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let body = match body {
+                        Ok(body) => body,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::create_project(
+                        project_manager,
+                        collection,
+                        project_name,
+                        body.force,
+                        body.storage_location,
+                    )
+                    .await
+                }
             },
         )
 }
 
 #[instrument(skip(project_manager))]
 fn delete_project(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("projects" / String / String)
         .and(warp::delete())
         .and(warp::query::<HashMap<String, String>>())
-        .map(
+        .and_then(
             move |collection, project_name, params: HashMap<String, String>| {
-                let force = match params.get("force") {
-                    Some(force) => force.parse::<bool>().unwrap(),
-                    None => false,
-                };
-                handlers::delete_project(project_manager.clone(), collection, project_name, force)
+                let project_manager = project_manager.clone();
+                async move {
+                    let force = match flag("force", params.get("force").map(|v| v.as_str())) {
+                        Ok(force) => force,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::delete_project(project_manager, collection, project_name, force).await
+                }
             },
         )
 }
 
 fn load_project(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("load" / String / String)
         .and(warp::post())
-        .map(move |collection, project_name| {
-            handlers::load_project(project_manager.clone(), collection, project_name)
+        .and_then(move |collection, project_name| {
+            let project_manager = project_manager.clone();
+            async move { handlers::load_project(project_manager, collection, project_name).await }
         })
 }
 
 fn drop_project(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("drop" / String / String)
         .and(warp::post())
-        .map(move |collection, project_name| {
-            handlers::drop_project(project_manager.clone(), collection, project_name)
+        .and_then(move |collection, project_name| {
+            let project_manager = project_manager.clone();
+            async move { handlers::drop_project(project_manager, collection, project_name).await }
         })
 }
 
 #[instrument(skip(project_manager))]
 fn project_export_tree(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("export" / String / String)
         .and(warp::get())
         .and(warp::query::<HashMap<String, String>>())
-        .map(
+        .and_then(
             move |collection, project_name, params: HashMap<String, String>| {
-                let output_path = match params.get("output_path") {
-                    Some(output_path) => output_path.to_owned(),
-                    None => {
-                        tracing::error!("Missing output_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing output_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
-                        ));
-                    } // invalid request
-                };
-                handlers::export_project_tree(
-                    project_manager.clone(),
-                    collection,
-                    project_name,
-                    output_path,
-                )
+                let project_manager = project_manager.clone();
+                async move {
+                    let output_path = match params.get("output_path") {
+                        Some(output_path) => output_path.to_owned(),
+                        None => {
+                            tracing::error!("Missing output_path argument");
+                            return Ok(GodataError::missing_argument("output_path").into_response());
+                        } // invalid request
+                    };
+                    match params.get("format").map(|f| f.as_str()) {
+                        None | Some("tree") => {
+                            handlers::export_project_tree(
+                                project_manager,
+                                collection,
+                                project_name,
+                                output_path,
+                            )
+                            .await
+                        }
+                        Some("archive") => {
+                            let compression = match Compression::parse(
+                                params.get("compression").map(|c| c.as_str()),
+                            ) {
+                                Ok(compression) => compression,
+                                Err(e) => return Ok(e.into_response()),
+                            };
+                            let include_external = match flag(
+                                "include_external",
+                                params.get("include_external").map(|v| v.as_str()),
+                            ) {
+                                Ok(include_external) => include_external,
+                                Err(e) => return Ok(e.into_response()),
+                            };
+                            handlers::export_project_archive(
+                                project_manager,
+                                collection,
+                                project_name,
+                                output_path,
+                                compression,
+                                include_external,
+                            )
+                            .await
+                        }
+                        Some("manifest") => {
+                            // Checksums are included unless turned off
+                            let checksums = match params.get("checksums") {
+                                None => true,
+                                Some(v) => match flag("checksums", Some(v)) {
+                                    Ok(checksums) => checksums,
+                                    Err(e) => return Ok(e.into_response()),
+                                },
+                            };
+                            handlers::export_project_manifest(
+                                project_manager,
+                                collection,
+                                project_name,
+                                output_path,
+                                checksums,
+                            )
+                            .await
+                        }
+                        Some(format) => {
+                            tracing::error!("Invalid export format {}", format);
+                            Ok(GodataError::invalid_argument(
+                                "format",
+                                format,
+                                format!("Invalid format argument {}", format),
+                            )
+                            .into_response())
+                        }
+                    }
+                }
             },
         )
 }
 
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum ImportFormat {
+    #[default]
+    Tree,
+    Archive,
+    Manifest,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ImportBody {
+    input_path: String,
+    #[serde(default)]
+    format: ImportFormat,
+    #[serde(default)]
+    on_conflict: ConflictStrategy,
+    #[serde(default)]
+    force: bool,
+    storage_location: Option<String>,
+    #[serde(default)]
+    verify: bool,
+}
+
+impl FromQuery for ImportBody {
+    fn from_query(mut params: HashMap<String, String>) -> Result<Self, GodataError> {
+        let input_path = required(&mut params, "input_path")?;
+        let on_conflict = ConflictStrategy::parse(params.remove("on_conflict").as_deref())?;
+        let format = match params.remove("format").as_deref() {
+            None | Some("tree") => ImportFormat::Tree,
+            Some("archive") => ImportFormat::Archive,
+            Some("manifest") => ImportFormat::Manifest,
+            Some(format) => {
+                tracing::error!("Invalid import format {}", format);
+                return Err(GodataError::invalid_argument(
+                    "format",
+                    format,
+                    format!("Invalid format argument {}", format),
+                ));
+            }
+        };
+        Ok(ImportBody {
+            input_path,
+            format,
+            on_conflict,
+            force: flag("force", params.remove("force").as_deref())?,
+            storage_location: params.remove("storage_location"),
+            verify: flag("verify", params.remove("verify").as_deref())?,
+        })
+    }
+}
+
+// Imports used to be GET requests with query parameters. They are now POST only, since
+// an import writes to the server, but the query parameters are still accepted.
 #[instrument(skip(project_manager))]
 fn import_project_tree(
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("import" / String / String)
+        .and(warp::post())
+        .and(body::<ImportBody>())
+        .and_then(
+            move |collection, project_name, body: Result<ImportBody, GodataError>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let body = match body {
+                        Ok(body) => body,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    let options = ImportOptions {
+                        strategy: body.on_conflict,
+                        force: body.force,
+                    };
+                    match body.format {
+                        ImportFormat::Tree => {
+                            handlers::import_project_tree(
+                                project_manager,
+                                collection,
+                                project_name,
+                                body.input_path,
+                                options,
+                            )
+                            .await
+                        }
+                        ImportFormat::Archive => {
+                            handlers::import_project_archive(
+                                project_manager,
+                                collection,
+                                project_name,
+                                body.input_path,
+                                body.storage_location,
+                                options,
+                            )
+                            .await
+                        }
+                        ImportFormat::Manifest => {
+                            handlers::import_project_manifest(
+                                project_manager,
+                                collection,
+                                project_name,
+                                body.input_path,
+                                body.storage_location,
+                                body.verify,
+                                options,
+                            )
+                            .await
+                        }
+                    }
+                }
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn diff_project(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("diff" / String / String)
         .and(warp::get())
         .and(warp::query::<HashMap<String, String>>())
-        .map(
-            move |collection, project_name, params: HashMap<String, String>| {
-                let input_path = match params.get("input_path") {
-                    Some(input_path) => input_path.to_owned(),
-                    None => {
-                        tracing::error!("Missing input_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing input_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
-                        ));
-                    } // invalid request
-                };
-                handlers::import_project_tree(
-                    project_manager.clone(),
-                    collection,
-                    project_name,
-                    input_path,
-                )
+        .and_then(
+            move |collection: String, project_name, params: HashMap<String, String>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let other = match (
+                        params.get("other_project"),
+                        params.get("other_path"),
+                        params.get("format").map(|f| f.as_str()),
+                    ) {
+                        (None, None, _) if params.contains_key("other_snapshot") => {
+                            DiffSource::Snapshot(params["other_snapshot"].to_owned())
+                        }
+                        (Some(other_project), None, _) => DiffSource::Project {
+                            name: other_project.to_owned(),
+                            collection: params
+                                .get("other_collection")
+                                .cloned()
+                                .unwrap_or(collection.clone()),
+                        },
+                        (None, Some(path), None | Some("tree")) => {
+                            DiffSource::Tree(PathBuf::from(path))
+                        }
+                        (None, Some(path), Some("manifest")) => {
+                            DiffSource::Manifest(PathBuf::from(path))
+                        }
+                        _ => {
+                            tracing::error!("Invalid arguments for diff");
+                            return Ok(GodataError::new(
+                                GodataErrorType::InvalidArgument,
+                                "Expected exactly one of other_project, other_snapshot or \
+                                 other_path, with format `tree` or `manifest`"
+                                    .to_string(),
+                            )
+                            .with_code("invalid_diff_source")
+                            .into_response());
+                        }
+                    };
+                    handlers::diff_project(
+                        project_manager,
+                        collection,
+                        project_name,
+                        params.get("snapshot").cloned(),
+                        other,
+                    )
+                    .await
+                }
             },
         )
 }
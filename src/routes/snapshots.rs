@@ -0,0 +1,110 @@
+use super::{body, required, FromQuery};
+use crate::errors::GodataError;
+use crate::handlers;
+use crate::project::ProjectManager;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::instrument;
+use warp::Filter;
+use warp::Reply;
+
+pub(super) fn routes(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    create_snapshot(project_manager.clone())
+        .or(list_snapshots(project_manager.clone()))
+        .or(restore_snapshot(project_manager.clone()))
+        .or(delete_snapshot(project_manager.clone()))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SnapshotBody {
+    name: String,
+}
+
+impl FromQuery for SnapshotBody {
+    fn from_query(mut params: HashMap<String, String>) -> Result<Self, GodataError> {
+        Ok(SnapshotBody {
+            name: required(&mut params, "name")?,
+        })
+    }
+}
+
+#[instrument(skip(project_manager))]
+fn create_snapshot(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "snapshots")
+        .and(warp::post())
+        .and(body::<SnapshotBody>())
+        .and_then(
+            move |collection, project_name, body: Result<SnapshotBody, GodataError>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let body = match body {
+                        Ok(body) => body,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::create_snapshot(project_manager, collection, project_name, body.name)
+                        .await
+                }
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn list_snapshots(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "snapshots")
+        .and(warp::get())
+        .and_then(move |collection, project_name| {
+            let project_manager = project_manager.clone();
+            async move { handlers::list_snapshots(project_manager, collection, project_name).await }
+        })
+}
+
+#[instrument(skip(project_manager))]
+fn restore_snapshot(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "snapshots" / "restore")
+        .and(warp::post())
+        .and(body::<SnapshotBody>())
+        .and_then(
+            move |collection, project_name, body: Result<SnapshotBody, GodataError>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let body = match body {
+                        Ok(body) => body,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::restore_snapshot(project_manager, collection, project_name, body.name)
+                        .await
+                }
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn delete_snapshot(
+    project_manager: Arc<ProjectManager>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "snapshots")
+        .and(warp::delete())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let project_manager = project_manager.clone();
+                async move {
+                    let name = match required(&mut params, "name") {
+                        Ok(name) => name,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    handlers::delete_snapshot(project_manager, collection, project_name, name).await
+                }
+            },
+        )
+}
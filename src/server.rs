@@ -1,29 +1,107 @@
+use crate::auth::AuthManager;
+use crate::config;
 use crate::project::{get_project_manager, ProjectManager};
+use crate::request::{self, Client};
 use crate::routes;
+use crate::tls;
 
-use directories::UserDirs;
-use std::sync::{Arc, Mutex};
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::fs::{DirBuilder, Permissions};
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::sync::Arc;
+use std::time::Duration;
 use sysinfo::System;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
 use tokio::signal;
-use tokio_stream::wrappers::UnixListenerStream;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
 use tracing::instrument;
-use warp::Filter;
+use uuid::Uuid;
+use warp::hyper::{
+    self,
+    server::accept,
+    service::{make_service_fn, service_fn},
+};
 
 pub struct Server {
-    project_manager: Arc<Mutex<ProjectManager>>,
+    project_manager: Arc<ProjectManager>,
+    auth_manager: Arc<AuthManager>,
     url: (String, Option<u16>),
+    addresses: Vec<SocketAddr>,
+    tls: Option<TlsAcceptor>,
+    socket_mode: u32,
+    allowed_uids: Vec<u32>,
 }
 
+// Any connection hyper can serve, so plain TCP and TLS streams can share a listener
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send + Peer {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Peer> Connection for T {}
+
+// What a connection tells us about the client on the other end
+trait Peer {
+    fn client(&self) -> Client;
+}
+
+impl Peer for UnixStream {
+    fn client(&self) -> Client {
+        Client {
+            uid: self.peer_cred().ok().map(|cred| cred.uid()),
+            ..Client::default()
+        }
+    }
+}
+
+impl Peer for TcpStream {
+    fn client(&self) -> Client {
+        Client {
+            address: self.peer_addr().ok().map(|address| address.to_string()),
+            ..Client::default()
+        }
+    }
+}
+
+impl Peer for TlsStream<TcpStream> {
+    fn client(&self) -> Client {
+        self.get_ref().0.client()
+    }
+}
+
+impl Peer for Box<dyn Connection> {
+    fn client(&self) -> Client {
+        self.as_ref().client()
+    }
+}
+
+// Number of TLS handshakes that may be in progress at the same time
+const MAX_PENDING_HANDSHAKES: usize = 64;
+// A client that doesn't finish its handshake in time is dropped, so clients that connect
+// and send nothing can't hold up everyone else
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 impl Server {
     pub async fn start(&self) {
         // If there's a port, start a TCP server
 
         if self.url.1.is_some() {
-            let (_, server) = warp::serve(routes::routes(self.project_manager.clone()))
-                .bind_with_graceful_shutdown(([127, 0, 0, 1], self.url.1.unwrap()), async {
-                    signal::ctrl_c().await.unwrap()
-                });
-            server.await
+            let mut listeners = Vec::new();
+            for address in self.addresses.iter() {
+                match TcpListener::bind(address).await {
+                    Ok(listener) => listeners.push(TcpListenerStream::new(listener)),
+                    Err(e) => {
+                        tracing::error!("Failed to bind to {}: {}", address, e);
+                        println!("Unable to listen on {}: {}", address, e);
+                        return;
+                    }
+                }
+            }
+            let incoming = tcp_incoming(listeners, self.tls.clone());
+            self.serve(incoming).await
         }
         // If there's no port, start a Unix socket server
         else {
@@ -36,27 +114,173 @@ impl Server {
                     println!("A server is already running on {}", self.url.0);
                     return;
                 }
-                std::fs::remove_file(&self.url.0).unwrap();
+                if let Err(e) = std::fs::remove_file(&self.url.0) {
+                    tracing::error!("Failed to remove stale socket {}: {}", self.url.0, e);
+                    println!("Unable to remove stale socket {}: {}", self.url.0, e);
+                    return;
+                }
             }
-            let listener = tokio::net::UnixListener::bind(&self.url.0).unwrap();
-            let incoming = UnixListenerStream::new(listener);
-            let server = warp::serve(routes::routes(self.project_manager.clone()).with(
-                warp::trace(|info| {
-                    let request_id = uuid::Uuid::new_v4();
-                    tracing::info_span!(
-                        "request",
-                        request_id = %request_id,
-                        method = %info.method(),
-                        path = %info.path(),
-                    )
-                }),
-            ))
-            .serve_incoming_with_graceful_shutdown(incoming, async {
-                signal::ctrl_c().await.unwrap()
-            });
-            server.await
+            // The socket is owned by the user running the server. Other users are refused
+            // both by the file permissions and by checking the credentials of each peer.
+            let socket_path = std::path::Path::new(&self.url.0);
+            let listener = match bind_socket(socket_path, self.socket_mode) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    tracing::error!("Failed to listen on {}: {}", self.url.0, e);
+                    println!("Unable to listen on {}: {}", self.url.0, e);
+                    return;
+                }
+            };
+            let owner = match std::fs::metadata(socket_path) {
+                Ok(metadata) => metadata.uid(),
+                Err(e) => {
+                    tracing::error!("Failed to read {}: {}", self.url.0, e);
+                    println!("Unable to read {}: {}", self.url.0, e);
+                    return;
+                }
+            };
+            let mut allowed_uids: HashSet<u32> = self.allowed_uids.iter().copied().collect();
+            allowed_uids.insert(owner);
+            let incoming = unix_incoming(UnixListenerStream::new(listener), allowed_uids);
+            self.serve(incoming).await
         };
     }
+
+    async fn serve<S, IO>(&self, incoming: S)
+    where
+        S: Stream<Item = io::Result<IO>> + Send + 'static,
+        IO: AsyncRead + AsyncWrite + Unpin + Send + Peer + 'static,
+    {
+        // Requests go through the same tracing wrapper whatever the transport is
+        let service = warp::service(routes::routes(
+            self.project_manager.clone(),
+            self.auth_manager.clone(),
+        ));
+        let make_service = make_service_fn(move |conn: &IO| {
+            let service = service.clone();
+            let client = conn.client();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    request::handle(service.clone(), client.clone(), request)
+                }))
+            }
+        });
+        // Event streams stay open until they are closed, so they are closed before
+        // waiting for the remaining requests to finish
+        let project_manager = self.project_manager.clone();
+        let server = hyper::Server::builder(accept::from_stream(incoming))
+            .serve(make_service)
+            .with_graceful_shutdown(async move {
+                signal::ctrl_c().await.unwrap();
+                project_manager.close_events();
+            });
+        if let Err(e) = server.await {
+            tracing::error!("Server error: {}", e);
+        }
+    }
+}
+
+// A socket gets default permissions when it's bound, so it's bound in a directory only
+// the server's user can enter, given its permissions there and only then moved into place
+fn bind_socket(path: &std::path::Path, mode: u32) -> io::Result<UnixListener> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => std::path::Path::new("."),
+    };
+    let staging = parent.join(format!(".godata-{}", Uuid::new_v4()));
+    DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("socket");
+    let result = UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, Permissions::from_mode(mode))?;
+        std::fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    if let Err(e) = std::fs::remove_dir_all(&staging) {
+        tracing::warn!("Failed to remove {}: {}", staging.display(), e);
+    }
+    result
+}
+
+fn tcp_incoming(
+    listeners: Vec<TcpListenerStream>,
+    tls: Option<TlsAcceptor>,
+) -> BoxStream<'static, io::Result<Box<dyn Connection>>> {
+    // A failed accept or handshake only affects that one client, so errors are logged
+    // and dropped rather than passed on to hyper, which would stop the server.
+    let accepted = stream::select_all(listeners).filter_map(|conn| async move {
+        match conn {
+            Ok(conn) => Some(conn),
+            Err(e) => {
+                tracing::warn!("Failed to accept connection: {}", e);
+                None
+            }
+        }
+    });
+    match tls {
+        None => accepted
+            .map(|conn| Ok(Box::new(conn) as Box<dyn Connection>))
+            .boxed(),
+        Some(acceptor) => accepted
+            .map(move |conn| tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(conn)))
+            .buffer_unordered(MAX_PENDING_HANDSHAKES)
+            .filter_map(|conn| async move {
+                match conn {
+                    Ok(Ok(conn)) => Some(Ok(Box::new(conn) as Box<dyn Connection>)),
+                    Ok(Err(e)) => {
+                        tracing::warn!("TLS handshake failed: {}", e);
+                        None
+                    }
+                    Err(_) => {
+                        tracing::warn!(
+                            "TLS handshake timed out after {} seconds",
+                            HANDSHAKE_TIMEOUT.as_secs()
+                        );
+                        None
+                    }
+                }
+            })
+            .boxed(),
+    }
+}
+
+fn unix_incoming(
+    listener: UnixListenerStream,
+    allowed_uids: HashSet<u32>,
+) -> BoxStream<'static, io::Result<UnixStream>> {
+    listener
+        .filter_map(move |conn| {
+            let allowed = match &conn {
+                Ok(conn) => match conn.peer_cred() {
+                    Ok(cred) => {
+                        let allowed = allowed_uids.contains(&cred.uid());
+                        if allowed {
+                            tracing::debug!(
+                                "Accepted connection from uid {} (pid {:?})",
+                                cred.uid(),
+                                cred.pid()
+                            );
+                        } else {
+                            tracing::warn!(
+                                "Refused connection from uid {} (pid {:?})",
+                                cred.uid(),
+                                cred.pid()
+                            );
+                        }
+                        allowed
+                    }
+                    Err(e) => {
+                        tracing::warn!("Unable to read peer credentials: {}", e);
+                        false
+                    }
+                },
+                Err(e) => {
+                    tracing::warn!("Failed to accept connection: {}", e);
+                    false
+                }
+            };
+            async move { allowed.then_some(conn) }
+        })
+        .boxed()
 }
 
 impl Drop for Server {
@@ -65,24 +289,53 @@ impl Drop for Server {
         if self.url.1.is_some() {
             return;
         }
-        std::fs::remove_file(&self.url.0).unwrap();
+        if let Err(e) = std::fs::remove_file(&self.url.0) {
+            if e.kind() != io::ErrorKind::NotFound {
+                tracing::error!("Failed to remove socket {}: {}", self.url.0, e);
+            }
+        }
     }
 }
 
 #[instrument]
-pub fn get_server(port: Option<u16>) -> Server {
+pub fn get_server() -> Server {
     tracing::info!("Getting server");
+    let config = config::get();
+    let port = config.server.port;
+    let addresses = config.bind_addresses();
+    let tls = match config.tls_paths() {
+        Ok(Some((cert, key))) => match tls::load_acceptor(&cert, &key) {
+            Ok(acceptor) => Some(acceptor),
+            Err(e) => {
+                tracing::error!("Failed to load TLS certificate: {}", e.message);
+                panic!("Failed to load TLS certificate: {}", e.message);
+            }
+        },
+        Ok(None) => None,
+        Err(e) => panic!("{}", e.message),
+    };
     let url = match port {
-        Some(p) => format!("localhost:{}", p),
-        None => UserDirs::new()
-            .unwrap()
-            .home_dir()
-            .join(".godata.sock")
-            .to_str()
-            .unwrap()
-            .to_string(),
+        Some(_) => {
+            let scheme = if tls.is_some() { "https" } else { "http" };
+            addresses
+                .iter()
+                .map(|a| format!("{}://{}", scheme, a))
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+        None => config.socket_path().to_str().unwrap().to_string(),
     };
     println!("Starting godata server on {}", url);
+    if port.is_some() && !config.auth.enabled {
+        for address in addresses.iter().filter(|a| !a.ip().is_loopback()) {
+            tracing::warn!("Listening on {} without authentication", address);
+            println!(
+                "Warning: listening on {} without authentication, anyone who can reach it \
+                 has full access. Start the server with --auth to require tokens.",
+                address
+            );
+        }
+    }
     let project_manager = get_project_manager();
     if project_manager.is_err() {
         tracing::error!(
@@ -91,8 +344,20 @@ pub fn get_server(port: Option<u16>) -> Server {
         );
         panic!("Failed to initialize project manager");
     }
+    let auth_manager = match AuthManager::get_manager(config.auth.enabled) {
+        Ok(auth_manager) => auth_manager,
+        Err(e) => {
+            tracing::error!("Failed to initialize auth manager: {:?}", e);
+            panic!("Failed to initialize auth manager");
+        }
+    };
     Server {
-        project_manager: Arc::new(Mutex::new(project_manager.unwrap())),
+        project_manager: Arc::new(project_manager.unwrap()),
+        auth_manager: Arc::new(auth_manager),
         url: (url, port),
+        addresses,
+        tls,
+        socket_mode: config.server.socket_mode,
+        allowed_uids: config.server.allowed_uids.clone(),
     }
 }
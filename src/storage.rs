@@ -1,5 +1,5 @@
 use crate::errors::{GodataError, GodataErrorType, Result};
-use crate::locations::get_default_storage_dir;
+use crate::locations::{get_default_storage_dir, path_to_string};
 use sled::Db;
 use std::fs;
 use std::path::Path;
@@ -13,7 +13,7 @@ pub(crate) struct StorageManager {
 
 impl StorageManager {
     pub(crate) fn get_manager() -> Result<StorageManager> {
-        let default_storage_dir = get_default_storage_dir().unwrap();
+        let default_storage_dir = get_default_storage_dir()?;
         let db_location = default_storage_dir.join(".db");
         let db = sled::open(db_location)?;
         Ok(StorageManager {
@@ -31,16 +31,31 @@ impl StorageManager {
         path: PathBuf,
     ) -> Result<()> {
         let key = format!("{}/{}", name, collection);
-        let value = format!("{}:{}", endpoint, path.to_str().unwrap());
-        if !path.exists() {
-            fs::create_dir_all(&path)?;
-        }
-        if self.storage_db.contains_key(&key).unwrap() {
+        if self.storage_db.contains_key(&key)? {
             tracing::error!("Tried to add project that already exists");
             return Err(GodataError::new(
                 GodataErrorType::AlreadyExists,
                 "Project already exists".to_string(),
-            ));
+            )
+            .with_code("project_exists")
+            .with_details(serde_json::json!({"collection": collection, "project": name})));
+        }
+        self.set(name, collection, endpoint, path)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn set(
+        &self,
+        name: &str,
+        collection: &str,
+        endpoint: &str,
+        path: PathBuf,
+    ) -> Result<()> {
+        // Record where a project's data is stored, replacing any existing record
+        let key = format!("{}/{}", name, collection);
+        let value = format!("{}:{}", endpoint, path_to_string(&path)?);
+        if !path.exists() {
+            fs::create_dir_all(&path)?;
         }
         self.storage_db.insert(key, value.as_bytes())?;
         Ok(())
@@ -48,7 +63,7 @@ impl StorageManager {
 
     pub(crate) fn get(&self, name: &str, collection: &str) -> Result<(String, PathBuf)> {
         let key = format!("{}/{}", name, collection);
-        let value = self.storage_db.get(key).unwrap();
+        let value = self.storage_db.get(key)?;
         let value = match value {
             None => {
                 return Err(GodataError::new(
@@ -57,24 +72,34 @@ impl StorageManager {
                         "Storage information not found for project `{}/{}`",
                         collection, name
                     ),
-                ));
+                )
+                .with_code("storage_not_found")
+                .with_details(serde_json::json!({"collection": collection, "project": name})));
             }
             Some(value) => value,
         };
 
-        let value = String::from_utf8(value.to_vec()).unwrap();
-        let mut split = value.split(':');
-        if split.clone().count() != 2 {
-            tracing::error!("Storage information is corrupted, found {}", value);
-            return Err(GodataError::new(
-                GodataErrorType::InternalError,
-                format!("Storage information for project `{}` is corrupted", name),
-            ));
+        let value = String::from_utf8_lossy(&value);
+        match value.split_once(':') {
+            Some((endpoint, path)) if !path.contains(':') => {
+                Ok((endpoint.to_string(), Path::new(path).to_path_buf()))
+            }
+            _ => {
+                tracing::error!("Storage information is corrupted, found {}", value);
+                Err(GodataError::new(
+                    GodataErrorType::InternalError,
+                    format!("Storage information for project `{}` is corrupted", name),
+                )
+                .with_code("storage_corrupted"))
+            }
         }
-        let endpoint = split.next().unwrap();
-        let path = split.next().unwrap();
-        let path = Path::new(path);
-        Ok((endpoint.to_string(), path.to_path_buf()))
+    }
+
+    pub(crate) fn forget(&self, name: &str, collection: &str) -> Result<()> {
+        // Remove the storage record for a project without touching the data
+        let key = format!("{}/{}", name, collection);
+        self.storage_db.remove(key)?;
+        Ok(())
     }
 
     pub(crate) fn delete(&self, name: &str, collection: &str) -> Result<()> {
@@ -82,8 +107,10 @@ impl StorageManager {
         let path = self.get(name, collection)?;
         self.storage_db.remove(key)?;
         fs::remove_dir_all(&path.1)?;
-        if path.1.parent().unwrap().read_dir()?.count() == 0 {
-            fs::remove_dir(path.1.parent().unwrap())?;
+        if let Some(parent) = path.1.parent() {
+            if parent.read_dir()?.count() == 0 {
+                fs::remove_dir(parent)?;
+            }
         }
         Ok(())
     }
@@ -92,19 +119,13 @@ impl StorageManager {
 pub(crate) trait StorageEndpoint {
     // Represents a type of location data can be stored. For example, local disk,
     // a remote serve, etc...
-    // Responsible for producing fully qualified paths to data, and checking that the
-    // endpoint is actually available, providing sensible errors if not.
+    // Responsible for producing fully qualified paths to data.
 
     // It is not actually responsible for reading or writing data. Since this is a
     // library designed for loading and storing data in python, we leave the actual
     // reading and writing to python.
 
     fn generate_path(&self, project_path: &str) -> Result<PathBuf>;
-    fn is_available(&self) -> Result<()>;
-    fn discover_file(&self, project_path: &str, file_extension: String) -> Result<PathBuf>;
-    fn move_file(&self, from: &str, to: &str) -> Result<()>;
-    fn copy_file(&self, from: &str, to: &str) -> Result<()>;
-    fn delete_file(&self, path: &str) -> Result<()>;
     fn is_internal(&self, path: &Path) -> bool;
     fn get_relative_path(&self, path: &Path) -> PathBuf;
     fn resolve(&self, relpath: &Path) -> PathBuf;
@@ -135,49 +156,6 @@ impl StorageEndpoint for LocalEndpoint {
         path.starts_with(&self.root_path)
     }
 
-    fn is_available(&self) -> Result<()> {
-        // Check that the local disk is available.
-        Ok(())
-    }
-
-    fn discover_file(&self, project_path: &str, file_extension: String) -> Result<PathBuf> {
-        let real_path = self.generate_path(project_path)?;
-        let expected_file_path = real_path.with_extension(&file_extension);
-        if expected_file_path.exists() {
-            return Ok(expected_file_path);
-        }
-        Err(GodataError::new(
-            GodataErrorType::NotFound,
-            format!(
-                "File with extension {} not found in project path {}",
-                file_extension, project_path
-            ),
-        ))
-    }
-
-    fn move_file(&self, from: &str, to: &str) -> Result<()> {
-        let from_path = self.generate_path(from)?;
-        let to_path = self.generate_path(to)?;
-        // copy the file
-        fs::rename(from_path, to_path)?;
-        Ok(())
-    }
-    fn copy_file(&self, from: &str, to: &str) -> Result<()> {
-        let from_path = self.generate_path(from)?;
-        let to_path = self.generate_path(to)?;
-        fs::copy(from_path, to_path)?;
-        Ok(())
-    }
-    fn delete_file(&self, path: &str) -> Result<()> {
-        let real_path = self.generate_path(path)?;
-        fs::remove_file(path)?;
-        let parent_directory = real_path.parent().unwrap();
-        if parent_directory.read_dir()?.count() == 0 {
-            fs::remove_dir(parent_directory)?;
-        }
-        Ok(())
-    }
-
     fn get_relative_path(&self, path: &Path) -> PathBuf {
         let result = path.strip_prefix(&self.root_path);
 
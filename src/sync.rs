@@ -0,0 +1,28 @@
+// Locking helpers. A lock is poisoned when a thread panics while holding it, which
+// would otherwise turn every later `lock().unwrap()` on it into another panic. Here a
+// poisoned lock becomes an ordinary error, so the request that hits it gets an error
+// response and the server keeps running.
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+    mutex.lock().map_err(|_| poisoned())
+}
+
+pub(crate) fn read<T>(lock: &RwLock<T>) -> Result<RwLockReadGuard<'_, T>> {
+    lock.read().map_err(|_| poisoned())
+}
+
+pub(crate) fn write<T>(lock: &RwLock<T>) -> Result<RwLockWriteGuard<'_, T>> {
+    lock.write().map_err(|_| poisoned())
+}
+
+fn poisoned() -> GodataError {
+    tracing::error!("Found a poisoned lock, a previous request panicked while holding it");
+    GodataError::new(
+        GodataErrorType::InternalError,
+        "A previous request failed while holding this resource".to_string(),
+    )
+    .with_code("lock_poisoned")
+}
@@ -0,0 +1,72 @@
+// TLS support for the TCP server. Certificates and keys are read from PEM files, so a
+// self-signed pair generated with e.g.
+//
+//     openssl req -x509 -newkey rsa:4096 -nodes -days 365 \
+//         -keyout key.pem -out cert.pem -subj "/CN=localhost"
+//
+// is enough to test locally.
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use std::fs;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+pub(crate) fn load_acceptor(cert_path: &Path, key_path: &Path) -> Result<TlsAcceptor> {
+    let mut reader = BufReader::new(open(cert_path)?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| invalid_pem(cert_path, e))?;
+    if certs.is_empty() {
+        return Err(GodataError::new(
+            GodataErrorType::InvalidArgument,
+            format!("No certificates found in `{}`", cert_path.display()),
+        )
+        .with_code("invalid_tls_certificate"));
+    }
+
+    let mut reader = BufReader::new(open(key_path)?);
+    let key = match rustls_pemfile::private_key(&mut reader) {
+        Ok(Some(key)) => key,
+        Ok(None) => {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidArgument,
+                format!("No private key found in `{}`", key_path.display()),
+            )
+            .with_code("invalid_tls_key"))
+        }
+        Err(e) => return Err(invalid_pem(key_path, e)),
+    };
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| {
+            GodataError::new(
+                GodataErrorType::InvalidArgument,
+                format!("Invalid TLS certificate or key: {}", e),
+            )
+            .with_code("invalid_tls_certificate")
+        })?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn open(path: &Path) -> Result<fs::File> {
+    fs::File::open(path).map_err(|e| {
+        GodataError::new(
+            GodataErrorType::NotFound,
+            format!("Unable to open `{}`: {}", path.display(), e),
+        )
+        .with_code("tls_file_not_found")
+    })
+}
+
+fn invalid_pem(path: &Path, e: std::io::Error) -> GodataError {
+    GodataError::new(
+        GodataErrorType::InvalidArgument,
+        format!("Failed to parse PEM file `{}`: {}", path.display(), e),
+    )
+    .with_code("invalid_pem")
+}
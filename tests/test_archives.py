@@ -0,0 +1,96 @@
+import os
+from pathlib import Path
+
+import pytest
+
+from godata import create_project, load_project
+
+from .utils import real_path, server_request
+
+data_path = Path(os.environ.get("DATA_PATH"))
+
+
+@pytest.fixture(scope="module")
+def project():
+    p = create_project("test_archives")
+    p.store({"a": 1}, "internal/config")
+    p.link(data_path / "test_json.json", "external/data", metadata={"kind": "json"})
+    return p
+
+
+def export_archive(output_path: Path, include_external: bool = False):
+    return server_request(
+        "GET",
+        "export/default/test_archives",
+        params={
+            "output_path": str(output_path),
+            "format": "archive",
+            "include_external": str(include_external).lower(),
+        },
+    )
+
+
+def import_archive(name: str, input_path: Path, **options):
+    body = {"input_path": str(input_path), "format": "archive", **options}
+    return server_request("POST", f"import/default/{name}", json=body)
+
+
+def test_archive_round_trip(project, tmp_path):
+    archive = tmp_path / "project.tar"
+    resp = export_archive(archive)
+    assert resp.ok, resp.text
+    resp = import_archive("test_archives_copy", archive)
+    assert resp.ok, resp.text
+
+    copy = load_project("test_archives_copy")
+    assert copy.get("internal/config") == {"a": 1}
+    assert copy.get_metadata("external/data")["kind"] == "json"
+    # Internal files are copied into the new project, external ones still point
+    # to where they were
+    assert real_path("default", "test_archives_copy", "internal/config") != real_path(
+        "default", "test_archives", "internal/config"
+    )
+    assert real_path("default", "test_archives_copy", "external/data") == str(
+        data_path / "test_json.json"
+    )
+
+
+def test_archive_round_trip_with_external_files(project, tmp_path):
+    archive = tmp_path / "project.tar"
+    resp = export_archive(archive, include_external=True)
+    assert resp.ok, resp.text
+    resp = import_archive("test_archives_external", archive)
+    assert resp.ok, resp.text
+
+    path = Path(real_path("default", "test_archives_external", "external/data"))
+    assert path != data_path / "test_json.json"
+    assert path.read_bytes() == (data_path / "test_json.json").read_bytes()
+
+
+def test_archive_import_into_existing_project(project, tmp_path):
+    archive = tmp_path / "project.tar"
+    assert export_archive(archive).ok
+
+    existing = create_project("test_archives_merge")
+    existing.store({"b": 2}, "internal/other")
+    resp = import_archive("test_archives_merge", archive)
+    assert resp.status_code == 409
+
+    resp = import_archive("test_archives_merge", archive, on_conflict="merge")
+    assert resp.ok, resp.text
+    assert resp.json()["imported"] == 2
+    assert existing.get("internal/config") == {"a": 1}
+    assert existing.get("internal/other") == {"b": 2}
+
+
+def test_archive_import_replaces_project(project, tmp_path):
+    archive = tmp_path / "project.tar"
+    assert export_archive(archive).ok
+
+    existing = create_project("test_archives_replace")
+    existing.store({"b": 2}, "internal/other")
+    resp = import_archive("test_archives_replace", archive, on_conflict="replace")
+    assert resp.ok, resp.text
+    replaced = load_project("test_archives_replace")
+    assert replaced.get("internal/config") == {"a": 1}
+    assert not replaced.has_path("internal/other")
@@ -0,0 +1,148 @@
+import os
+import socket
+import subprocess
+import time
+from pathlib import Path
+
+import pytest
+import requests
+
+from godata.server.config import get_config
+
+data_path = Path(os.environ.get("DATA_PATH"))
+
+
+def free_port():
+    with socket.socket() as s:
+        s.bind(("127.0.0.1", 0))
+        return s.getsockname()[1]
+
+
+@pytest.fixture(scope="module")
+def server(tmp_path_factory):
+    """
+    A separate server that requires tokens, so the main test server can stay open
+    """
+    home = tmp_path_factory.mktemp("auth_server")
+    binary = str(get_config().server_path)
+    args = ["--storage-dir", str(home / "storage"), "--data-dir", str(home / "data")]
+    env = {**os.environ, "HOME": str(home)}
+    output = subprocess.check_output(
+        [binary, "--create-token", "root", *args], env=env, text=True
+    )
+    admin_token = output.strip().splitlines()[-1]
+
+    port = free_port()
+    process = subprocess.Popen(
+        [binary, "--port", str(port), "--auth", *args],
+        env=env,
+        stdout=subprocess.DEVNULL,
+        stderr=subprocess.DEVNULL,
+    )
+    url = f"http://127.0.0.1:{port}"
+    for _ in range(50):
+        try:
+            requests.get(f"{url}/version")
+            break
+        except requests.exceptions.ConnectionError:
+            time.sleep(0.1)
+    yield url, admin_token
+    process.terminate()
+    process.wait()
+
+
+def session(token: str = None):
+    s = requests.Session()
+    if token:
+        s.headers["Authorization"] = f"Bearer {token}"
+    return s
+
+
+def create_token(server, level: str, scope: str = "auth/p"):
+    url, admin_token = server
+    resp = session(admin_token).post(
+        f"{url}/admin/tokens",
+        json={"name": level, "grants": [{"scope": scope, "level": level}]},
+    )
+    assert resp.ok, resp.text
+    return resp.json()["token"]
+
+
+@pytest.fixture(scope="module")
+def project(server):
+    url, admin_token = server
+    admin = session(admin_token)
+    resp = admin.post(f"{url}/create/auth/p", params={"force": "true"})
+    assert resp.ok, resp.text
+    resp = admin.post(f"{url}/create/auth/other", params={"force": "true"})
+    assert resp.ok, resp.text
+    return url
+
+
+def link(s: requests.Session, url: str, project_path: str):
+    return s.post(
+        f"{url}/projects/auth/p/files",
+        json={
+            "project_path": project_path,
+            "real_path": str(data_path / "test_ones.npy"),
+            "type": "file",
+        },
+    )
+
+
+def test_token_required(server, project):
+    url, _ = server
+    assert session().get(f"{url}/version").ok
+    resp = session().get(f"{url}/projects/auth/p/list")
+    assert resp.status_code == 401
+    resp = session("gd_not_a_token").get(f"{url}/projects/auth/p/list")
+    assert resp.status_code == 401
+
+
+def test_read_token(server, project):
+    url = project
+    reader = session(create_token(server, "read"))
+    assert reader.get(f"{url}/projects/auth/p/list").ok
+
+    assert link(reader, url, "data").status_code == 403
+    assert reader.get(f"{url}/projects/auth/other/list").status_code == 403
+    assert reader.get(f"{url}/projects/auth/p/hooks").status_code == 403
+    resp = reader.get(f"{url}/admin/tokens")
+    assert resp.status_code == 403
+
+
+def test_write_token(server, project):
+    url = project
+    writer = session(create_token(server, "write"))
+    resp = link(writer, url, "data")
+    assert resp.ok, resp.text
+
+    # Hooks run commands on the server, and deleting a project can't be undone
+    assert writer.get(f"{url}/projects/auth/p/hooks").status_code == 403
+    assert writer.delete(f"{url}/projects/auth/p").status_code == 403
+
+
+def test_server_paths_need_admin(server, project, tmp_path):
+    url = project
+    owner = session(create_token(server, "admin"))
+    resp = owner.get(
+        f"{url}/export/auth/p", params={"output_path": str(tmp_path / "out")}
+    )
+    assert resp.status_code == 403
+    resp = owner.get(f"{url}/diff/auth/p", params={"other_path": str(tmp_path)})
+    assert resp.status_code == 403
+    resp = owner.get(f"{url}/diff/auth/p", params={"other_project": "other"})
+    assert resp.status_code == 403
+
+    _, admin_token = server
+    resp = session(admin_token).get(
+        f"{url}/export/auth/p", params={"output_path": str(tmp_path / "out")}
+    )
+    assert resp.ok, resp.text
+
+
+def test_unknown_and_encoded_routes_are_denied(server, project):
+    url = project
+    reader = session(create_token(server, "read"))
+    assert reader.get(f"{url}/projects/auth/p%2F..%2Fother/list").status_code == 403
+    assert reader.get(f"{url}/not/a/route").status_code == 403
@@ -0,0 +1,62 @@
+import os
+from pathlib import Path
+
+import pytest
+
+from godata import create_project
+from godata.client.client import apply_batch, query_audit
+
+data_path = Path(os.environ.get("DATA_PATH"))
+
+
+def operations(prefix: str):
+    return [
+        {
+            "op": "link",
+            "project_path": f"{prefix}/ones",
+            "real_path": data_path / "test_ones.npy",
+        },
+        {
+            "op": "metadata",
+            "project_path": f"{prefix}/ones",
+            "metadata": {"checked": True},
+        },
+        {"op": "remove", "project_path": f"{prefix}/missing"},
+        {
+            "op": "link",
+            "project_path": f"{prefix}/df",
+            "real_path": data_path / "test_df.csv",
+        },
+    ]
+
+
+def test_atomic_batch_rolls_back():
+    p = create_project("test_batch_atomic")
+    before = query_audit("default", "test_batch_atomic")["entries"]
+
+    with pytest.raises(FileNotFoundError) as e:
+        apply_batch("default", "test_batch_atomic", operations("data"), atomic=True)
+    assert e.value.code == "batch_failed"
+
+    assert not p.has_path("data/ones")
+    assert not p.has_path("data/df")
+    # Nothing that was rolled back shows up in the audit log
+    assert query_audit("default", "test_batch_atomic")["entries"] == before
+
+
+def test_batch_applies_remaining_operations():
+    p = create_project("test_batch_partial")
+    result = apply_batch("default", "test_batch_partial", operations("data"))
+    assert result["applied"] == 3
+    assert result["failed"] == 1
+    assert [r["status"] for r in result["results"]] == [
+        "applied",
+        "applied",
+        "failed",
+        "applied",
+    ]
+    assert p.get_metadata("data/ones")["checked"] is True
+    assert p.has_path("data/df")
+
+    entries = query_audit("default", "test_batch_partial")["entries"]
+    assert [e["action"] for e in entries] == ["link", "metadata", "link"]
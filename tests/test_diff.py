@@ -0,0 +1,66 @@
+import os
+from pathlib import Path
+
+from godata import create_project
+
+from .utils import server_request
+
+data_path = Path(os.environ.get("DATA_PATH"))
+
+
+def diff(project: str, **params):
+    resp = server_request("GET", f"diff/default/{project}", params=params)
+    assert resp.ok, resp.text
+    return resp.json()
+
+
+def test_diff_projects():
+    before = create_project("test_diff_before")
+    before.link(data_path / "test_ones.npy", "kept", metadata={"version": 1})
+    before.link(data_path / "test_df.csv", "removed")
+    before.link(data_path / "test_json.json", "moved")
+    after = create_project("test_diff_after")
+    after.link(data_path / "test_ones.npy", "kept", metadata={"version": 2})
+    after.link(data_path / "test_df.csv", "added")
+    after.link(data_path / "test_fits.fits", "moved")
+
+    result = diff("test_diff_before", other_project="test_diff_after")
+    assert [f["path"] for f in result["added"]] == ["added"]
+    assert [f["path"] for f in result["removed"]] == ["removed"]
+    changed = {f["path"]: f for f in result["changed"]}
+    assert set(changed) == {"kept", "moved"}
+    assert changed["kept"]["metadata"]["changed"]["version"] == {
+        "before": 1,
+        "after": 2,
+    }
+    assert "target" not in changed["kept"]
+    assert changed["moved"]["target"]["after"] == str(data_path / "test_fits.fits")
+
+
+def test_diff_identical_projects():
+    p = create_project("test_diff_same")
+    p.link(data_path / "test_ones.npy", "data")
+    result = diff("test_diff_same", other_project="test_diff_same")
+    assert result == {"added": [], "removed": [], "changed": []}
+
+
+def test_diff_exported_tree(tmp_path):
+    p = create_project("test_diff_tree")
+    p.link(data_path / "test_ones.npy", "data")
+    output = tmp_path / "export"
+    resp = server_request(
+        "GET", "export/default/test_diff_tree", params={"output_path": str(output)}
+    )
+    assert resp.ok, resp.text
+    p.link(data_path / "test_df.csv", "new")
+
+    result = diff("test_diff_tree", other_path=str(output))
+    assert [f["path"] for f in result["removed"]] == ["new"]
+    assert not result["added"] and not result["changed"]
+
+
+def test_diff_needs_one_source():
+    create_project("test_diff_invalid")
+    resp = server_request("GET", "diff/default/test_diff_invalid")
+    assert resp.status_code == 400
+    assert resp.json()["code"] == "invalid_diff_source"
@@ -0,0 +1,61 @@
+import os
+from pathlib import Path
+
+import pytest
+
+from godata import create_project
+from godata.client.client import (
+    acquire_lease,
+    link_file,
+    list_leases,
+    release_lease,
+    renew_lease,
+)
+
+data_path = Path(os.environ.get("DATA_PATH"))
+
+
+@pytest.fixture(scope="module")
+def project():
+    return create_project("test_leases")
+
+
+def test_lease_conflict(project):
+    lease = acquire_lease("default", "test_leases", "outputs", "worker-a", ttl=60)
+    leases = list_leases("default", "test_leases")
+    assert [entry["holder"] for entry in leases] == ["worker-a"]
+
+    # Anything below the leased path is covered, and so are the paths above it
+    with pytest.raises(FileExistsError) as e:
+        link_file("default", "test_leases", "outputs/ones", data_path / "test_ones.npy")
+    assert e.value.code == "path_leased"
+    with pytest.raises(FileExistsError) as e:
+        acquire_lease("default", "test_leases", "outputs/run", "worker-b")
+    assert e.value.code == "path_leased"
+
+    link_file(
+        "default",
+        "test_leases",
+        "outputs/ones",
+        data_path / "test_ones.npy",
+        lease=lease["id"],
+    )
+    assert project.has_path("outputs/ones")
+    # Paths outside the lease are not affected
+    project.link(data_path / "test_df.csv", "inputs/df")
+
+    release_lease("default", "test_leases", lease["id"])
+    assert list_leases("default", "test_leases") == []
+    link_file("default", "test_leases", "outputs/df", data_path / "test_df.csv")
+
+
+def test_release_unknown_lease(project):
+    lease = acquire_lease("default", "test_leases", "released", "worker-a")
+    release_lease("default", "test_leases", lease["id"])
+
+    with pytest.raises(FileNotFoundError) as e:
+        release_lease("default", "test_leases", lease["id"])
+    assert e.value.code == "lease_not_found"
+    with pytest.raises(FileNotFoundError) as e:
+        renew_lease("default", "test_leases", lease["id"])
+    assert e.value.code == "lease_not_found"
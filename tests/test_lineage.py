@@ -0,0 +1,85 @@
+from godata import create_project
+from godata.client.client import link_file, stale_files
+
+
+def link_pipeline(project: str, tmp_path):
+    """
+    raw -> clean -> summary, each made from the one before
+    """
+    paths = {}
+    for name in ["raw", "clean", "summary"]:
+        paths[name] = tmp_path / f"{name}.txt"
+        paths[name].write_text(name)
+    link_file("default", project, "raw", paths["raw"])
+    link_file(
+        "default",
+        project,
+        "clean",
+        paths["clean"],
+        provenance={"inputs": ["raw"], "tool": "clean.py"},
+    )
+    link_file(
+        "default",
+        project,
+        "summary",
+        paths["summary"],
+        provenance={"inputs": ["clean"]},
+    )
+    return paths
+
+
+def reasons(result: dict):
+    return {
+        f["project_path"]: {i["input"]: i["reason"] for i in f["inputs"]}
+        for f in result["stale"]
+    }
+
+
+def test_staleness_propagates(tmp_path):
+    p = create_project("test_lineage_changed")
+    paths = link_pipeline("test_lineage_changed", tmp_path)
+    result = stale_files("default", "test_lineage_changed")
+    assert result["checked"] == 2
+    assert result["stale"] == []
+
+    p.link(paths["raw"], "raw", metadata={"version": 2}, overwrite=True)
+    result = stale_files("default", "test_lineage_changed")
+    assert reasons(result) == {
+        "clean": {"raw": "changed"},
+        "summary": {"clean": "upstream"},
+    }
+
+    # Relinking the file that was out of date only leaves what was made from it
+    link_file(
+        "default",
+        "test_lineage_changed",
+        "clean",
+        paths["clean"],
+        force=True,
+        provenance={"inputs": ["raw"]},
+    )
+    result = stale_files("default", "test_lineage_changed")
+    assert reasons(result) == {"summary": {"clean": "changed"}}
+
+
+def test_missing_input(tmp_path):
+    p = create_project("test_lineage_missing")
+    link_pipeline("test_lineage_missing", tmp_path)
+    p.remove("raw")
+    result = stale_files("default", "test_lineage_missing", project_path="summary")
+    assert result["checked"] == 1
+    assert reasons(result) == {"summary": {"clean": "upstream"}}
+
+
+def test_modified_contents(tmp_path):
+    create_project("test_lineage_modified")
+    paths = link_pipeline("test_lineage_modified", tmp_path)
+    paths["raw"].write_text("edited in place")
+
+    # The revision of an input doesn't change when its contents do
+    assert stale_files("default", "test_lineage_modified")["stale"] == []
+    result = stale_files("default", "test_lineage_modified", checksums=True)
+    assert reasons(result) == {
+        "clean": {"raw": "modified"},
+        "summary": {"clean": "upstream"},
+    }
@@ -0,0 +1,72 @@
+import json
+from pathlib import Path
+
+from godata import create_project, load_project
+
+from .utils import server_request
+
+
+def export_manifest(project: str, output_path: Path, **params):
+    return server_request(
+        "GET",
+        f"export/default/{project}",
+        params={"output_path": str(output_path), "format": "manifest", **params},
+    )
+
+
+def import_manifest(project: str, input_path: Path, verify: bool):
+    body = {"input_path": str(input_path), "format": "manifest", "verify": verify}
+    return server_request("POST", f"import/default/{project}", json=body)
+
+
+def test_manifest_round_trip(tmp_path):
+    data = tmp_path / "data.txt"
+    data.write_text("original")
+    p = create_project("test_manifest_source")
+    p.link(data, "data", metadata={"run": 1})
+
+    manifest = tmp_path / "manifest.json"
+    resp = export_manifest("test_manifest_source", manifest)
+    assert resp.ok, resp.text
+    files = json.loads(manifest.read_text())["files"]
+    assert [f["path"] for f in files] == ["data"]
+    assert files[0]["checksum"].startswith("sha256:")
+
+    resp = import_manifest("test_manifest_copy", manifest, verify=True)
+    assert resp.ok, resp.text
+    copy = load_project("test_manifest_copy")
+    assert copy.get_metadata("data")["run"] == 1
+
+
+def test_manifest_verify_mismatch(tmp_path):
+    data = tmp_path / "data.txt"
+    data.write_text("original")
+    p = create_project("test_manifest_changed")
+    p.link(data, "data")
+    manifest = tmp_path / "manifest.json"
+    assert export_manifest("test_manifest_changed", manifest).ok
+
+    data.write_text("changed")
+    resp = import_manifest("test_manifest_mismatch", manifest, verify=True)
+    assert resp.status_code == 400
+    assert resp.json()["code"] == "checksum_mismatch"
+    # Nothing is imported when verification fails
+    resp = server_request("GET", "projects/default")
+    assert "test_manifest_mismatch" not in resp.json()
+
+    resp = import_manifest("test_manifest_mismatch", manifest, verify=False)
+    assert resp.ok, resp.text
+
+
+def test_manifest_flags_are_validated(tmp_path):
+    create_project("test_manifest_flags")
+    resp = export_manifest(
+        "test_manifest_flags", tmp_path / "manifest.json", checksums="no"
+    )
+    assert resp.status_code == 400
+    resp = export_manifest(
+        "test_manifest_flags", tmp_path / "manifest.json", checksums="false"
+    )
+    assert resp.ok, resp.text
+    manifest = json.loads((tmp_path / "manifest.json").read_text())
+    assert all(f.get("checksum") is None for f in manifest["files"])
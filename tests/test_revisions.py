@@ -0,0 +1,78 @@
+import os
+from pathlib import Path
+
+import pytest
+
+from godata import create_project
+from godata.client.client import apply_batch, get_etag, link_file, move, remove_file
+from godata.errors import RevisionMismatch
+
+data_path = Path(os.environ.get("DATA_PATH"))
+
+
+@pytest.fixture(scope="module")
+def project():
+    p = create_project("test_revisions")
+    p.link(data_path / "test_ones.npy", "data/ones")
+    p.link(data_path / "test_df.csv", "data/df")
+    return p
+
+
+def test_write_with_current_etag(project):
+    etag = get_etag("default", "test_revisions", "data/ones")
+    link_file(
+        "default",
+        "test_revisions",
+        "data/ones",
+        data_path / "test_ones.npy",
+        metadata={"checked": True},
+        force=True,
+        if_match=etag,
+    )
+    assert get_etag("default", "test_revisions", "data/ones") != etag
+
+
+def test_write_with_stale_etag(project):
+    etag = get_etag("default", "test_revisions", "data/df")
+    project.link(
+        data_path / "test_df.csv", "data/df", metadata={"v": 2}, overwrite=True
+    )
+
+    with pytest.raises(RevisionMismatch) as e:
+        remove_file("default", "test_revisions", "data/df", if_match=etag)
+    assert e.value.code == "revision_mismatch"
+    assert project.has_path("data/df")
+
+
+def test_move_checks_source(project):
+    project.link(data_path / "test_json.json", "moves/source")
+    project.link(data_path / "test_json.json", "moves/destination")
+    source = get_etag("default", "test_revisions", "moves/source")
+    destination = get_etag("default", "test_revisions", "moves/destination")
+
+    with pytest.raises(RevisionMismatch):
+        move(
+            "default",
+            "test_revisions",
+            "moves/source",
+            "moves/other",
+            if_match=destination,
+        )
+    move("default", "test_revisions", "moves/source", "moves/other", if_match=source)
+    assert project.has_path("moves/other")
+    assert not project.has_path("moves/source")
+
+
+def test_batch_revision(project):
+    revision = get_etag("default", "test_revisions", "data/ones").strip('"')
+    project.link(
+        data_path / "test_ones.npy", "data/ones", metadata={"v": 3}, overwrite=True
+    )
+    operation = {
+        "op": "remove",
+        "project_path": "data/ones",
+        "revision": int(revision),
+    }
+    with pytest.raises(RevisionMismatch):
+        apply_batch("default", "test_revisions", [operation], atomic=True)
+    assert project.has_path("data/ones")
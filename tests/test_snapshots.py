@@ -0,0 +1,77 @@
+import os
+from pathlib import Path
+
+import pytest
+
+from godata import create_project
+
+from .utils import server_request
+
+data_path = Path(os.environ.get("DATA_PATH"))
+
+SNAPSHOTS = "projects/default/test_snapshots/snapshots"
+
+
+@pytest.fixture(scope="module")
+def project():
+    p = create_project("test_snapshots")
+    p.link(data_path / "test_ones.npy", "data/ones", metadata={"version": 1})
+    p.link(data_path / "test_df.csv", "data/df")
+    resp = server_request("POST", SNAPSHOTS, json={"name": "first"})
+    assert resp.ok, resp.text
+    return p
+
+
+def test_list_snapshots(project):
+    resp = server_request("GET", SNAPSHOTS)
+    assert resp.ok, resp.text
+    assert "first" in [s["name"] for s in resp.json()]
+
+    resp = server_request("POST", SNAPSHOTS, json={"name": "first"})
+    assert resp.status_code == 409
+
+
+def test_snapshot_restore(project):
+    project.remove("data/df")
+    project.link(data_path / "test_json.json", "data/json")
+    project.link(
+        data_path / "test_ones.npy",
+        "data/ones",
+        metadata={"version": 2},
+        overwrite=True,
+    )
+
+    # The snapshot can be read without restoring it
+    resp = server_request(
+        "GET",
+        "projects/default/test_snapshots/list",
+        params={"project_path": "data", "snapshot": "first"},
+    )
+    assert resp.ok, resp.text
+    assert sorted(resp.json()["files"]) == ["df", "ones"]
+
+    resp = server_request("POST", f"{SNAPSHOTS}/restore", json={"name": "first"})
+    assert resp.ok, resp.text
+    assert project.has_path("data/df")
+    assert not project.has_path("data/json")
+    assert project.get_metadata("data/ones")["version"] == 1
+
+    resp = server_request(
+        "GET", "diff/default/test_snapshots", params={"other_snapshot": "first"}
+    )
+    assert resp.json() == {"added": [], "removed": [], "changed": []}
+
+
+def test_restore_missing_snapshot(project):
+    resp = server_request("POST", f"{SNAPSHOTS}/restore", json={"name": "missing"})
+    assert resp.status_code == 404
+    assert resp.json()["code"] == "snapshot_not_found"
+
+
+def test_delete_snapshot(project):
+    resp = server_request("POST", SNAPSHOTS, json={"name": "second"})
+    assert resp.ok, resp.text
+    resp = server_request("DELETE", SNAPSHOTS, params={"name": "second"})
+    assert resp.ok, resp.text
+    resp = server_request("GET", SNAPSHOTS)
+    assert "second" not in [s["name"] for s in resp.json()]
@@ -0,0 +1,19 @@
+"""
+Helpers for tests that call server endpoints the Python client doesn't wrap
+"""
+from godata.client.client import get_client
+
+
+def server_request(method: str, path: str, **kwargs):
+    client, url = get_client()
+    return client.request(method, f"{url}/{path.lstrip('/')}", **kwargs)
+
+
+def real_path(collection: str, project: str, project_path: str) -> str:
+    resp = server_request(
+        "GET",
+        f"projects/{collection}/{project}/files",
+        params={"project_path": project_path},
+    )
+    assert resp.ok, resp.text
+    return resp.json()["real_path"]